@@ -0,0 +1,360 @@
+//! Alert rules evaluated against the measurements and the webhook payloads they send
+
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    Co2Above { threshold: u16 },
+    Pm25Above { threshold: u16 },
+    SensorOffline,
+    ClockUnsynced,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    pub name: String,
+    pub condition: Condition,
+    /// The condition has to hold this long before the alert fires
+    #[serde(default)]
+    pub for_secs: u64,
+    /// A firing threshold alert resolves once the value drops below `threshold - hysteresis`
+    #[serde(default)]
+    pub hysteresis: u16,
+    /// Repeat the notification while firing, 0 = notify only once
+    #[serde(default)]
+    pub renotify_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    /// JSON body, see `render_template`, `DEFAULT_TEMPLATE` when not set
+    #[serde(default)]
+    pub template: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertConfig {
+    pub rules: Vec<Rule>,
+    pub webhooks: Vec<Webhook>,
+}
+
+/// Values the rules are evaluated against, `None` means the sensor read failed
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub co2: Option<u16>,
+    pub pm25: Option<u16>,
+    pub clock_synced: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Firing,
+    Resolved,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub rule: String,
+    pub status: Status,
+    pub value: Option<u16>,
+    pub threshold: Option<u16>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveAlert {
+    pub rule: String,
+    pub firing_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RuleState {
+    Inactive,
+    Pending { since: Instant },
+    Firing { since: Instant, notified: Instant },
+}
+
+pub const DEFAULT_TEMPLATE: &str = r#"{"alert":{{rule}},"status":{{status}},"value":{{value}},"threshold":{{threshold}},"device":{{device}},"timestamp":{{timestamp}}}"#;
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self {
+            rules: vec![
+                Rule {
+                    name: "co2_high".into(),
+                    condition: Condition::Co2Above { threshold: 1500 },
+                    for_secs: 10 * 60,
+                    hysteresis: 100,
+                    renotify_secs: 60 * 60,
+                },
+                Rule {
+                    name: "pm25_high".into(),
+                    condition: Condition::Pm25Above { threshold: 55 },
+                    for_secs: 0,
+                    hysteresis: 5,
+                    renotify_secs: 60 * 60,
+                },
+                Rule {
+                    name: "sensor_offline".into(),
+                    condition: Condition::SensorOffline,
+                    for_secs: 5 * 60,
+                    hysteresis: 0,
+                    renotify_secs: 0,
+                },
+                Rule {
+                    name: "clock_unsynced".into(),
+                    condition: Condition::ClockUnsynced,
+                    for_secs: 10 * 60,
+                    hysteresis: 0,
+                    renotify_secs: 0,
+                },
+            ],
+            webhooks: Vec::new(),
+        }
+    }
+}
+
+impl AlertConfig {
+    pub fn validate(&self) -> Result<()> {
+        for (i, rule) in self.rules.iter().enumerate() {
+            if rule.name.is_empty() {
+                bail!("Rule names can't be empty");
+            }
+            if self.rules[..i].iter().any(|other| other.name == rule.name) {
+                bail!("Duplicate rule name {:?}", rule.name);
+            }
+            match rule.condition.threshold() {
+                // it would never resolve
+                Some(threshold) if rule.hysteresis >= threshold => bail!(
+                    "Hysteresis of {:?} must be less than its threshold {}, got {}",
+                    rule.name,
+                    threshold,
+                    rule.hysteresis
+                ),
+                _ => {}
+            }
+        }
+        for webhook in &self.webhooks {
+            let host = webhook
+                .url
+                .strip_prefix("http://")
+                .or_else(|| webhook.url.strip_prefix("https://"));
+            if !host.is_some_and(|host| !host.is_empty() && !host.starts_with('/')) {
+                bail!(
+                    "Invalid webhook URL {:?}, expected http(s)://...",
+                    webhook.url
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Webhook {
+    /// JSON body of the request sent for the notification
+    pub fn payload(
+        &self,
+        notification: &Notification,
+        device: &str,
+        timestamp: Option<i64>,
+    ) -> String {
+        let template = self.template.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+        render_template(template, notification, device, timestamp)
+    }
+}
+
+impl Condition {
+    fn threshold(&self) -> Option<u16> {
+        match *self {
+            Self::Co2Above { threshold } | Self::Pm25Above { threshold } => Some(threshold),
+            Self::SensorOffline | Self::ClockUnsynced => None,
+        }
+    }
+
+    fn value(&self, sample: &Sample) -> Option<u16> {
+        match self {
+            Self::Co2Above { .. } => sample.co2,
+            Self::Pm25Above { .. } => sample.pm25,
+            Self::SensorOffline | Self::ClockUnsynced => None,
+        }
+    }
+
+    /// Returns `None` when the sample doesn't tell (missing value), the rule keeps its state then
+    fn holds(&self, sample: &Sample, hysteresis: u16, firing: bool) -> Option<bool> {
+        match self {
+            Self::Co2Above { threshold } | Self::Pm25Above { threshold } => {
+                let value = self.value(sample)?;
+                if firing {
+                    Some(value > threshold.saturating_sub(hysteresis))
+                } else {
+                    Some(value > *threshold)
+                }
+            }
+            Self::SensorOffline => Some(sample.co2.is_none() || sample.pm25.is_none()),
+            Self::ClockUnsynced => Some(!sample.clock_synced),
+        }
+    }
+}
+
+pub struct AlertEngine {
+    config: AlertConfig,
+    states: Vec<RuleState>,
+}
+
+impl AlertEngine {
+    pub fn new(config: AlertConfig) -> Self {
+        let states = vec![RuleState::Inactive; config.rules.len()];
+        Self { config, states }
+    }
+
+    pub fn config(&self) -> &AlertConfig {
+        &self.config
+    }
+
+    /// Replaces the rules and webhooks. Rules with the same name and condition keep their state,
+    /// the firing alerts of the other ones are resolved, returns their notifications to be sent
+    /// to the previous webhooks
+    pub fn set_config(&mut self, config: AlertConfig) -> Vec<Notification> {
+        let mut notifications = Vec::new();
+        let mut states = vec![RuleState::Inactive; config.rules.len()];
+        for (old, state) in self.config.rules.iter().zip(&self.states) {
+            let kept = config
+                .rules
+                .iter()
+                .position(|rule| rule.name == old.name && rule.condition == old.condition);
+            match kept {
+                Some(i) => states[i] = *state,
+                None if matches!(state, RuleState::Firing { .. }) => {
+                    notifications.push(Notification {
+                        rule: old.name.clone(),
+                        status: Status::Resolved,
+                        value: None,
+                        threshold: old.condition.threshold(),
+                    })
+                }
+                None => {}
+            }
+        }
+        self.config = config;
+        self.states = states;
+        notifications
+    }
+
+    /// Evaluates all the rules against the sample, returns the notifications to be sent
+    pub fn evaluate(&mut self, sample: &Sample, now: Instant) -> Vec<Notification> {
+        let mut notifications = Vec::new();
+
+        for (rule, state) in self.config.rules.iter().zip(self.states.iter_mut()) {
+            let firing = matches!(state, RuleState::Firing { .. });
+            let Some(holds) = rule.condition.holds(sample, rule.hysteresis, firing) else {
+                continue;
+            };
+
+            let notify = |status| Notification {
+                rule: rule.name.clone(),
+                status,
+                value: rule.condition.value(sample),
+                threshold: rule.condition.threshold(),
+            };
+
+            *state = match (*state, holds) {
+                (RuleState::Inactive, false) => RuleState::Inactive,
+                (RuleState::Pending { .. }, false) => RuleState::Inactive,
+                (RuleState::Firing { .. }, false) => {
+                    notifications.push(notify(Status::Resolved));
+                    RuleState::Inactive
+                }
+                (RuleState::Inactive, true) => RuleState::Pending { since: now },
+                (RuleState::Pending { since }, true) => RuleState::Pending { since },
+                (RuleState::Firing { since, notified }, true) => {
+                    let renotify = Duration::from_secs(rule.renotify_secs);
+                    if rule.renotify_secs > 0 && now.duration_since(notified) >= renotify {
+                        notifications.push(notify(Status::Firing));
+                        RuleState::Firing {
+                            since,
+                            notified: now,
+                        }
+                    } else {
+                        RuleState::Firing { since, notified }
+                    }
+                }
+            };
+
+            if let RuleState::Pending { since } = *state {
+                if now.duration_since(since) >= Duration::from_secs(rule.for_secs) {
+                    notifications.push(notify(Status::Firing));
+                    *state = RuleState::Firing {
+                        since,
+                        notified: now,
+                    };
+                }
+            }
+        }
+
+        notifications
+    }
+
+    pub fn active(&self, now: Instant) -> Vec<ActiveAlert> {
+        self.config
+            .rules
+            .iter()
+            .zip(self.states.iter())
+            .filter_map(|(rule, state)| match state {
+                RuleState::Firing { since, .. } => Some(ActiveAlert {
+                    rule: rule.name.clone(),
+                    firing_secs: now.duration_since(*since).as_secs(),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Replaces `{{rule}}`, `{{status}}`, `{{value}}`, `{{threshold}}`, `{{device}}` and `{{timestamp}}`
+/// with JSON values (strings are quoted, missing values are `null`)
+pub fn render_template(
+    template: &str,
+    notification: &Notification,
+    device: &str,
+    timestamp: Option<i64>,
+) -> String {
+    let json = |value: serde_json::Value| value.to_string();
+
+    template
+        .replace("{{rule}}", &json(notification.rule.as_str().into()))
+        .replace("{{status}}", &json(serde_json::json!(notification.status)))
+        .replace("{{value}}", &json(notification.value.into()))
+        .replace("{{threshold}}", &json(notification.threshold.into()))
+        .replace("{{device}}", &json(device.into()))
+        .replace("{{timestamp}}", &json(timestamp.into()))
+}
+
+/// Sends the notification to all the webhooks with `post(url, payload)`, a failing webhook
+/// doesn't stop the others
+pub fn send_webhooks(
+    webhooks: &[Webhook],
+    notification: &Notification,
+    device: &str,
+    timestamp: Option<i64>,
+    mut post: impl FnMut(&str, &str) -> Result<()>,
+) -> Result<()> {
+    let mut failed = 0;
+    for webhook in webhooks {
+        let payload = webhook.payload(notification, device, timestamp);
+        if let Err(e) = post(&webhook.url, &payload) {
+            error!("Webhook {} failed: {}", webhook.url, e);
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        bail!("{} of {} webhooks failed", failed, webhooks.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,174 @@
+//! LED animations of the indications and which of the active ones is shown
+
+use std::collections::BTreeMap;
+use std::f32::consts::PI;
+use std::time::Instant;
+
+use crate::color::{Color, LedPosition};
+use crate::palette::Palette;
+
+/// LED animation, the frames are a pure function of the time since the animation started
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    /// Quick flash fading out, repeats every `period_ms`
+    Pulse { color: Color, period_ms: u32 },
+    Blink {
+        color: Color,
+        on_ms: u32,
+        off_ms: u32,
+    },
+    /// A single lit LED moving bottom to top
+    Chase { color: Color, step_ms: u32 },
+    /// Slow sine fade in and out
+    Breathe { color: Color, period_ms: u32 },
+}
+
+/// Something the LEDs signal instead of the measured values, the highest one wins
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Indication {
+    /// Wi-Fi not connected yet
+    Provisioning,
+    SensorFault,
+    Alert,
+    /// Helps finding the physical device, see `POST /identify`
+    Identify,
+    OtaInProgress,
+}
+
+// LEDs from bottom to top
+const POSITIONS: [LedPosition; 3] = [LedPosition::Bottom, LedPosition::Center, LedPosition::Top];
+
+// Part of the pulse period spent fading in
+const PULSE_ATTACK: f32 = 0.1;
+
+impl Indication {
+    pub fn pattern(&self, palette: &Palette) -> Pattern {
+        match self {
+            Self::Provisioning => Pattern::Breathe {
+                color: palette.provisioning,
+                period_ms: 3_000,
+            },
+            Self::SensorFault => Pattern::Blink {
+                color: palette.sensor_fault,
+                on_ms: 200,
+                off_ms: 1_800,
+            },
+            Self::Alert => Pattern::Pulse {
+                color: palette.alert,
+                period_ms: 1_000,
+            },
+            Self::Identify => Pattern::Blink {
+                color: palette.identify,
+                on_ms: 100,
+                off_ms: 100,
+            },
+            Self::OtaInProgress => Pattern::Chase {
+                color: palette.ota,
+                step_ms: 150,
+            },
+        }
+    }
+}
+
+/// Active indications with the time they expire at, the highest one is shown
+#[derive(Debug)]
+pub struct Indications {
+    active: BTreeMap<Indication, Option<Instant>>,
+    /// The animation starts over when the shown indication changes
+    started: Instant,
+}
+
+impl Indications {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            active: BTreeMap::new(),
+            started: now,
+        }
+    }
+
+    pub fn shown(&self) -> Option<Indication> {
+        self.active.last_key_value().map(|(shown, _)| *shown)
+    }
+
+    pub fn is_active(&self, indication: Indication) -> bool {
+        self.active.contains_key(&indication)
+    }
+
+    /// Activates the indication until the given time or until it's removed, a repeated insert
+    /// only changes the expiration
+    pub fn insert(&mut self, indication: Indication, until: Option<Instant>, now: Instant) {
+        let shown = self.shown();
+        self.active.insert(indication, until);
+        self.restart_if_changed(shown, now);
+    }
+
+    pub fn remove(&mut self, indication: Indication, now: Instant) {
+        let shown = self.shown();
+        self.active.remove(&indication);
+        self.restart_if_changed(shown, now);
+    }
+
+    /// Removes the expired indications, returns whether there were any
+    pub fn expire(&mut self, now: Instant) -> bool {
+        let shown = self.shown();
+        let count = self.active.len();
+        self.active
+            .retain(|_, until| !until.is_some_and(|until| now >= until));
+        self.restart_if_changed(shown, now);
+        self.active.len() != count
+    }
+
+    /// Frame of the shown indication, `None` without any
+    pub fn frame(&self, palette: &Palette, now: Instant) -> Option<[Color; 3]> {
+        let elapsed_ms = now.saturating_duration_since(self.started).as_millis() as u32;
+        self.shown()
+            .map(|shown| shown.pattern(palette).frame(elapsed_ms))
+    }
+
+    fn restart_if_changed(&mut self, shown: Option<Indication>, now: Instant) {
+        if self.shown() != shown {
+            self.started = now;
+        }
+    }
+}
+
+impl Pattern {
+    /// Colors indexed by `LedPosition`, the brightness of each is relative to the LEDs brightness
+    pub fn frame(&self, elapsed_ms: u32) -> [Color; 3] {
+        match *self {
+            Self::Pulse { color, period_ms } => {
+                let phase = phase(elapsed_ms, period_ms);
+                let level = if phase < PULSE_ATTACK {
+                    phase / PULSE_ATTACK
+                } else {
+                    (1.0 - (phase - PULSE_ATTACK) / (1.0 - PULSE_ATTACK)).powi(2)
+                };
+                [color.with_level(level); 3]
+            }
+            Self::Blink {
+                color,
+                on_ms,
+                off_ms,
+            } => {
+                let on = elapsed_ms % (on_ms + off_ms).max(1) < on_ms;
+                [color.with_level(if on { 1.0 } else { 0.0 }); 3]
+            }
+            Self::Chase { color, step_ms } => {
+                let lit = POSITIONS[(elapsed_ms / step_ms.max(1)) as usize % POSITIONS.len()];
+                let mut frame = [color.with_level(0.0); 3];
+                frame[lit as usize] = color.with_level(1.0);
+                frame
+            }
+            Self::Breathe { color, period_ms } => {
+                let level = (1.0 - (2.0 * PI * phase(elapsed_ms, period_ms)).cos()) / 2.0;
+                [color.with_level(level); 3]
+            }
+        }
+    }
+}
+
+/// Position within the period, 0.0 - 1.0
+fn phase(elapsed_ms: u32, period_ms: u32) -> f32 {
+    let period_ms = period_ms.max(1);
+    (elapsed_ms % period_ms) as f32 / period_ms as f32
+}
@@ -0,0 +1,64 @@
+//! Quality bands of the measured values, 0 (best) - 4 (worst), that pick the LED colors
+
+/// Air quality band of the CO2 concentration, 0 (best) - 4 (worst)
+pub fn get_co2_band(co2: u16) -> usize {
+    // https://www.kane.co.uk/knowledge-centre/what-are-safe-levels-of-co-and-co2-in-rooms
+    match co2 {
+        0..=400 => 0,
+        401..=1000 => 1,
+        1001..=1500 => 2,
+        1501..=2000 => 3,
+        _ => 4,
+    }
+}
+
+/// Air quality band of the PM2.5 concentration, 0 (best) - 4 (worst)
+pub fn get_pm25_band(pm25: u16) -> usize {
+    // https://aqicn.org/faq/2013-09-09/revised-pm25-aqi-breakpoints/
+    // Good              0.0 - 12.0
+    // Moderate         12.1 - 35.4
+    // Unhealthy        35.5 - 55.4
+    // Very Unhealthy   55.5 - 150.4
+    // Hazardous       150.5
+    match pm25 {
+        0..=12 => 0,
+        13..=35 => 1,
+        36..=55 => 2,
+        56..=150 => 3,
+        _ => 4,
+    }
+}
+
+/// Thermal comfort band of the temperature in °C, 0 (comfortable) - 4 (far too cold or warm)
+pub fn get_temperature_band(temperature: f32) -> usize {
+    // https://www.who.int/publications/i/item/9789241550376 (18 °C minimum, ~20-24 °C comfortable)
+    let deviation = if temperature < 20.0 {
+        20.0 - temperature
+    } else {
+        (temperature - 24.0).max(0.0)
+    };
+    match deviation {
+        d if d <= 0.0 => 0,
+        d if d <= 2.0 => 1,
+        d if d <= 4.0 => 2,
+        d if d <= 6.0 => 3,
+        _ => 4,
+    }
+}
+
+/// Comfort band of the relative humidity in %, 0 (comfortable) - 4 (far too dry or humid)
+pub fn get_humidity_band(humidity: f32) -> usize {
+    // https://www.epa.gov/mold/mold-course-chapter-2 (30-50 % recommended, below 60 %)
+    let deviation = if humidity < 30.0 {
+        30.0 - humidity
+    } else {
+        (humidity - 50.0).max(0.0)
+    };
+    match deviation {
+        d if d <= 0.0 => 0,
+        d if d <= 10.0 => 1,
+        d if d <= 20.0 => 2,
+        d if d <= 30.0 => 3,
+        _ => 4,
+    }
+}
@@ -1,57 +1,100 @@
+use std::fmt;
+
 use esp_idf_svc::hal::{
     delay, gpio, gpio::PinDriver, gpio::Pins, i2c::I2cConfig, i2c::I2cDriver, i2c::I2C1, rmt::RMT,
     uart::UartConfig, uart::UartDriver, uart::UART1, units::Hertz, units::KiloHertz,
 };
+use esp_idf_svc::sys::EspError;
 use pm1006::pm1006::Pm1006;
+use smart_leds_trait::{SmartLedsWrite, RGB8};
+use ws2812_esp32_rmt_driver::{driver::color::LedPixelColorGrb24, LedPixelEsp32Rmt};
 
 use crate::fan::Fan;
-use crate::leds::Leds;
+use crate::leds::{LedWriter, Leds, INITIAL_BRIGHTNESS};
 use crate::scd41::Scd41;
 
+pub type LedDriver = LedPixelEsp32Rmt<'static, RGB8, LedPixelColorGrb24>;
+pub type LedError = <LedDriver as SmartLedsWrite>::Error;
+
+impl LedWriter for LedDriver {
+    type Error = LedError;
+
+    fn write(&mut self, colors: [RGB8; 3]) -> Result<(), Self::Error> {
+        SmartLedsWrite::write(self, colors)
+    }
+}
+
+/// A peripheral driver that couldn't be set up, the device runs without it. The sensors
+/// themselves are initialized later under supervision
+#[derive(Debug)]
+pub enum BoardError {
+    Fan(EspError),
+    I2c(EspError),
+    Uart(EspError),
+    Leds(LedError),
+}
+
+impl fmt::Display for BoardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fan(e) => write!(f, "Failed to set up the fan pin: {}", e),
+            Self::I2c(e) => write!(f, "Failed to set up the I2C bus: {}", e),
+            Self::Uart(e) => write!(f, "Failed to set up the UART: {}", e),
+            Self::Leds(e) => write!(f, "Failed to set up the LEDs: {:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for BoardError {}
+
+/// Peripherals that failed to set up are `None`, the LEDs keep their state without a driver
 pub struct Board {
-    pub scd41: Scd41<I2cDriver<'static>, delay::FreeRtos>,
-    pub pm1006: Pm1006<UartDriver<'static>>,
-    pub leds: Leds,
-    pub fan: Fan<'static, gpio::Gpio12>,
+    pub scd41: Option<Scd41<I2cDriver<'static>, delay::FreeRtos>>,
+    pub pm1006: Option<Pm1006<UartDriver<'static>>>,
+    pub leds: Leds<LedDriver>,
+    pub fan: Option<Fan<'static, gpio::Gpio12>>,
 }
 
 impl Board {
     pub fn new(pins: Pins, i2c1: I2C1, uart1: UART1, rmt: RMT) -> Self {
         // Fan
-        let fan_pin = PinDriver::output(pins.gpio12).unwrap();
-        let fan = Fan::new(fan_pin);
+        let fan = optional(PinDriver::output(pins.gpio12).map_err(BoardError::Fan)).map(Fan::new);
 
         // SCD41
         let config = I2cConfig::default().baudrate(KiloHertz(100).into());
-        let i2c = I2cDriver::new(i2c1, pins.gpio21, pins.gpio22, &config).unwrap();
-        let delay = delay::FreeRtos {};
-        let scd41 = Scd41::new(i2c, delay);
+        let scd41 = optional(
+            I2cDriver::new(i2c1, pins.gpio21, pins.gpio22, &config).map_err(BoardError::I2c),
+        )
+        .map(|i2c| Scd41::new(i2c, delay::FreeRtos {}));
 
         // PM1006
         let config = UartConfig::new().baudrate(Hertz(9_600));
-        let uart_driver = UartDriver::new(
-            uart1,
-            pins.gpio17,
-            pins.gpio16,
-            Option::<gpio::Gpio0>::None,
-            Option::<gpio::Gpio1>::None,
-            &config,
+        let pm1006 = optional(
+            UartDriver::new(
+                uart1,
+                pins.gpio17,
+                pins.gpio16,
+                Option::<gpio::Gpio0>::None,
+                Option::<gpio::Gpio1>::None,
+                &config,
+            )
+            .map_err(BoardError::Uart),
         )
-        .unwrap();
-
-        // Clear RX buffer to avoid reading old data
-        match uart_driver.clear_rx() {
-            Ok(_) => log::info!("Cleared RX buffer"),
-            Err(e) => log::warn!("Failed to clear RX buffer: {}", e),
-        }
-
-        let pm1006 = Pm1006::new(uart_driver);
+        .map(|uart_driver| {
+            // Clear RX buffer to avoid reading old data
+            match uart_driver.clear_rx() {
+                Ok(_) => log::info!("Cleared RX buffer"),
+                Err(e) => log::warn!("Failed to clear RX buffer: {}", e),
+            }
+            Pm1006::new(uart_driver)
+        });
 
         // LEDs
         let led_pin = pins.gpio25;
         let led_channel = rmt.channel0;
-        let mut leds = Leds::new(led_channel, led_pin);
-        leds.set_brightness(20);
+        let mut leds = optional(LedDriver::new(led_channel, led_pin).map_err(BoardError::Leds))
+            .map_or_else(Leds::disconnected, Leds::with_driver);
+        leds.set_brightness(INITIAL_BRIGHTNESS);
 
         Self {
             pm1006,
@@ -60,8 +103,10 @@ impl Board {
             fan,
         }
     }
+}
 
-    pub fn init(&mut self) {
-        self.scd41.init().unwrap();
-    }
+fn optional<T>(result: Result<T, BoardError>) -> Option<T> {
+    result
+        .map_err(|e| log::error!("{}, running without it", e))
+        .ok()
 }
@@ -0,0 +1,161 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::leds::INITIAL_BRIGHTNESS;
+use crate::schedule::Scheduled;
+
+// Identify should be easy to spot, even in daylight
+const IDENTIFY_BRIGHTNESS: u8 = 255;
+// Longer timed levels are rejected, they would also overflow the expiration
+const MAX_MANUAL_SECS: u64 = 24 * 60 * 60;
+
+/// Persisted user brightness levels, the schedule switches between `day` and `night`
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BrightnessPreferences {
+    pub day: u8,
+    pub night: u8,
+    /// Used while an alert is firing, also at night
+    pub alert: u8,
+}
+
+impl Default for BrightnessPreferences {
+    fn default() -> Self {
+        Self {
+            day: INITIAL_BRIGHTNESS,
+            night: 1,
+            alert: INITIAL_BRIGHTNESS,
+        }
+    }
+}
+
+/// What decided the effective brightness, the highest priority first
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Alert,
+    Identify,
+    Manual,
+    /// A schedule window or the solar dimming
+    Schedule,
+    /// The day preference outside of the schedule, or when the time is unknown
+    Day,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Effective {
+    pub brightness: u8,
+    pub source: Source,
+}
+
+/// `PUT /brightness` body, a plain level is kept until cleared
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(untagged)]
+pub enum ManualBrightness {
+    Level(u8),
+    Timed {
+        brightness: u8,
+        duration_secs: Option<u64>,
+    },
+}
+
+impl ManualBrightness {
+    pub fn validate(&self) -> Result<()> {
+        if let Self::Timed {
+            duration_secs: Some(duration_secs),
+            ..
+        } = *self
+        {
+            if duration_secs > MAX_MANUAL_SECS {
+                bail!(
+                    "duration_secs must be at most {}, got {}",
+                    MAX_MANUAL_SECS,
+                    duration_secs
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ManualOverride {
+    pub brightness: u8,
+    #[serde(skip)]
+    until: Option<Instant>,
+}
+
+impl ManualOverride {
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.until.map(|until| until.saturating_duration_since(now))
+    }
+}
+
+/// Picks the brightness from the user preferences, the manual override and the states that
+/// need the LEDs visible
+#[derive(Debug, Default, Serialize)]
+pub struct BrightnessArbiter {
+    manual: Option<ManualOverride>,
+    /// Result of the last `resolve()`
+    effective: Option<Effective>,
+}
+
+impl BrightnessArbiter {
+    pub fn set_manual(&mut self, manual: ManualBrightness, now: Instant) {
+        let (brightness, duration_secs) = match manual {
+            ManualBrightness::Level(brightness) => (brightness, None),
+            ManualBrightness::Timed {
+                brightness,
+                duration_secs,
+            } => (brightness, duration_secs),
+        };
+        self.manual = Some(ManualOverride {
+            brightness,
+            until: duration_secs.map(|secs| now + Duration::from_secs(secs)),
+        });
+    }
+
+    pub fn clear_manual(&mut self) {
+        self.manual = None;
+    }
+
+    /// The manual override, unless it has expired
+    pub fn manual(&self, now: Instant) -> Option<ManualOverride> {
+        self.manual
+            .filter(|manual| manual.remaining(now) != Some(Duration::ZERO))
+    }
+
+    pub fn effective(&self) -> Option<Effective> {
+        self.effective
+    }
+
+    /// `scheduled` is `None` outside of the schedule and when the time is unknown
+    pub fn resolve(
+        &mut self,
+        preferences: &BrightnessPreferences,
+        alert: bool,
+        identify: bool,
+        scheduled: Option<Scheduled>,
+        now: Instant,
+    ) -> Effective {
+        self.manual = self.manual(now);
+
+        let (brightness, source) = if alert {
+            (preferences.alert, Source::Alert)
+        } else if identify {
+            (IDENTIFY_BRIGHTNESS, Source::Identify)
+        } else if let Some(manual) = self.manual {
+            (manual.brightness, Source::Manual)
+        } else {
+            match scheduled {
+                Some(scheduled) => (scheduled.brightness, Source::Schedule),
+                None => (preferences.day, Source::Day),
+            }
+        };
+        let effective = Effective { brightness, source };
+        self.effective = Some(effective);
+        effective
+    }
+}
@@ -1,62 +1,266 @@
+use anyhow::{bail, Result};
+use esp_idf_svc::sntp::{EspSntp, SntpConf};
 use log::*;
-use sntp_request::SntpRequest;
-use std::time::Instant;
+use serde::Serialize;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use time::OffsetDateTime;
-use time_tz::{timezones, OffsetDateTimeExt, TimeZone, Tz};
+
+use crate::timezone::Timezone;
 
 pub struct Clock {
-    sntp: SntpRequest,
-    last_update: Option<Instant>,
-    timestamp: i64,
-    timezone: &'static Tz,
+    _sntp: EspSntp<'static>,
+    /// The one offered by DHCP first
+    servers: Vec<String>,
+    /// Set by the SNTP callback
+    last_sync: Arc<Mutex<Option<Instant>>>,
+    last_retry: Option<Instant>,
+    started: Instant,
+    last_probe: Option<(Instant, Result<Probe>)>,
+    timezone: Timezone,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncState {
+    /// The system time is still counting from 1970, there is no time
+    #[default]
+    NeverSynced,
+    Synced,
+    /// Not synced for `STALE_AFTER`, the time is usable but drifting
+    Stale,
+}
+
+/// Offset of the system time against an NTP server
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Probe {
+    /// Positive when the system time is behind
+    pub offset_ms: i64,
+    /// Round trip time
+    pub delay_ms: i64,
+    pub stratum: u8,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClockStatus {
+    pub state: SyncState,
+    pub last_sync_age_secs: Option<u64>,
+    pub servers: Vec<String>,
+    pub offset_ms: Option<i64>,
+    pub delay_ms: Option<i64>,
+    pub stratum: Option<u8>,
+    pub last_probe_age_secs: Option<u64>,
+    pub last_probe_error: Option<String>,
 }
 
-const TIMEZONE: &str = env!("TIMEZONE");
+// Comma separated, the one offered by DHCP replaces the first, see `use_dhcp_server()`
+const NTP_SERVERS: Option<&str> = option_env!("NTP_SERVERS");
+const DEFAULT_NTP_SERVERS: &str = "0.pool.ntp.org,1.pool.ntp.org,2.pool.ntp.org";
+
+const NTP_PORT: u16 = 123;
+// Seconds between 1900 (NTP era 0) and 1970 (Unix epoch)
+const NTP_UNIX_OFFSET: f64 = 2_208_988_800.0;
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+const PROBE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// SNTP syncs every hour (CONFIG_LWIP_SNTP_UPDATE_DELAY), a few missed ones make the time stale
+const STALE_AFTER: Duration = Duration::from_secs(3 * 60 * 60);
+// How often the sync is restarted until it succeeds, lwIP alone backs off to minutes
+const NEVER_SYNCED_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+const STALE_RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+// Time the first sync gets before the clock counts as failing
+const FIRST_SYNC_GRACE: Duration = Duration::from_secs(5 * 60);
+
+/// Lets DHCP replace the first NTP server, to be called before the station gets its address as
+/// the server is only taken from the DHCP reply, needs CONFIG_LWIP_DHCP_GET_NTP_SRV
+pub fn use_dhcp_server() {
+    unsafe { esp_idf_svc::sys::esp_sntp_servermode_dhcp(true) };
+}
+
+/// Server of the first slot when it was set by DHCP, configured servers are set by name
+fn dhcp_server() -> Option<Ipv4Addr> {
+    let server = unsafe { esp_idf_svc::sys::esp_sntp_getserver(0).as_ref()? };
+    if u32::from(server.type_) != esp_idf_svc::sys::lwip_ip_addr_type_IPADDR_TYPE_V4 {
+        return None;
+    }
+    // network byte order
+    let address = Ipv4Addr::from(unsafe { server.u_addr.ip4.addr }.to_ne_bytes());
+    (!address.is_unspecified()).then_some(address)
+}
 
 impl Clock {
-    pub fn new() -> Self {
-        let sntp = SntpRequest::new();
-        let timezone = timezones::get_by_name(TIMEZONE).unwrap_or(timezones::db::GMT);
-        info!("Timezone: {:?}", timezone.name());
-        Self {
+    pub fn new(timezone: Timezone) -> Result<Self> {
+        info!("Timezone: {}", timezone);
+
+        let configured: Vec<&'static str> = NTP_SERVERS
+            .unwrap_or(DEFAULT_NTP_SERVERS)
+            .split(',')
+            .map(str::trim)
+            .filter(|server| !server.is_empty())
+            .collect();
+        if configured.is_empty() {
+            bail!("No NTP servers configured");
+        }
+        let mut conf = SntpConf::default();
+        if configured.len() > conf.servers.len() {
+            warn!(
+                "Only {} NTP servers are supported, ignoring {:?}",
+                conf.servers.len(),
+                &configured[conf.servers.len()..]
+            );
+        }
+        // unused slots repeat the configured servers
+        for (slot, server) in conf.servers.iter_mut().zip(configured.iter().cycle()) {
+            *slot = *server;
+        }
+
+        // DHCP is already done, starting SNTP overwrites the server it set with a configured one
+        let dhcp_server = dhcp_server();
+        let last_sync = Arc::new(Mutex::new(None));
+        let sntp = EspSntp::new_with_callback(&conf, {
+            let last_sync = last_sync.clone();
+            move |synced: Duration| {
+                info!("Clock synced, timestamp: {}", synced.as_secs());
+                *last_sync.lock().unwrap() = Some(Instant::now());
+            }
+        })?;
+        let mut slots: Vec<String> = conf.servers.iter().map(|&server| server.into()).collect();
+        if let Some(server) = dhcp_server {
+            let address: esp_idf_svc::sys::ip_addr_t = unsafe {
+                let mut ip_addr: esp_idf_svc::sys::ip_addr_t = std::mem::zeroed();
+                ip_addr.u_addr.ip4.addr = u32::from_ne_bytes(server.octets());
+                ip_addr.type_ = esp_idf_svc::sys::lwip_ip_addr_type_IPADDR_TYPE_V4 as _;
+                ip_addr
+            };
+            // clears the configured name of the slot, DHCP renewals set it the same way
+            unsafe { esp_idf_svc::sys::esp_sntp_setserver(0, &address) };
+            slots[0] = server.to_string();
+        }
+        let mut servers: Vec<String> = Vec::new();
+        for server in slots {
+            if !servers.contains(&server) {
+                servers.push(server);
+            }
+        }
+        info!("NTP servers: {:?}", servers);
+
+        Ok(Self {
+            _sntp: sntp,
+            servers,
+            last_sync,
+            last_retry: None,
+            started: Instant::now(),
+            last_probe: None,
             timezone,
-            timestamp: 0,
-            sntp,
-            last_update: None,
+        })
+    }
+
+    pub fn timezone(&self) -> &Timezone {
+        &self.timezone
+    }
+
+    pub fn set_timezone(&mut self, timezone: Timezone) {
+        info!("Timezone set to {}", timezone);
+        self.timezone = timezone;
+    }
+
+    pub fn state(&self) -> SyncState {
+        match *self.last_sync.lock().unwrap() {
+            None => SyncState::NeverSynced,
+            Some(last_sync) if last_sync.elapsed() >= STALE_AFTER => SyncState::Stale,
+            Some(_) => SyncState::Synced,
         }
     }
 
-    pub fn sync(&mut self) {
-        // sync with remote server
-        let result = self.sntp.get_unix_time(); // in seconds
-        self.last_update = Some(Instant::now());
+    /// Never synced but still within `FIRST_SYNC_GRACE` of the start, not a failure yet
+    pub fn is_starting(&self) -> bool {
+        self.state() == SyncState::NeverSynced && self.started.elapsed() < FIRST_SYNC_GRACE
+    }
 
-        // update the local timestamp
-        match result {
-            Ok(timestamp) => {
-                info!("Sync successful, timestamp: {}", timestamp);
-                self.timestamp = timestamp;
-            }
-            Err(e) => {
-                error!("Sync failed: {:?}", e);
-            }
+    /// Restarts the SNTP sync while the time is missing or stale, to be called every few seconds
+    pub fn retry_sync(&mut self) {
+        let interval = match self.state() {
+            SyncState::Synced => return,
+            SyncState::NeverSynced => NEVER_SYNCED_RETRY_INTERVAL,
+            SyncState::Stale => STALE_RETRY_INTERVAL,
+        };
+        if self
+            .last_retry
+            .is_some_and(|last_retry| last_retry.elapsed() < interval)
+        {
+            return;
+        }
+        self.last_retry = Some(Instant::now());
+
+        warn!("Clock {:?}, restarting the sync", self.state());
+        if !unsafe { esp_idf_svc::sys::esp_sntp_restart() } {
+            error!("Failed to restart the SNTP sync");
         }
     }
 
-    pub fn get_unix_timestamp(&self) -> Option<i64> {
-        self.last_update.map(|last_update| {
-            let now = Instant::now();
-            let duration = now.duration_since(last_update);
+    /// Server to measure the offset against with `probe()`, when a measurement is due
+    pub fn probe_due(&self) -> Option<String> {
+        let due = self.state() != SyncState::NeverSynced
+            && self
+                .last_probe
+                .as_ref()
+                .map_or(true, |(probed_at, _)| probed_at.elapsed() >= PROBE_INTERVAL);
+        due.then(|| self.servers[0].clone())
+    }
 
-            self.timestamp + duration.as_secs() as i64
-        })
+    pub fn set_probe(&mut self, probe: Result<Probe>) {
+        match &probe {
+            Ok(probe) => info!("Clock probe: {:?}", probe),
+            Err(e) => error!("Clock probe failed: {}", e),
+        }
+        self.last_probe = Some((Instant::now(), probe));
+    }
+
+    pub fn status(&self) -> ClockStatus {
+        let probe = self
+            .last_probe
+            .as_ref()
+            .and_then(|(_, probe)| probe.as_ref().ok());
+        ClockStatus {
+            state: self.state(),
+            last_sync_age_secs: self
+                .last_sync
+                .lock()
+                .unwrap()
+                .map(|last_sync| last_sync.elapsed().as_secs()),
+            servers: self.servers.clone(),
+            offset_ms: probe.map(|probe| probe.offset_ms),
+            delay_ms: probe.map(|probe| probe.delay_ms),
+            stratum: probe.map(|probe| probe.stratum),
+            last_probe_age_secs: self
+                .last_probe
+                .as_ref()
+                .map(|(probed_at, _)| probed_at.elapsed().as_secs()),
+            last_probe_error: self
+                .last_probe
+                .as_ref()
+                .and_then(|(_, probe)| probe.as_ref().err())
+                .map(|e| e.to_string()),
+        }
+    }
+
+    /// System time, `None` until the first sync, a stale time is still returned
+    pub fn get_unix_timestamp(&self) -> Option<i64> {
+        if self.state() == SyncState::NeverSynced {
+            return None;
+        }
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|since_epoch| since_epoch.as_secs() as i64)
     }
 
     pub fn get_datetime(&self) -> Option<OffsetDateTime> {
         if let Some(timestamp) = self.get_unix_timestamp() {
             let datetime = OffsetDateTime::from_unix_timestamp(timestamp);
             match datetime {
-                Ok(datetime) => Some(datetime.to_timezone(self.timezone)),
+                Ok(datetime) => Some(self.timezone.convert(datetime)),
                 Err(e) => {
                     error!(
                         "Failed to convert timestamp {} to datetime: {}",
@@ -70,3 +274,55 @@ impl Clock {
         }
     }
 }
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+fn ntp_timestamp(bytes: &[u8]) -> f64 {
+    let seconds = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64;
+    let fraction = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as f64;
+    seconds + fraction / 4_294_967_296.0 - NTP_UNIX_OFFSET
+}
+
+/// Measures the system time offset and the server stratum with a single SNTP request,
+/// blocks up to `PROBE_TIMEOUT`
+pub fn probe(server: &str) -> Result<Probe> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(PROBE_TIMEOUT))?;
+    socket.connect((server, NTP_PORT))?;
+
+    let mut request = [0u8; 48];
+    // leap indicator 0, version 4, mode 3 (client)
+    request[0] = 0b00_100_011;
+    let sent_at = unix_now();
+    socket.send(&request)?;
+
+    let mut response = [0u8; 48];
+    let length = socket.recv(&mut response)?;
+    let received_at = unix_now();
+    if length < response.len() {
+        bail!("Short NTP response from {}: {} bytes", server, length);
+    }
+    let mode = response[0] & 0b111;
+    let stratum = response[1];
+    if mode != 4 {
+        bail!("Unexpected NTP mode {} from {}", mode, server);
+    }
+    if stratum == 0 {
+        bail!("Kiss-o'-death from {}", server);
+    }
+
+    let received = ntp_timestamp(&response[32..40]);
+    let transmitted = ntp_timestamp(&response[40..48]);
+    let offset = ((received - sent_at) + (transmitted - received_at)) / 2.0;
+    let delay = (received_at - sent_at) - (transmitted - received);
+    Ok(Probe {
+        offset_ms: (offset * 1000.0).round() as i64,
+        delay_ms: (delay * 1000.0).round() as i64,
+        stratum,
+    })
+}
@@ -0,0 +1,82 @@
+//! Colors of the three LEDs, kept apart from the LED driver
+
+use std::str::FromStr;
+
+use anyhow::{bail, Error};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub brightness: Option<u8>,
+}
+
+impl Color {
+    pub fn new(r: u8, g: u8, b: u8) -> Self {
+        Self {
+            r,
+            g,
+            b,
+            brightness: None,
+        }
+    }
+
+    pub fn brightness(&self, brightness: u8) -> Self {
+        Self {
+            r: self.r,
+            g: self.g,
+            b: self.b,
+            brightness: Some(brightness),
+        }
+    }
+
+    /// Color with brightness relative to the full one, `level` is 0.0 - 1.0
+    pub fn with_level(&self, level: f32) -> Self {
+        self.brightness((level.clamp(0.0, 1.0) * 255.0).round() as u8)
+    }
+
+    pub fn mix(&self, other: &Self) -> Self {
+        Self {
+            r: ((self.r as u16 + other.r as u16) / 2) as u8,
+            g: ((self.g as u16 + other.g as u16) / 2) as u8,
+            b: ((self.b as u16 + other.b as u16) / 2) as u8,
+            brightness: None,
+        }
+    }
+}
+
+/// Parses `#rrggbb`
+impl FromStr for Color {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim().trim_start_matches('#');
+        if hex.len() != 6 || !hex.is_ascii() {
+            bail!("Expected #rrggbb color, got {:?}", s);
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+        Ok(Self::new(channel(0)?, channel(2)?, channel(4)?))
+    }
+}
+
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b))
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum LedPosition {
+    Bottom = 0,
+    Center = 1,
+    Top = 2,
+}
@@ -0,0 +1,292 @@
+//! Why the previous run ended: the panic hook keeps the message, location and backtrace in RTC
+//! memory, which survives the restart, and the next boot combines it with the reset reason, so
+//! watchdog resets and brownouts without a panic are reported too. A loop stalled past its
+//! liveness budget is kept there the same way, the timer catching it can't wait for the storage.
+
+use std::ffi::CStr;
+use std::fmt::{self, Write};
+use std::ptr::{self, addr_of_mut};
+
+use esp_idf_svc::sys;
+use serde::{Deserialize, Serialize};
+
+use crate::diagnostics;
+use crate::liveness::Stall;
+use crate::supervisor::RestartReason;
+use crate::update::CURRENT_VERSION;
+use crate::utils::unix_time;
+
+// Marks a panic written by the previous run, the RTC memory is random after a power on
+const RTC_MAGIC: u32 = 0x564E_4450;
+const STALL_MAGIC: u32 = 0x5354_4C4C;
+const MESSAGE_MAX_LEN: usize = 512;
+const LOCATION_MAX_LEN: usize = 128;
+const NAME_MAX_LEN: usize = 32;
+const MAX_FRAMES: usize = 32;
+// Reset reasons of `diagnostics::reset_reason()` that end a run unexpectedly
+const CRASH_RESETS: &[&str] = &[
+    "panic",
+    "interrupt_watchdog",
+    "task_watchdog",
+    "watchdog",
+    "brownout",
+];
+
+/// Text of a fixed capacity, longer text is cut at a character boundary
+#[repr(C)]
+struct RtcText<const N: usize> {
+    len: u32,
+    bytes: [u8; N],
+}
+
+impl<const N: usize> RtcText<N> {
+    const EMPTY: Self = Self {
+        len: 0,
+        bytes: [0; N],
+    };
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// `None` when empty
+    fn read(&self) -> Option<String> {
+        let len = (self.len as usize).min(N);
+        (len > 0).then(|| String::from_utf8_lossy(&self.bytes[..len]).into_owned())
+    }
+}
+
+impl<const N: usize> Write for RtcText<N> {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        let start = (self.len as usize).min(N);
+        let mut len = text.len().min(N - start);
+        while !text.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.bytes[start..start + len].copy_from_slice(&text.as_bytes()[..len]);
+        self.len = (start + len) as u32;
+        Ok(())
+    }
+}
+
+/// Written by the panic hook, so plain fields filled in place without allocating
+#[repr(C)]
+struct RtcRecord {
+    magic: u32,
+    message: RtcText<MESSAGE_MAX_LEN>,
+    location: RtcText<LOCATION_MAX_LEN>,
+    thread: RtcText<NAME_MAX_LEN>,
+    firmware_version: RtcText<NAME_MAX_LEN>,
+    /// `pc`, `sp`
+    frames: [(u32, u32); MAX_FRAMES],
+    frame_count: u32,
+    /// Unix time, 0 when the clock wasn't synced
+    timestamp: i64,
+    uptime_secs: u64,
+}
+
+/// Not initialized by the bootloader, keeps its content across software and panic resets
+#[link_section = ".rtc_noinit"]
+static mut RTC_RECORD: RtcRecord = RtcRecord {
+    magic: 0,
+    message: RtcText::EMPTY,
+    location: RtcText::EMPTY,
+    thread: RtcText::EMPTY,
+    firmware_version: RtcText::EMPTY,
+    frames: [(0, 0); MAX_FRAMES],
+    frame_count: 0,
+    timestamp: 0,
+    uptime_secs: 0,
+};
+
+#[repr(C)]
+struct RtcStall {
+    magic: u32,
+    task: RtcText<NAME_MAX_LEN>,
+    elapsed_secs: u64,
+    budget_secs: u64,
+}
+
+#[link_section = ".rtc_noinit"]
+static mut RTC_STALL: RtcStall = RtcStall {
+    magic: 0,
+    task: RtcText::EMPTY,
+    elapsed_secs: 0,
+    budget_secs: 0,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Panic {
+    pub message: String,
+    /// `file:line:column`
+    pub location: Option<String>,
+    pub thread: Option<String>,
+    /// `pc:sp` pairs, decode with `xtensa-esp32-elf-addr2line -pfiaC -e <elf> <pc>...`
+    pub backtrace: Option<String>,
+    /// Unix time, `None` when the clock wasn't synced
+    pub timestamp: Option<i64>,
+    pub uptime_secs: u64,
+    /// Of the firmware that panicked, a new one may have been rolled back since
+    pub firmware_version: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrashReport {
+    /// `panic`, `task_watchdog`, `brownout`..., see `diagnostics::reset_reason()`
+    pub reset_reason: String,
+    /// `None` when the device went down without a Rust panic, e.g. a watchdog reset
+    pub panic: Option<Panic>,
+    /// Size of the core dump in the `coredump` partition, read it with `make coredump`
+    pub core_dump_size: Option<usize>,
+    /// Included in a log upload already
+    #[serde(default)]
+    pub reported: bool,
+}
+
+/// Keeps panics for the next boot, the default hook still prints them. The heap may be what
+/// failed, so the panic is written to RTC memory as is and only turned into text at the boot.
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let record = unsafe { &mut *addr_of_mut!(RTC_RECORD) };
+        // first, an interrupted write isn't picked up
+        record.magic = 0;
+
+        record.message.clear();
+        let payload = info.payload();
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            message
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.as_str()
+        } else {
+            "Box<dyn Any>"
+        };
+        let _ = record.message.write_str(message);
+
+        record.location.clear();
+        if let Some(location) = info.location() {
+            let _ = write!(
+                record.location,
+                "{}:{}:{}",
+                location.file(),
+                location.line(),
+                location.column()
+            );
+        }
+
+        record.thread.clear();
+        if let Some(name) = diagnostics::current_task().or_else(task_name) {
+            let _ = record.thread.write_str(name);
+        }
+        record.firmware_version.clear();
+        let _ = record.firmware_version.write_str(CURRENT_VERSION);
+        record.frame_count = backtrace(&mut record.frames) as u32;
+        record.timestamp = unix_time().map_or(0, |since_epoch| since_epoch.as_secs() as i64);
+        record.uptime_secs = diagnostics::uptime_secs();
+        record.magic = RTC_MAGIC;
+
+        default_hook(info);
+    }));
+}
+
+/// FreeRTOS name of the calling task, for the ESP-IDF tasks and unregistered threads
+fn task_name() -> Option<&'static str> {
+    let name = unsafe { sys::pcTaskGetName(ptr::null_mut()) };
+    (!name.is_null())
+        .then(|| unsafe { CStr::from_ptr(name) }.to_str().ok())
+        .flatten()
+}
+
+/// Walks the stack of the calling task like the ESP-IDF panic handler, returns the frame count
+fn backtrace(frames: &mut [(u32, u32); MAX_FRAMES]) -> usize {
+    let mut frame = sys::esp_backtrace_frame_t::default();
+    unsafe { sys::esp_backtrace_get_start(&mut frame.pc, &mut frame.sp, &mut frame.next_pc) };
+    for (count, slot) in frames.iter_mut().enumerate() {
+        *slot = (stack_pc(frame.pc), frame.sp);
+        if frame.next_pc == 0 || !unsafe { sys::esp_backtrace_get_next_frame(&mut frame) } {
+            return count + 1;
+        }
+    }
+    MAX_FRAMES
+}
+
+/// Return address of the windowed ABI to the address of the call, see `esp_cpu_process_stack_pc`
+fn stack_pc(pc: u32) -> u32 {
+    let pc = if pc & 0x8000_0000 != 0 {
+        (pc & 0x3FFF_FFFF) | 0x4000_0000
+    } else {
+        pc
+    };
+    pc.saturating_sub(3)
+}
+
+/// The panic written by the previous run, cleared so it's only reported once
+fn take_rtc() -> Option<Panic> {
+    let record = unsafe { &mut *addr_of_mut!(RTC_RECORD) };
+    if record.magic != RTC_MAGIC {
+        return None;
+    }
+    record.magic = 0;
+    let frames = &record.frames[..(record.frame_count as usize).min(MAX_FRAMES)];
+    let backtrace = frames
+        .iter()
+        .map(|(pc, sp)| format!("{:#010x}:{:#010x}", pc, sp))
+        .collect::<Vec<_>>()
+        .join(" ");
+    Some(Panic {
+        message: record.message.read().unwrap_or_default(),
+        location: record.location.read(),
+        thread: record.thread.read(),
+        backtrace: (!backtrace.is_empty()).then_some(backtrace),
+        timestamp: (record.timestamp != 0).then_some(record.timestamp),
+        uptime_secs: record.uptime_secs,
+        firmware_version: record.firmware_version.read().unwrap_or_default(),
+    })
+}
+
+/// Keeps the stalled loop for the next boot, without locking or allocating
+pub fn record_stall(stall: &Stall) {
+    let record = unsafe { &mut *addr_of_mut!(RTC_STALL) };
+    record.magic = 0;
+    record.task.clear();
+    let _ = record.task.write_str(stall.name);
+    record.elapsed_secs = stall.elapsed.as_secs();
+    record.budget_secs = stall.budget.as_secs();
+    record.magic = STALL_MAGIC;
+}
+
+/// The stall that restarted the previous run, cleared so it's only reported once
+pub fn take_stall() -> Option<RestartReason> {
+    let record = unsafe { &mut *addr_of_mut!(RTC_STALL) };
+    if record.magic != STALL_MAGIC {
+        return None;
+    }
+    record.magic = 0;
+    Some(RestartReason::Stalled {
+        task: record.task.read().unwrap_or_default(),
+        elapsed_secs: record.elapsed_secs,
+        budget_secs: record.budget_secs,
+    })
+}
+
+fn core_dump_size() -> Option<usize> {
+    let mut address = 0;
+    let mut size = 0;
+    (unsafe { sys::esp_core_dump_image_get(&mut address, &mut size) } == sys::ESP_OK)
+        .then_some(size)
+}
+
+/// How the previous run ended, `None` after a power on, a deliberate restart or a clean reset
+pub fn collect() -> Option<CrashReport> {
+    let panic = take_rtc();
+    let reset_reason = diagnostics::reset_reason();
+    if panic.is_none() && !CRASH_RESETS.contains(&reset_reason) {
+        return None;
+    }
+    Some(CrashReport {
+        reset_reason: reset_reason.into(),
+        panic,
+        core_dump_size: core_dump_size(),
+        reported: false,
+    })
+}
@@ -0,0 +1,276 @@
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::sys;
+use serde::Serialize;
+
+use crate::crash::CrashReport;
+use crate::supervisor::{self, RestartReason};
+
+// Consecutive failures after which a subsystem counts as degraded, a single one may be a glitch
+const DEGRADED_AFTER_ERRORS: u32 = 3;
+// A degraded subsystem recovers after this long without a failure, webhooks only fail when used
+const ERRORS_EXPIRE_AFTER: Duration = Duration::from_secs(30 * 60);
+// ESP-IDF tasks worth watching, next to the registered threads
+const SYSTEM_TASKS: &[&str] = &["main", "httpd", "esp_timer", "sys_evt", "tiT", "wifi"];
+
+/// Threads of the firmware, their FreeRTOS handles can't be looked up reliably by name
+static TASKS: Mutex<Vec<(&'static str, usize)>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Subsystem {
+    Wifi,
+    Clock,
+    Co2Sensor,
+    Pm25Sensor,
+    Logger,
+    Webhooks,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+    Ok,
+    Degraded,
+    /// Nothing recorded yet
+    Unknown,
+    /// The peripheral couldn't be set up at boot, the device runs without it
+    Absent,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    successes: u32,
+    errors: u32,
+    consecutive_errors: u32,
+    last_success: Option<Instant>,
+    last_error: Option<(Instant, String)>,
+    /// Of the supervised subsystems
+    state: Option<supervisor::State>,
+    absent: bool,
+}
+
+impl Counters {
+    fn health(&self) -> Health {
+        if self.absent {
+            return Health::Absent;
+        }
+        let failing = self.consecutive_errors >= DEGRADED_AFTER_ERRORS
+            && self
+                .last_error
+                .as_ref()
+                .is_some_and(|(at, _)| at.elapsed() < ERRORS_EXPIRE_AFTER);
+        if self.state == Some(supervisor::State::Degraded) || failing {
+            Health::Degraded
+        } else if self.successes == 0 && self.errors == 0 {
+            Health::Unknown
+        } else {
+            Health::Ok
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubsystemReport {
+    pub health: Health,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<supervisor::State>,
+    pub successes: u32,
+    pub errors: u32,
+    pub consecutive_errors: u32,
+    pub last_success_age_secs: Option<u64>,
+    pub last_error: Option<String>,
+    pub last_error_age_secs: Option<u64>,
+}
+
+/// Success and error counters per subsystem, the health of Wi-Fi and the clock comes from their
+/// live state
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    counters: BTreeMap<Subsystem, Counters>,
+    /// Recorded by the firmware before it restarted the device on purpose
+    last_restart: Option<RestartReason>,
+    /// Of this or an earlier run, kept until the next crash
+    last_crash: Option<CrashReport>,
+}
+
+impl Diagnostics {
+    pub fn new(last_restart: Option<RestartReason>, last_crash: Option<CrashReport>) -> Self {
+        Self {
+            last_restart,
+            last_crash,
+            ..Default::default()
+        }
+    }
+
+    pub fn last_restart(&self) -> Option<&RestartReason> {
+        self.last_restart.as_ref()
+    }
+
+    pub fn last_crash(&self) -> Option<&CrashReport> {
+        self.last_crash.as_ref()
+    }
+
+    /// The last crash if it hasn't been uploaded with the logs yet
+    pub fn unreported_crash(&self) -> Option<&CrashReport> {
+        self.last_crash.as_ref().filter(|crash| !crash.reported)
+    }
+
+    /// Marks the last crash as uploaded, returns it to be persisted
+    pub fn set_crash_reported(&mut self) -> Option<&CrashReport> {
+        let crash = self.last_crash.as_mut()?;
+        crash.reported = true;
+        Some(crash)
+    }
+
+    pub fn record_ok(&mut self, subsystem: Subsystem) {
+        let counters = self.counters.entry(subsystem).or_default();
+        counters.successes += 1;
+        counters.consecutive_errors = 0;
+        counters.last_success = Some(Instant::now());
+    }
+
+    pub fn record_error(&mut self, subsystem: Subsystem, error: impl Display) {
+        let counters = self.counters.entry(subsystem).or_default();
+        counters.errors += 1;
+        counters.consecutive_errors += 1;
+        counters.last_error = Some((Instant::now(), error.to_string()));
+    }
+
+    /// The peripheral of the subsystem is missing, it is neither used nor counted as failing
+    pub fn record_absent(&mut self, subsystem: Subsystem) {
+        self.counters.entry(subsystem).or_default().absent = true;
+    }
+
+    pub fn record_state(&mut self, subsystem: Subsystem, state: supervisor::State) {
+        self.counters.entry(subsystem).or_default().state = Some(state);
+    }
+
+    pub fn record<T, E: Display>(&mut self, subsystem: Subsystem, result: &Result<T, E>) {
+        match result {
+            Ok(_) => self.record_ok(subsystem),
+            Err(e) => self.record_error(subsystem, e),
+        }
+    }
+
+    /// All subsystems, `live` overrides the health of the ones with a known state
+    pub fn report(&self, live: &[(Subsystem, Health)]) -> BTreeMap<Subsystem, SubsystemReport> {
+        let empty = Counters::default();
+        [
+            Subsystem::Wifi,
+            Subsystem::Clock,
+            Subsystem::Co2Sensor,
+            Subsystem::Pm25Sensor,
+            Subsystem::Logger,
+            Subsystem::Webhooks,
+        ]
+        .into_iter()
+        .map(|subsystem| {
+            let counters = self.counters.get(&subsystem).unwrap_or(&empty);
+            let health = live
+                .iter()
+                .find(|(live, _)| *live == subsystem)
+                .map_or_else(|| counters.health(), |(_, health)| *health);
+            let report = SubsystemReport {
+                health,
+                state: counters.state,
+                successes: counters.successes,
+                errors: counters.errors,
+                consecutive_errors: counters.consecutive_errors,
+                last_success_age_secs: counters
+                    .last_success
+                    .map(|last_success| last_success.elapsed().as_secs()),
+                last_error: counters.last_error.as_ref().map(|(_, e)| e.clone()),
+                last_error_age_secs: counters
+                    .last_error
+                    .as_ref()
+                    .map(|(at, _)| at.elapsed().as_secs()),
+            };
+            (subsystem, report)
+        })
+        .collect()
+    }
+}
+
+/// Registers the calling thread for the stack high-water marks
+pub fn register_task(name: &'static str) {
+    let handle = unsafe { sys::xTaskGetCurrentTaskHandle() } as usize;
+    TASKS.lock().unwrap().push((name, handle));
+}
+
+/// Registered name of the calling thread, doesn't block or allocate so the panic hook can use it
+pub fn current_task() -> Option<&'static str> {
+    let handle = unsafe { sys::xTaskGetCurrentTaskHandle() } as usize;
+    let tasks = TASKS.try_lock().ok()?;
+    tasks
+        .iter()
+        .find(|(_, registered)| *registered == handle)
+        .map(|(name, _)| *name)
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaskStack {
+    pub name: &'static str,
+    /// Bytes of the stack never used so far
+    pub high_water_mark: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Heap {
+    pub free: u32,
+    /// Lowest free heap since boot
+    pub minimum_free: u32,
+    pub largest_free_block: usize,
+}
+
+pub fn uptime_secs() -> u64 {
+    (unsafe { sys::esp_timer_get_time() } / 1_000_000) as u64
+}
+
+pub fn reset_reason() -> &'static str {
+    match unsafe { sys::esp_reset_reason() } {
+        sys::esp_reset_reason_t_ESP_RST_POWERON => "power_on",
+        sys::esp_reset_reason_t_ESP_RST_EXT => "external",
+        sys::esp_reset_reason_t_ESP_RST_SW => "software",
+        sys::esp_reset_reason_t_ESP_RST_PANIC => "panic",
+        sys::esp_reset_reason_t_ESP_RST_INT_WDT => "interrupt_watchdog",
+        sys::esp_reset_reason_t_ESP_RST_TASK_WDT => "task_watchdog",
+        sys::esp_reset_reason_t_ESP_RST_WDT => "watchdog",
+        sys::esp_reset_reason_t_ESP_RST_DEEPSLEEP => "deep_sleep",
+        sys::esp_reset_reason_t_ESP_RST_BROWNOUT => "brownout",
+        sys::esp_reset_reason_t_ESP_RST_SDIO => "sdio",
+        _ => "unknown",
+    }
+}
+
+pub fn heap() -> Heap {
+    unsafe {
+        Heap {
+            free: sys::esp_get_free_heap_size(),
+            minimum_free: sys::esp_get_minimum_free_heap_size(),
+            largest_free_block: sys::heap_caps_get_largest_free_block(sys::MALLOC_CAP_8BIT),
+        }
+    }
+}
+
+pub fn task_stacks() -> Vec<TaskStack> {
+    let system = SYSTEM_TASKS.iter().filter_map(|&name| {
+        let c_name = CString::new(name).ok()?;
+        let handle = unsafe { sys::xTaskGetHandle(c_name.as_ptr()) };
+        (!handle.is_null()).then_some((name, handle as usize))
+    });
+    let registered = TASKS.lock().unwrap().clone();
+    system
+        .chain(registered)
+        .map(|(name, handle)| TaskStack {
+            name,
+            high_water_mark: unsafe {
+                sys::uxTaskGetStackHighWaterMark(handle as sys::TaskHandle_t)
+            },
+        })
+        .collect()
+}
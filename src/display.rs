@@ -0,0 +1,149 @@
+use serde::{Deserialize, Serialize};
+
+use crate::bands::{get_co2_band, get_humidity_band, get_pm25_band, get_temperature_band};
+use crate::color::{Color, LedPosition};
+use crate::palette::Palette;
+
+/// How the measured values are mapped to the three LEDs
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisplayMode {
+    /// CO2 top, PM2.5 bottom, their mix in the center
+    #[default]
+    Split,
+    /// The worse of CO2 and PM2.5 on all LEDs
+    WorstOf,
+    /// The number of lit LEDs grows with CO2
+    BarGraph,
+    /// Temperature top, humidity bottom, their mix in the center, both on the PM2.5 scale of the
+    /// palette
+    Climate,
+    /// CO2, PM2.5, temperature and humidity one after another on all LEDs
+    Cycle,
+    /// LEDs off unless the air is unhealthy, then like `WorstOf`
+    OffUnlessBad,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct DisplaySettings {
+    pub mode: DisplayMode,
+    /// Used instead of `mode` while a schedule window is active
+    #[serde(default)]
+    pub night_mode: Option<DisplayMode>,
+}
+
+impl DisplaySettings {
+    pub fn mode(&self, night: bool) -> DisplayMode {
+        match self.night_mode {
+            Some(night_mode) if night => night_mode,
+            _ => self.mode,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Measures {
+    pub co2: u16,
+    pub pm25: u16,
+    pub temperature: Option<f32>,
+    pub humidity: Option<f32>,
+}
+
+// Band from which the air counts as unhealthy for `OffUnlessBad`
+const BAD_BAND: usize = 2;
+
+const OFF: Color = Color {
+    r: 0,
+    g: 0,
+    b: 0,
+    brightness: None,
+};
+
+impl Measures {
+    /// Color of each metric on the quality scale of the palette, temperature and humidity are
+    /// missing when the SCD41 read failed
+    fn metric_colors(&self, palette: &Palette) -> Vec<Color> {
+        let mut colors = vec![
+            palette.co2[get_co2_band(self.co2)],
+            palette.pm25[get_pm25_band(self.pm25)],
+        ];
+        colors.extend(self.climate_colors(palette).into_iter().flatten());
+        colors
+    }
+
+    // The palettes have no comfort scale, their PM2.5 one goes from good to hazardous like the
+    // comfort bands
+    fn climate_colors(&self, palette: &Palette) -> [Option<Color>; 2] {
+        [
+            self.temperature
+                .map(|temperature| palette.pm25[get_temperature_band(temperature)]),
+            self.humidity
+                .map(|humidity| palette.pm25[get_humidity_band(humidity)]),
+        ]
+    }
+
+    /// Band and color of the worse of CO2 and PM2.5
+    fn worst(&self, palette: &Palette) -> (usize, Color) {
+        let co2_band = get_co2_band(self.co2);
+        let pm25_band = get_pm25_band(self.pm25);
+        if co2_band > pm25_band {
+            (co2_band, palette.co2[co2_band])
+        } else {
+            (pm25_band, palette.pm25[pm25_band])
+        }
+    }
+}
+
+/// Colors indexed by `LedPosition`, `cycle_step` selects the metric shown by `DisplayMode::Cycle`
+pub fn layout(
+    mode: DisplayMode,
+    measures: &Measures,
+    palette: &Palette,
+    cycle_step: usize,
+) -> [Color; 3] {
+    let mut colors = [OFF; 3];
+    let mut set = |position: LedPosition, color: Color| colors[position as usize] = color;
+
+    match mode {
+        DisplayMode::Split => {
+            let co2_color = palette.co2[get_co2_band(measures.co2)];
+            let pm25_color = palette.pm25[get_pm25_band(measures.pm25)];
+            set(LedPosition::Top, co2_color);
+            set(LedPosition::Center, pm25_color.mix(&co2_color));
+            set(LedPosition::Bottom, pm25_color);
+        }
+        DisplayMode::WorstOf => return [measures.worst(palette).1; 3],
+        DisplayMode::BarGraph => {
+            let band = get_co2_band(measures.co2);
+            let color = palette.co2[band];
+            set(LedPosition::Bottom, color);
+            if band >= 2 {
+                set(LedPosition::Center, color);
+            }
+            if band >= 3 {
+                set(LedPosition::Top, color);
+            }
+        }
+        DisplayMode::Climate => match measures.climate_colors(palette) {
+            [Some(temperature), Some(humidity)] => {
+                set(LedPosition::Top, temperature);
+                set(LedPosition::Center, temperature.mix(&humidity));
+                set(LedPosition::Bottom, humidity);
+            }
+            // no climate data, fall back to the air quality
+            _ => return layout(DisplayMode::Split, measures, palette, cycle_step),
+        },
+        DisplayMode::Cycle => {
+            let metrics = measures.metric_colors(palette);
+            return [metrics[cycle_step % metrics.len()]; 3];
+        }
+        DisplayMode::OffUnlessBad => {
+            let (band, color) = measures.worst(palette);
+            if band >= BAD_BAND {
+                return [color; 3];
+            }
+        }
+    }
+
+    colors
+}
@@ -0,0 +1,120 @@
+//! Robust summary of the PM2.5 samples of a window: Hampel filter and trimmed mean
+
+use serde::Serialize;
+
+// Scales the median absolute deviation to match the standard deviation of normally distributed data
+const MAD_SCALE: f32 = 1.4826;
+
+/// Robust summary of a series of readings taken during one measurement window
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Summary {
+    /// Trimmed mean of the accepted samples, this is the value we store
+    pub value: f32,
+    pub median: f32,
+    pub min: f32,
+    pub mean: f32,
+    pub max: f32,
+    pub stddev: f32,
+    pub samples: usize,
+    pub rejected: usize,
+}
+
+// PM1006 reports whole ug/m3, so the spread can't be lower than 1
+pub const PM25_FILTER: SampleFilter = SampleFilter::new(3.0, 0.2, 1.0);
+
+pub struct SampleFilter {
+    /// Samples further than `spike_threshold` robust standard deviations from the median are dropped
+    spike_threshold: f32,
+    /// Fraction of samples dropped from each end before averaging (0.0 - 0.5)
+    trim_ratio: f32,
+    /// Lower bound of the spread, so identical readings don't turn every small deviation into a spike
+    min_spread: f32,
+}
+
+impl SampleFilter {
+    pub const fn new(spike_threshold: f32, trim_ratio: f32, min_spread: f32) -> Self {
+        Self {
+            spike_threshold,
+            trim_ratio,
+            min_spread,
+        }
+    }
+
+    pub fn apply<T>(&self, samples: &[T]) -> Option<Summary>
+    where
+        T: Copy + Into<f32>,
+    {
+        let mut sorted: Vec<f32> = samples.iter().map(|&s| s.into()).collect();
+        sorted.sort_by(f32::total_cmp);
+
+        let inliers = reject_spikes(&sorted, self.spike_threshold, self.min_spread);
+        let value = trimmed_mean(&inliers, self.trim_ratio)?;
+        let (mean, stddev) = mean_stddev(&inliers)?;
+
+        Some(Summary {
+            value,
+            median: median(&inliers)?,
+            min: inliers[0],
+            mean,
+            max: inliers[inliers.len() - 1],
+            stddev,
+            samples: samples.len(),
+            rejected: samples.len() - inliers.len(),
+        })
+    }
+}
+
+/// Median of already sorted samples
+pub fn median(sorted: &[f32]) -> Option<f32> {
+    let len = sorted.len();
+    match len {
+        0 => None,
+        _ if len % 2 == 1 => Some(sorted[len / 2]),
+        _ => Some((sorted[len / 2 - 1] + sorted[len / 2]) / 2.0),
+    }
+}
+
+/// Drops the samples that are too far from the median (Hampel filter), keeps the order
+pub fn reject_spikes(sorted: &[f32], threshold: f32, min_spread: f32) -> Vec<f32> {
+    let Some(center) = median(sorted) else {
+        return Vec::new();
+    };
+
+    let mut deviations: Vec<f32> = sorted.iter().map(|s| (s - center).abs()).collect();
+    deviations.sort_by(f32::total_cmp);
+    let mad = median(&deviations).unwrap_or_default();
+    let spread = (mad * MAD_SCALE).max(min_spread);
+
+    sorted
+        .iter()
+        .copied()
+        .filter(|s| (s - center).abs() <= threshold * spread)
+        .collect()
+}
+
+/// Mean of already sorted samples without the `ratio` lowest and highest ones
+pub fn trimmed_mean(sorted: &[f32], ratio: f32) -> Option<f32> {
+    if sorted.is_empty() {
+        return None;
+    }
+
+    let trim = (sorted.len() as f32 * ratio.clamp(0.0, 0.5)) as usize;
+    // always keep at least one sample
+    let trim = trim.min((sorted.len() - 1) / 2);
+    let kept = &sorted[trim..sorted.len() - trim];
+
+    Some(kept.iter().sum::<f32>() / kept.len() as f32)
+}
+
+/// Mean and population standard deviation
+pub fn mean_stddev(samples: &[f32]) -> Option<(f32, f32)> {
+    if samples.is_empty() {
+        return None;
+    }
+
+    let count = samples.len() as f32;
+    let mean = samples.iter().sum::<f32>() / count;
+    let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<f32>() / count;
+
+    Some((mean, variance.sqrt()))
+}
@@ -0,0 +1,143 @@
+//! Gamma correction, white balance and perceptual brightness of the LED colors
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Error};
+// The pixel type of `smart_leds_trait`
+use rgb::RGB8;
+
+use crate::color::Color;
+
+// Channels this much dimmer than the brightest one are considered off (e.g. 255,0,5)
+const MIN_CHANNEL_RATIO: f32 = 0.02;
+
+/// Converts colors to WS2812 PWM values
+///
+/// Colors are sRGB-like (gamma encoded), the brightness is perceptual lightness (CIE L*),
+/// so that 128 looks half as bright as 255 and the hue doesn't change when dimming.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorPipeline {
+    pub gamma: f32,
+    /// Per channel gain (0.0 - 1.0) to calibrate the white point
+    pub white_balance: [f32; 3],
+    /// PWM value of the brightest channel of a full color at the lowest brightness, so dim
+    /// colors stay recognisable, the brightness is scaled over the range above it
+    pub min_level: u8,
+}
+
+impl Default for ColorPipeline {
+    fn default() -> Self {
+        Self {
+            gamma: 2.2,
+            white_balance: [1.0, 1.0, 1.0],
+            min_level: 8,
+        }
+    }
+}
+
+impl ColorPipeline {
+    pub fn apply(&self, color: Color) -> RGB8 {
+        let luminance = lightness_to_luminance(color.brightness.unwrap_or(255));
+        let linear = [color.r, color.g, color.b]
+            .map(|c| (c as f32 / 255.0).powf(self.gamma))
+            .iter()
+            .zip(self.white_balance)
+            .map(|(c, gain)| c * gain.clamp(0.0, 1.0))
+            .collect::<Vec<_>>();
+        let peak = linear.iter().copied().fold(0.0, f32::max);
+
+        if luminance <= 0.0 || peak <= 0.0 {
+            return RGB8::default();
+        }
+
+        // scale the brightest channel to the target level, keep the ratios of the others, darker
+        // colors stay darker at the lowest brightness too
+        let min_level = self.min_level as f32;
+        let level = peak * (min_level + (255.0 - min_level) * luminance);
+        let channel = |c: f32| {
+            let value = (c / peak * level).round().min(255.0) as u8;
+            if c / peak >= MIN_CHANNEL_RATIO {
+                value.max(1)
+            } else {
+                value
+            }
+        };
+
+        RGB8 {
+            r: channel(linear[0]),
+            g: channel(linear[1]),
+            b: channel(linear[2]),
+        }
+    }
+}
+
+/// CIE 1976 lightness (0 - 255 mapped to L* 0 - 100) to relative luminance (0.0 - 1.0)
+pub fn lightness_to_luminance(brightness: u8) -> f32 {
+    let lightness = brightness as f32 / 255.0 * 100.0;
+    if lightness > 8.0 {
+        ((lightness + 16.0) / 116.0).powi(3)
+    } else {
+        lightness / 903.3
+    }
+}
+
+/// Channel gains of a black body at the given temperature, 6500 K is neutral
+///
+/// Approximation of the CIE data by Tanner Helland, good enough for 1000 - 40000 K
+pub fn white_point(kelvin: u32) -> [f32; 3] {
+    let t = kelvin.clamp(1_000, 40_000) as f32 / 100.0;
+
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.699 * (t - 60.0).powf(-0.133_204_76)
+    };
+    let g = if t <= 66.0 {
+        99.470_8 * t.ln() - 161.119_57
+    } else {
+        288.122_16 * (t - 60.0).powf(-0.075_514_85)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_73 * (t - 10.0).ln() - 305.044_8
+    };
+
+    [r, g, b].map(|c| (c / 255.0).clamp(0.0, 1.0))
+}
+
+/// Parses white balance as `<r>,<g>,<b>` gains or a color temperature `<kelvin>K`
+impl FromStr for ColorPipeline {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let white_balance = if let Some(kelvin) = s.strip_suffix(['K', 'k']) {
+            let kelvin = kelvin
+                .trim()
+                .parse::<u32>()
+                .map_err(|e| anyhow!("Invalid color temperature {:?}: {}", kelvin, e))?;
+            white_point(kelvin)
+        } else {
+            let gains = s
+                .split(',')
+                .map(|gain| gain.trim().parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| anyhow!("Invalid white balance {:?}: {}", s, e))?;
+            match gains[..] {
+                [r, g, b] if [r, g, b].iter().all(|gain| (0.0..=1.0).contains(gain)) => [r, g, b],
+                _ => bail!(
+                    "Expected <r>,<g>,<b> gains 0.0 - 1.0 or <kelvin>K, got {:?}",
+                    s
+                ),
+            }
+        };
+
+        Ok(Self {
+            white_balance,
+            ..Default::default()
+        })
+    }
+}
@@ -1,7 +1,13 @@
+use std::fmt::Display;
+
+use anyhow::{anyhow, Result};
 use embedded_svc::http::server::{Connection, Request};
-use embedded_svc::io::Write;
+use embedded_svc::http::Query;
+use embedded_svc::io::{Read, Write};
 use serde::Serialize;
-use serde_json::Result;
+
+// Settings bodies are small, a larger one would exhaust the heap
+const MAX_BODY_LEN: usize = 16 * 1024;
 
 pub trait SendJson<C>
 where
@@ -10,6 +16,13 @@ where
     fn send_json<T>(self, json: &T) -> std::result::Result<(), C::Error>
     where
         T: ?Sized + Serialize;
+
+    fn send_json_status<T>(self, status: u16, json: &T) -> std::result::Result<(), C::Error>
+    where
+        T: ?Sized + Serialize;
+
+    /// `{"error": "<message>"}` with 400, for bodies that parse but aren't valid
+    fn send_bad_request(self, error: &dyn Display) -> std::result::Result<(), C::Error>;
 }
 
 impl<C> SendJson<C> for Request<C>
@@ -20,12 +33,37 @@ where
     where
         T: ?Sized + Serialize,
     {
-        let json = serde_json::to_string(json).unwrap();
-        self.into_response(200, Some("OK"), &[("Content-Type", "application/json")])
-            .unwrap()
-            .write_all(json.as_bytes())
-            .unwrap();
-        Ok(())
+        self.send_json_status(200, json)
+    }
+
+    fn send_json_status<T>(self, status: u16, json: &T) -> std::result::Result<(), C::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let (status, json) = match serde_json::to_string(json) {
+            Ok(json) => (status, json),
+            Err(e) => (
+                500,
+                serde_json::json!({ "error": e.to_string() }).to_string(),
+            ),
+        };
+        let reason = match status {
+            200 => "OK",
+            400 => "Bad Request",
+            500 => "Internal Server Error",
+            503 => "Service Unavailable",
+            _ => "",
+        };
+        self.into_response(
+            status,
+            Some(reason),
+            &[("Content-Type", "application/json")],
+        )?
+        .write_all(json.as_bytes())
+    }
+
+    fn send_bad_request(self, error: &dyn Display) -> std::result::Result<(), C::Error> {
+        self.send_json_status(400, &serde_json::json!({ "error": error.to_string() }))
     }
 }
 
@@ -47,17 +85,38 @@ where
         let length = self
             .connection()
             .header("Content-Length")
-            .unwrap()
-            .parse::<usize>()
-            .unwrap();
+            .and_then(|length| length.parse::<usize>().ok())
+            .ok_or_else(|| anyhow!("Content-Length is required"))?;
+        if length > MAX_BODY_LEN {
+            return Err(anyhow!("Body larger than {} bytes", MAX_BODY_LEN));
+        }
 
         // allocate buffer
         let mut buffer = vec![0; length];
 
-        // read body
-        self.read(&mut buffer).unwrap();
+        // read body, it may arrive in several chunks
+        self.read_exact(&mut buffer)
+            .map_err(|e| anyhow!("Failed to read the body: {:?}", e))?;
 
         // parse body
-        serde_json::from_slice(&buffer)
+        Ok(serde_json::from_slice(&buffer)?)
+    }
+}
+
+pub trait QueryParser {
+    /// Value of a query parameter, undecoded
+    fn query(&self, name: &str) -> Option<&str>;
+}
+
+impl<C> QueryParser for Request<C>
+where
+    C: Connection,
+{
+    fn query(&self, name: &str) -> Option<&str> {
+        let (_, query) = self.uri().split_once('?')?;
+        query.split('&').find_map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (key == name).then_some(value)
+        })
     }
 }
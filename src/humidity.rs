@@ -0,0 +1,72 @@
+//! Humidity correction of the PM2.5 readings
+
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Error};
+
+// Above this the growth model diverges, the correction is capped here
+const MAX_RELATIVE_HUMIDITY: f32 = 95.0;
+
+/// Compensation of the optical PM2.5 reading for particle growth at high humidity
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum HumidityCorrection {
+    #[default]
+    None,
+    /// Single parameter hygroscopic growth (kappa-Köhler), `kappa` is ~0.2-0.6 for urban aerosols
+    Kappa { kappa: f32 },
+    /// Calibration against a reference instrument, `slope * pm25 + offset`
+    Linear { slope: f32, offset: f32 },
+}
+
+impl HumidityCorrection {
+    /// Returns the corrected PM2.5 for the raw reading and relative humidity in %, the growth
+    /// model needs the humidity and is skipped without it, a calibration applies regardless
+    pub fn apply(&self, pm25: f32, humidity: Option<f32>) -> f32 {
+        let corrected = match (*self, humidity) {
+            (Self::None, _) | (Self::Kappa { .. }, None) => pm25,
+            (Self::Kappa { kappa }, Some(humidity)) => {
+                let water_activity = humidity.clamp(0.0, MAX_RELATIVE_HUMIDITY) / 100.0;
+                let growth = 1.0 + kappa * water_activity / (1.0 - water_activity);
+                pm25 / growth
+            }
+            (Self::Linear { slope, offset }, _) => slope * pm25 + offset,
+        };
+        corrected.max(0.0)
+    }
+}
+
+/// Parses `none`, `kappa:<kappa>` or `linear:<slope>,<offset>`
+impl FromStr for HumidityCorrection {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, params) = s.trim().split_once(':').unwrap_or((s.trim(), ""));
+        let parse = |value: &str| {
+            value
+                .trim()
+                .parse::<f32>()
+                .map_err(|e| anyhow!("Invalid number {:?}: {}", value, e))
+        };
+
+        match kind {
+            "" | "none" => Ok(Self::None),
+            "kappa" => {
+                let kappa = parse(params)?;
+                if !(0.0..=2.0).contains(&kappa) {
+                    bail!("Kappa {} out of range 0.0 - 2.0", kappa);
+                }
+                Ok(Self::Kappa { kappa })
+            }
+            "linear" => {
+                let (slope, offset) = params
+                    .split_once(',')
+                    .ok_or_else(|| anyhow!("Expected linear:<slope>,<offset>"))?;
+                Ok(Self::Linear {
+                    slope: parse(slope)?,
+                    offset: parse(offset)?,
+                })
+            }
+            _ => bail!("Unknown humidity correction {:?}", kind),
+        }
+    }
+}
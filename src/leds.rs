@@ -1,90 +1,206 @@
-use esp_idf_svc::hal::{gpio::OutputPin, peripheral::Peripheral, rmt::RmtChannel};
-use smart_leds_trait::{SmartLedsWrite, RGB8};
-use ws2812_esp32_rmt_driver::{driver::color::LedPixelColorGrb24, LedPixelEsp32Rmt};
-
-use crate::utils::{get_co2_color, get_pm25_color};
-
-#[derive(Debug, Clone, Copy, Default)]
-pub struct Color {
-    pub r: u8,
-    pub g: u8,
-    pub b: u8,
-    pub brightness: Option<u8>,
+use std::fmt::Debug;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use log::*;
+use rgb::RGB8;
+use serde::Deserialize;
+
+use crate::animation::{Indication, Indications};
+use crate::color::{Color, LedPosition};
+use crate::display::{layout, DisplayMode, Measures};
+use crate::gamma::ColorPipeline;
+use crate::palette::Palette;
+
+/// LED strip the final colors are written to, bottom to top
+pub trait LedWriter {
+    type Error: Debug;
+
+    fn write(&mut self, colors: [RGB8; 3]) -> Result<(), Self::Error>;
 }
 
-impl From<Color> for RGB8 {
-    fn from(color: Color) -> Self {
-        let brightness = color.brightness.unwrap_or(255);
-        Self {
-            r: ((color.r as u16) * (brightness as u16 + 1) / 256) as u8,
-            g: ((color.g as u16) * (brightness as u16 + 1) / 256) as u8,
-            b: ((color.b as u16) * (brightness as u16 + 1) / 256) as u8,
+pub struct Leds<D> {
+    colors: [Color; 3],
+    /// `None` when the driver couldn't be set up, the state is still kept for the API
+    driver: Option<D>,
+    brightness: u8,
+    pipeline: ColorPipeline,
+    palette: Palette,
+    display_mode: DisplayMode,
+    /// Last visualized measures
+    measures: Option<Measures>,
+    cycle_step: usize,
+    cycle_started: Instant,
+    /// Colors set through `override_colors` are kept until then
+    override_until: Option<Instant>,
+    indications: Indications,
+}
+
+/// Explicit colors for some time, positions not set are off
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct LedOverride {
+    #[serde(default)]
+    pub top: Option<Color>,
+    #[serde(default)]
+    pub center: Option<Color>,
+    #[serde(default)]
+    pub bottom: Option<Color>,
+    pub duration_secs: u64,
+}
+
+// Longer overrides are rejected, they would also overflow the expiration
+const MAX_OVERRIDE_SECS: u64 = 24 * 60 * 60;
+
+impl LedOverride {
+    pub fn validate(&self) -> Result<()> {
+        if self.duration_secs > MAX_OVERRIDE_SECS {
+            bail!(
+                "duration_secs must be at most {}, got {}",
+                MAX_OVERRIDE_SECS,
+                self.duration_secs
+            );
         }
+        Ok(())
     }
 }
 
-impl Color {
-    pub fn new(r: u8, g: u8, b: u8) -> Self {
-        Self {
-            r,
-            g,
-            b,
-            brightness: None,
-        }
+// Perceptual brightness, ~8 % of the full LED power like the linear 20 before gamma correction
+pub const INITIAL_BRIGHTNESS: u8 = 85;
+
+// Explicit colors are shown over this and the lower indications, the states the device would
+// otherwise report for good (sensor fault, provisioning), but not over alerts, identify or updates
+const OVERRIDABLE: Indication = Indication::SensorFault;
+
+// How long each metric is shown with `DisplayMode::Cycle`
+const CYCLE_INTERVAL: Duration = Duration::from_secs(5);
+
+impl<D> Leds<D>
+where
+    D: LedWriter,
+{
+    pub fn with_driver(driver: D) -> Self {
+        Self::new(Some(driver))
     }
 
-    fn brightness(&self, brightness: u8) -> Self {
+    /// Without LEDs to write to, the device keeps measuring
+    pub fn disconnected() -> Self {
+        Self::new(None)
+    }
+
+    fn new(driver: Option<D>) -> Self {
         Self {
-            r: self.r,
-            g: self.g,
-            b: self.b,
-            brightness: Some(brightness),
+            driver,
+            colors: [Color::default(); 3],
+            brightness: INITIAL_BRIGHTNESS,
+            pipeline: ColorPipeline::default(),
+            palette: Palette::default(),
+            display_mode: DisplayMode::default(),
+            measures: None,
+            cycle_step: 0,
+            cycle_started: Instant::now(),
+            override_until: None,
+            indications: Indications::new(Instant::now()),
         }
     }
 
-    pub fn mix(&self, other: &Self) -> Self {
-        Self {
-            r: ((self.r as u16 + other.r as u16) / 2) as u8,
-            g: ((self.g as u16 + other.g as u16) / 2) as u8,
-            b: ((self.b as u16 + other.b as u16) / 2) as u8,
-            brightness: None,
+    /// Writes the current colors, or the animation frame while there is an active indication that
+    /// isn't overridden
+    pub fn flush(&mut self) -> Result<(), D::Error> {
+        self.flush_at(Instant::now())
+    }
+
+    /// `flush()` with the animation frame at the given time
+    pub fn flush_at(&mut self, now: Instant) -> Result<(), D::Error> {
+        let Some(driver) = &mut self.driver else {
+            return Ok(());
+        };
+        let frame = match self.indications.shown() {
+            Some(shown) if shown <= OVERRIDABLE && self.override_until.is_some() => None,
+            _ => self.indications.frame(&self.palette, now),
+        };
+        match frame {
+            Some(frame) => {
+                let brightness = self.brightness as u16;
+                let frame = frame.map(|color| {
+                    let level = color.brightness.unwrap_or(255) as u16;
+                    self.pipeline
+                        .apply(color.brightness((level * brightness / 255) as u8))
+                });
+                driver.write(frame)
+            }
+            None => driver.write(self.colors.map(|color| self.pipeline.apply(color))),
         }
     }
-}
 
-#[derive(Debug, Copy, Clone)]
-pub enum LedPosition {
-    Bottom = 0,
-    Center = 1,
-    Top = 2,
-}
+    pub fn set_pipeline(&mut self, pipeline: ColorPipeline) -> &mut Self {
+        self.pipeline = pipeline;
+        self
+    }
 
-pub struct Leds {
-    colors: [Color; 3],
-    driver: LedPixelEsp32Rmt<'static, RGB8, LedPixelColorGrb24>,
-    brightness: u8,
-}
+    /// Advances the animation and the cycling display mode, expires the overrides,
+    /// to be called periodically (every ~50 ms)
+    pub fn tick(&mut self) -> Result<(), D::Error> {
+        self.tick_at(Instant::now())
+    }
 
-pub const INITIAL_BRIGHTNESS: u8 = 20;
+    /// `tick()` at the given time
+    pub fn tick_at(&mut self, now: Instant) -> Result<(), D::Error> {
+        let mut changed = self.indications.expire(now);
 
-impl Leds {
-    pub fn new<C: RmtChannel>(
-        channel: impl Peripheral<P = C> + 'static,
-        pin: impl Peripheral<P = impl OutputPin> + 'static,
-    ) -> Self {
-        let driver = LedPixelEsp32Rmt::<RGB8, LedPixelColorGrb24>::new(channel, pin).unwrap();
-        Self {
-            driver,
-            colors: [Color::default(); 3],
-            brightness: INITIAL_BRIGHTNESS,
+        if self.override_until.is_some_and(|until| now >= until) {
+            self.override_until = None;
+            self.apply_layout();
+            changed = true;
         }
+
+        if self.display_mode == DisplayMode::Cycle && now - self.cycle_started >= CYCLE_INTERVAL {
+            self.cycle_step = self.cycle_step.wrapping_add(1);
+            self.cycle_started = now;
+            self.apply_layout();
+            changed = true;
+        }
+
+        if self.indications.shown().is_none() && !changed {
+            return Ok(());
+        }
+        self.flush_at(now)
     }
 
-    pub fn flush(&mut self) -> Result<(), ws2812_esp32_rmt_driver::Ws2812Esp32RmtDriverError> {
-        self.driver.write(self.colors.iter().cloned())
+    /// Turns the indication on or off, the animation restarts when the shown indication changes
+    pub fn set_indication(&mut self, indication: Indication, active: bool) -> &mut Self {
+        let now = Instant::now();
+        if active {
+            self.indications.insert(indication, None, now);
+        } else {
+            self.indications.remove(indication, now);
+        }
+        self
     }
 
-    pub fn set_brightness(&mut self, brightness: u8) -> &mut Leds {
+    /// Turns the indication on for the given time
+    pub fn set_indication_for(&mut self, indication: Indication, duration: Duration) -> &mut Self {
+        let now = Instant::now();
+        self.indications
+            .insert(indication, Some(now + duration), now);
+        self
+    }
+
+    pub fn has_indication(&self, indication: Indication) -> bool {
+        self.indications.is_active(indication)
+    }
+
+    /// Shows explicit colors instead of the measures for a while, see `LedOverride`
+    pub fn override_colors(&mut self, led_override: LedOverride) -> &mut Self {
+        let off = Color::default();
+        self.set_color(LedPosition::Top, led_override.top.unwrap_or(off))
+            .set_color(LedPosition::Center, led_override.center.unwrap_or(off))
+            .set_color(LedPosition::Bottom, led_override.bottom.unwrap_or(off));
+        self.override_until =
+            Some(Instant::now() + Duration::from_secs(led_override.duration_secs));
+        self
+    }
+
+    pub fn set_brightness(&mut self, brightness: u8) -> &mut Self {
         self.brightness = brightness;
         self.colors.iter_mut().for_each(|color| {
             *color = color.brightness(brightness);
@@ -92,7 +208,7 @@ impl Leds {
         self
     }
 
-    pub fn set_color(&mut self, position: LedPosition, mut color: Color) -> &mut Leds {
+    pub fn set_color(&mut self, position: LedPosition, mut color: Color) -> &mut Self {
         color = color.brightness(self.brightness);
 
         self.colors[position as usize] = color;
@@ -102,34 +218,78 @@ impl Leds {
     pub fn get_brightness(&self) -> u8 {
         self.brightness
     }
+
+    pub fn get_display_mode(&self) -> DisplayMode {
+        self.display_mode
+    }
+
+    /// Sets the colors of the last measures according to the display mode and palette,
+    /// unless they are overridden
+    fn apply_layout(&mut self) {
+        if self.override_until.is_some() {
+            return;
+        }
+        if let Some(measures) = self.measures {
+            let colors = layout(self.display_mode, &measures, &self.palette, self.cycle_step);
+            self.set_color(LedPosition::Bottom, colors[LedPosition::Bottom as usize])
+                .set_color(LedPosition::Center, colors[LedPosition::Center as usize])
+                .set_color(LedPosition::Top, colors[LedPosition::Top as usize]);
+        }
+    }
 }
 
-impl Leds {
+impl<D> Leds<D>
+where
+    D: LedWriter,
+{
+    /// Flushes, a failing LED strip must not stop the measurements so errors are only logged
+    pub fn show(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("Error updating LEDs: {:?}", e);
+        }
+    }
+
     pub fn set_initial_color(&mut self) {
-        let initial_color = Color::new(255, 0, 255); // Fuchsia / Magenta / Violet
+        let initial_color = self.palette.initial;
         self.set_color(LedPosition::Top, initial_color)
             .set_color(LedPosition::Bottom, initial_color)
             .set_color(LedPosition::Center, initial_color)
-            .flush()
-            .unwrap();
+            .show();
     }
 
     pub fn set_waiting_color(&mut self) {
-        let waiting_color = Color::new(0, 255, 255); // cyan
+        let waiting_color = self.palette.waiting;
         self.set_color(LedPosition::Top, waiting_color)
             .set_color(LedPosition::Bottom, waiting_color)
             .set_color(LedPosition::Center, waiting_color)
-            .flush()
-            .unwrap();
-    }
-
-    pub fn visualize_measures(&mut self, co2: u16, pm25: u16) {
-        let co2_color = get_co2_color(co2);
-        let pm25_color = get_pm25_color(pm25);
-        self.set_color(LedPosition::Top, co2_color)
-            .set_color(LedPosition::Center, pm25_color.mix(&co2_color))
-            .set_color(LedPosition::Bottom, pm25_color)
-            .flush()
-            .unwrap();
+            .show();
+    }
+
+    /// Changes all the colors, the last measures are shown again with the new palette
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        if self.measures.is_some() {
+            self.apply_layout();
+            self.show();
+        }
+    }
+
+    pub fn set_display_mode(&mut self, display_mode: DisplayMode) {
+        if display_mode == self.display_mode {
+            return;
+        }
+        self.display_mode = display_mode;
+        self.cycle_step = 0;
+        self.cycle_started = Instant::now();
+        if self.measures.is_some() {
+            self.apply_layout();
+            self.show();
+        }
+    }
+
+    pub fn visualize_measures(&mut self, measures: Measures) {
+        self.measures = Some(measures);
+        self.apply_layout();
+        self.show();
     }
 }
@@ -0,0 +1,88 @@
+//! Iteration budgets of the long running loops, catches the ones that feed but stall
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+#[derive(Debug)]
+struct Watched {
+    budget: Duration,
+    started: Instant,
+    iterations: u64,
+    longest: Duration,
+}
+
+/// A loop whose current iteration runs longer than its budget
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stall {
+    pub name: &'static str,
+    pub elapsed: Duration,
+    pub budget: Duration,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoopReport {
+    pub budget_secs: u64,
+    pub current_secs: u64,
+    /// Longest finished iteration
+    pub longest_secs: u64,
+    pub iterations: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Liveness {
+    loops: BTreeMap<&'static str, Watched>,
+}
+
+impl Liveness {
+    /// Watches a loop, its first iteration starts now
+    pub fn register(&mut self, name: &'static str, budget: Duration, now: Instant) {
+        self.loops.insert(
+            name,
+            Watched {
+                budget,
+                started: now,
+                iterations: 0,
+                longest: Duration::ZERO,
+            },
+        );
+    }
+
+    /// Ends the current iteration of the loop and starts the next one
+    pub fn beat(&mut self, name: &'static str, now: Instant) {
+        if let Some(watched) = self.loops.get_mut(name) {
+            watched.longest = watched.longest.max(now - watched.started);
+            watched.iterations += 1;
+            watched.started = now;
+        }
+    }
+
+    /// The loop furthest over its budget
+    pub fn stalled(&self, now: Instant) -> Option<Stall> {
+        self.loops
+            .iter()
+            .map(|(&name, watched)| Stall {
+                name,
+                elapsed: now - watched.started,
+                budget: watched.budget,
+            })
+            .filter(|stall| stall.elapsed > stall.budget)
+            .max_by_key(|stall| stall.elapsed - stall.budget)
+    }
+
+    pub fn report(&self, now: Instant) -> BTreeMap<&'static str, LoopReport> {
+        self.loops
+            .iter()
+            .map(|(&name, watched)| {
+                let report = LoopReport {
+                    budget_secs: watched.budget.as_secs(),
+                    current_secs: (now - watched.started).as_secs(),
+                    longest_secs: watched.longest.as_secs(),
+                    iterations: watched.iterations,
+                };
+                (name, report)
+            })
+            .collect()
+    }
+}
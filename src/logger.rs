@@ -0,0 +1,277 @@
+//! `log` backend replacing the plain `EspLogger`: records go to the serial console, into the RAM
+//! ring buffer of `GET /logs` and, when configured, to a remote syslog collector. The levels of
+//! `LogConfig` apply to all three, the console also keeps the ESP-IDF log level.
+
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use esp_idf_svc::log::EspLogger;
+use esp_idf_svc::sys;
+use log::{Log, Metadata, Record};
+use serde::Serialize;
+
+use crate::diagnostics;
+use crate::logs::{self, Levels, LogConfig, LogLine, Protocol, Remote, RingBuffer};
+use crate::utils::{device_id, unix_time};
+
+const BUFFER_CAPACITY: usize = 16 * 1024;
+// Lines waiting for the forwarder, newer ones are dropped while it's blocked
+const QUEUE_LEN: usize = 64;
+const FORWARDER_STACK_SIZE: usize = 6 * 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+// Lines are dropped until the next attempt
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RemoteStatus {
+    pub connected: bool,
+    pub last_error: Option<String>,
+    /// Lines that couldn't be sent
+    pub dropped: u64,
+}
+
+#[derive(Default)]
+struct Forwarding {
+    remote: Option<Remote>,
+    /// Started with the first remote, it's kept running afterwards
+    sender: Option<SyncSender<LogLine>>,
+    status: RemoteStatus,
+    /// HOSTNAME of the messages, the MAC address until `set_hostname()`
+    hostname: Option<String>,
+}
+
+struct Logger {
+    serial: EspLogger,
+    levels: RwLock<Levels>,
+    buffer: Mutex<RingBuffer>,
+    forwarding: Mutex<Forwarding>,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+fn logger() -> &'static Logger {
+    LOGGER.get_or_init(|| Logger {
+        serial: EspLogger::new(),
+        levels: RwLock::new(Levels::default()),
+        buffer: Mutex::new(RingBuffer::new(BUFFER_CAPACITY)),
+        forwarding: Mutex::new(Forwarding::default()),
+    })
+}
+
+/// Installs the logger with the default levels until `configure()`
+pub fn initialize() {
+    let logger = logger();
+    log::set_logger(logger).unwrap();
+    log::set_max_level(logger.levels.read().unwrap().max());
+}
+
+pub fn configure(config: LogConfig) -> Result<()> {
+    let logger = logger();
+    log::set_max_level(config.levels.max());
+    *logger.levels.write().unwrap() = config.levels;
+
+    let mut forwarding = logger.forwarding.lock().unwrap();
+    if config.remote.is_some() && forwarding.sender.is_none() {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_LEN);
+        thread::Builder::new()
+            .name("syslog".into())
+            .stack_size(FORWARDER_STACK_SIZE)
+            .spawn(move || forward(receiver))?;
+        forwarding.sender = Some(sender);
+    }
+    if forwarding.remote != config.remote {
+        forwarding.remote = config.remote;
+        forwarding.status = RemoteStatus::default();
+    }
+    Ok(())
+}
+
+pub fn set_hostname(hostname: &str) {
+    logger().forwarding.lock().unwrap().hostname = Some(hostname.into());
+}
+
+pub fn config() -> LogConfig {
+    let logger = logger();
+    LogConfig {
+        levels: logger.levels.read().unwrap().clone(),
+        remote: logger.forwarding.lock().unwrap().remote.clone(),
+    }
+}
+
+pub fn remote_status() -> Option<RemoteStatus> {
+    let forwarding = logger().forwarding.lock().unwrap();
+    forwarding
+        .remote
+        .is_some()
+        .then(|| forwarding.status.clone())
+}
+
+#[derive(Debug, Serialize)]
+pub struct Lines {
+    /// Pass as `since` to get the lines logged after these
+    pub next_seq: u64,
+    /// Lines that didn't fit into the buffer anymore
+    pub dropped: u64,
+    pub lines: Vec<LogLine>,
+}
+
+/// Copied out, serializing under the lock would deadlock on logs of the HTTP server
+pub fn lines(since: Option<u64>) -> Lines {
+    let buffer = logger().buffer.lock().unwrap();
+    Lines {
+        next_seq: buffer.next_seq(),
+        dropped: buffer.dropped(),
+        lines: buffer.since(since).cloned().collect(),
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.levels
+            .read()
+            .is_ok_and(|levels| levels.enabled(metadata.level(), metadata.target()))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.serial.log(record);
+
+        let line = LogLine {
+            seq: 0,
+            uptime_ms: (unsafe { sys::esp_timer_get_time() } / 1000) as u64,
+            timestamp_ms: unix_time().map(|since_epoch| since_epoch.as_millis() as i64),
+            level: record.level(),
+            target: record.target().into(),
+            thread: thread::current().name().map(Into::into),
+            message: record.args().to_string(),
+        };
+        if let Ok(mut forwarding) = self.forwarding.lock() {
+            if let (Some(_), Some(sender)) = (&forwarding.remote, &forwarding.sender) {
+                if sender.try_send(line.clone()).is_err() {
+                    forwarding.status.dropped += 1;
+                }
+            }
+        }
+        if let Ok(mut buffer) = self.buffer.lock() {
+            buffer.push(line);
+        }
+    }
+
+    fn flush(&self) {
+        self.serial.flush();
+    }
+}
+
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+impl Connection {
+    fn open(remote: &Remote) -> Result<Self> {
+        let address = (remote.host.as_str(), remote.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("{} didn't resolve", remote.host))?;
+        Ok(match remote.protocol {
+            Protocol::Udp => {
+                let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+                socket.connect(address)?;
+                Self::Udp(socket)
+            }
+            Protocol::Tcp => {
+                let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+                stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                Self::Tcp(stream)
+            }
+        })
+    }
+
+    fn send(&mut self, data: &[u8]) -> Result<()> {
+        match self {
+            Self::Udp(socket) => {
+                socket.send(data)?;
+            }
+            Self::Tcp(stream) => stream.write_all(data)?,
+        }
+        Ok(())
+    }
+}
+
+/// Sends the queued lines, errors are kept in the status instead of being logged as each log
+/// line would fail again
+fn forward(lines: Receiver<LogLine>) {
+    diagnostics::register_task("syslog");
+    let mac = device_id();
+    let mut connection: Option<(Remote, Connection)> = None;
+    let mut retry_at: Option<Instant> = None;
+
+    for line in lines {
+        let (remote, hostname) = {
+            let forwarding = logger().forwarding.lock().unwrap();
+            (forwarding.remote.clone(), forwarding.hostname.clone())
+        };
+        let Some(remote) = remote else {
+            connection = None;
+            continue;
+        };
+        if connection.as_ref().is_some_and(|(open, _)| *open != remote) {
+            connection = None;
+            retry_at = None;
+        }
+        if connection.is_none() {
+            if retry_at.is_some_and(|retry_at| Instant::now() < retry_at) {
+                failed(&remote, None);
+                continue;
+            }
+            match Connection::open(&remote) {
+                Ok(opened) => connection = Some((remote.clone(), opened)),
+                Err(e) => {
+                    retry_at = Some(Instant::now() + RECONNECT_INTERVAL);
+                    failed(&remote, Some(e));
+                    continue;
+                }
+            }
+        }
+
+        let Some((_, opened)) = &mut connection else {
+            continue;
+        };
+        let message = logs::format_syslog(&line, hostname.as_deref().unwrap_or(&mac));
+        match opened.send(&logs::frame(remote.protocol, &message)) {
+            Ok(()) => {
+                let mut forwarding = logger().forwarding.lock().unwrap();
+                if forwarding.remote.as_ref() == Some(&remote) {
+                    forwarding.status.connected = true;
+                }
+            }
+            Err(e) => {
+                connection = None;
+                retry_at = Some(Instant::now() + RECONNECT_INTERVAL);
+                failed(&remote, Some(e));
+            }
+        }
+    }
+}
+
+/// Counts a line that wasn't sent to `remote`
+fn failed(remote: &Remote, error: Option<anyhow::Error>) {
+    let mut forwarding = logger().forwarding.lock().unwrap();
+    // reconfigured meanwhile
+    if forwarding.remote.as_ref() != Some(remote) {
+        return;
+    }
+    forwarding.status.dropped += 1;
+    forwarding.status.connected = false;
+    if let Some(e) = error {
+        forwarding.status.last_error = Some(e.to_string());
+    }
+}
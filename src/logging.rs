@@ -7,6 +7,10 @@ use embedded_svc::{
 use log::*;
 use serde::Serialize;
 
+use crate::alerts::{self, Notification, Webhook};
+use crate::crash::CrashReport;
+use crate::filter::Summary;
+
 use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
 
 const URL: &str = env!("LOG_URL");
@@ -16,11 +20,44 @@ const API_KEY: &str = env!("LOG_API_KEY");
 pub struct LogEntry {
     co2: u16,
     pm25: u16,
+    pm25_raw: u16,
+    humidity: Option<f32>,
+    pm25_stats: Option<Summary>,
+    /// Left out until the clock has been synced, the server's receive time is used then
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<i64>,
+    /// Sent once, with the first upload after the crash
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crash: Option<CrashReport>,
 }
 
 impl LogEntry {
-    pub fn new(co2: u16, pm25: u16) -> Self {
-        Self { co2, pm25 }
+    pub fn new(
+        co2: u16,
+        pm25: u16,
+        pm25_raw: u16,
+        humidity: Option<f32>,
+        pm25_stats: Option<Summary>,
+        timestamp: Option<i64>,
+    ) -> Self {
+        Self {
+            co2,
+            pm25,
+            pm25_raw,
+            humidity,
+            pm25_stats,
+            timestamp,
+            crash: None,
+        }
+    }
+
+    pub fn with_crash(mut self, crash: Option<CrashReport>) -> Self {
+        self.crash = crash;
+        self
+    }
+
+    pub fn has_crash(&self) -> bool {
+        self.crash.is_some()
     }
 }
 
@@ -59,8 +96,8 @@ fn print_response(response: &mut impl Read) -> Result<()> {
     Ok(())
 }
 
-pub fn log_data(log_entry: &LogEntry) -> Result<()> {
-    // 1. Create a new EspHttpClient. (Check documentation)
+/// HTTP(S) client connection, server certificates are verified against the ESP-IDF bundle
+pub fn connection() -> Result<EspHttpConnection> {
     // ANCHOR: connection
     let connection = EspHttpConnection::new(&Configuration {
         use_global_ca_store: true,
@@ -68,13 +105,18 @@ pub fn log_data(log_entry: &LogEntry) -> Result<()> {
         ..Default::default()
     })?;
     // ANCHOR_END: connection
-    let mut client = Client::wrap(connection);
+    Ok(connection)
+}
 
-    // 2. Open a GET request to `url`
-    let headers = [("content-type", "application/json"), ("apikey", API_KEY)];
-    let mut request = client.request(Method::Post, URL.as_ref(), &headers)?;
+/// POSTs a JSON payload to `url`, fails on non-2xx responses
+pub fn post_json(url: &str, headers: &[(&str, &str)], payload: &str) -> Result<()> {
+    // 1. Create a new EspHttpClient. (Check documentation)
+    let mut client = Client::wrap(connection()?);
+
+    // 2. Open a POST request to `url`
+    let headers = [&[("content-type", "application/json")][..], headers].concat();
+    let mut request = client.request(Method::Post, url, &headers)?;
 
-    let payload = serde_json::to_string(&log_entry)?;
     request.write_all(payload.as_bytes())?;
     request.flush()?;
 
@@ -89,3 +131,20 @@ pub fn log_data(log_entry: &LogEntry) -> Result<()> {
 
     Ok(())
 }
+
+pub fn log_data(log_entry: &LogEntry) -> Result<()> {
+    let payload = serde_json::to_string(&log_entry)?;
+    post_json(URL, &[("apikey", API_KEY)], &payload)
+}
+
+/// Sends the notification to all the webhooks, a failing webhook doesn't stop the others
+pub fn send_webhooks(
+    webhooks: &[Webhook],
+    notification: &Notification,
+    device: &str,
+    timestamp: Option<i64>,
+) -> Result<()> {
+    alerts::send_webhooks(webhooks, notification, device, timestamp, |url, payload| {
+        post_json(url, &[], payload)
+    })
+}
@@ -0,0 +1,311 @@
+//! Ring buffer of `GET /logs`, per-module levels and the syslog formatting
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use log::{Level, LevelFilter};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use time::OffsetDateTime;
+
+// local0, the facility of the RFC 5424 priority
+const FACILITY: u8 = 16;
+const APP_NAME: &str = "esp-vindriktning";
+// Header field lengths of RFC 5424
+const HOSTNAME_MAX_LEN: usize = 255;
+const PROCID_MAX_LEN: usize = 128;
+const DEFAULT_SYSLOG_PORT: u16 = 514;
+// Bookkeeping of a buffered line besides its text
+const LINE_OVERHEAD: usize = 48;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LogLine {
+    /// Increments with every line, `GET /logs?since=<seq>` returns the newer ones
+    pub seq: u64,
+    pub uptime_ms: u64,
+    /// Unix time in milliseconds, `None` before the clock was synced
+    pub timestamp_ms: Option<i64>,
+    #[serde(serialize_with = "serialize_level")]
+    pub level: Level,
+    /// Module path of the record
+    pub target: String,
+    pub thread: Option<String>,
+    pub message: String,
+}
+
+impl LogLine {
+    fn size(&self) -> usize {
+        LINE_OVERHEAD
+            + self.target.len()
+            + self.thread.as_ref().map_or(0, String::len)
+            + self.message.len()
+    }
+}
+
+fn serialize_level<S: Serializer>(level: &Level, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&level.as_str().to_lowercase())
+}
+
+/// Newest lines within a byte budget, the oldest ones are dropped first
+#[derive(Debug)]
+pub struct RingBuffer {
+    lines: VecDeque<LogLine>,
+    capacity: usize,
+    size: usize,
+    next_seq: u64,
+}
+
+impl RingBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            capacity,
+            size: 0,
+            next_seq: 0,
+        }
+    }
+
+    /// Numbers the line, a single line larger than the capacity still replaces everything
+    pub fn push(&mut self, mut line: LogLine) -> u64 {
+        line.seq = self.next_seq;
+        self.next_seq += 1;
+        self.size += line.size();
+        self.lines.push_back(line);
+        while self.size > self.capacity && self.lines.len() > 1 {
+            if let Some(dropped) = self.lines.pop_front() {
+                self.size -= dropped.size();
+            }
+        }
+        self.next_seq - 1
+    }
+
+    /// Lines with a sequence number of at least `seq`, all of them without it
+    pub fn since(&self, seq: Option<u64>) -> impl Iterator<Item = &LogLine> {
+        let seq = seq.unwrap_or(0);
+        self.lines.iter().filter(move |line| line.seq >= seq)
+    }
+
+    /// Lines that were pushed but aren't buffered anymore
+    pub fn dropped(&self) -> u64 {
+        self.next_seq - self.lines.len() as u64
+    }
+
+    pub fn next_seq(&self) -> u64 {
+        self.next_seq
+    }
+}
+
+/// `LevelFilter` as lowercase `off`, `error`, `warn`, `info`, `debug` or `trace`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Threshold(pub LevelFilter);
+
+impl Serialize for Threshold {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0.as_str().to_lowercase())
+    }
+}
+
+impl<'de> Deserialize<'de> for Threshold {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value
+            .parse()
+            .map(Self)
+            .map_err(|_| de::Error::custom(format!("Invalid log level {:?}", value)))
+    }
+}
+
+/// Level of each module, a module without its own level inherits the one of its parent
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Levels {
+    pub default: Threshold,
+    /// Module paths like `esp_vindriktning::clock` or crate names like `esp_idf_svc`
+    pub modules: BTreeMap<String, Threshold>,
+}
+
+impl Default for Levels {
+    fn default() -> Self {
+        Self {
+            default: Threshold(LevelFilter::Info),
+            modules: BTreeMap::new(),
+        }
+    }
+}
+
+impl Levels {
+    pub fn level(&self, target: &str) -> LevelFilter {
+        let mut module = target;
+        loop {
+            if let Some(threshold) = self.modules.get(module) {
+                return threshold.0;
+            }
+            match module.rfind("::") {
+                Some(end) => module = &module[..end],
+                None => return self.default.0,
+            }
+        }
+    }
+
+    pub fn enabled(&self, level: Level, target: &str) -> bool {
+        level <= self.level(target)
+    }
+
+    /// Most verbose level of any module, for `log::set_max_level`
+    pub fn max(&self) -> LevelFilter {
+        self.modules
+            .values()
+            .map(|threshold| threshold.0)
+            .fold(self.default.0, Ord::max)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    /// RFC 5424 messages, one per datagram
+    Udp,
+    /// RFC 5424 messages framed by octet counting (RFC 6587)
+    Tcp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Remote {
+    pub protocol: Protocol,
+    pub host: String,
+    pub port: u16,
+}
+
+impl Remote {
+    pub fn validate(&self) -> Result<()> {
+        if self.host.is_empty() || self.host.contains(char::is_whitespace) {
+            bail!("Invalid host {:?}", self.host);
+        }
+        if self.port == 0 {
+            bail!("port must be positive");
+        }
+        Ok(())
+    }
+}
+
+impl Display for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match self.protocol {
+            Protocol::Udp => "udp",
+            Protocol::Tcp => "tcp",
+        };
+        write!(f, "{}://{}:{}", scheme, self.host, self.port)
+    }
+}
+
+/// `udp://<host>[:<port>]` or `tcp://<host>[:<port>]`, port 514 by default
+impl FromStr for Remote {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (protocol, address) = if let Some(address) = value.strip_prefix("udp://") {
+            (Protocol::Udp, address)
+        } else if let Some(address) = value.strip_prefix("tcp://") {
+            (Protocol::Tcp, address)
+        } else {
+            bail!("Expected udp://<host>[:<port>] or tcp://<host>[:<port>]");
+        };
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| anyhow!("Invalid port {:?}", port))?,
+            ),
+            None => (address, DEFAULT_SYSLOG_PORT),
+        };
+        let remote = Self {
+            protocol,
+            host: host.into(),
+            port,
+        };
+        remote.validate()?;
+        Ok(remote)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    pub levels: Levels,
+    /// Lines are only kept in RAM without it
+    pub remote: Option<Remote>,
+}
+
+impl LogConfig {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(remote) = &self.remote {
+            remote.validate()?;
+        }
+        Ok(())
+    }
+}
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Printable ASCII without spaces, `-` (nil) when empty
+fn header_field(value: &str, max_len: usize) -> String {
+    let field: String = value
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+        .take(max_len)
+        .collect();
+    if field.is_empty() {
+        "-".into()
+    } else {
+        field
+    }
+}
+
+fn rfc3339(timestamp_ms: i64) -> Option<String> {
+    let datetime =
+        OffsetDateTime::from_unix_timestamp_nanos(i128::from(timestamp_ms) * 1_000_000).ok()?;
+    Some(format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        datetime.year(),
+        datetime.month() as u8,
+        datetime.day(),
+        datetime.hour(),
+        datetime.minute(),
+        datetime.second(),
+        datetime.millisecond()
+    ))
+}
+
+/// RFC 5424 message, the thread is the PROCID and the module the beginning of the MSG
+pub fn format_syslog(line: &LogLine, hostname: &str) -> String {
+    let timestamp = line
+        .timestamp_ms
+        .and_then(rfc3339)
+        .unwrap_or_else(|| "-".into());
+    format!(
+        "<{}>1 {} {} {} {} - - {}: {}",
+        FACILITY * 8 + severity(line.level),
+        timestamp,
+        header_field(hostname, HOSTNAME_MAX_LEN),
+        APP_NAME,
+        header_field(line.thread.as_deref().unwrap_or(""), PROCID_MAX_LEN),
+        line.target,
+        line.message
+    )
+}
+
+/// Bytes sent for one message over the protocol
+pub fn frame(protocol: Protocol, message: &str) -> Vec<u8> {
+    match protocol {
+        Protocol::Udp => message.as_bytes().to_vec(),
+        Protocol::Tcp => format!("{} {}", message.len(), message).into_bytes(),
+    }
+}
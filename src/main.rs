@@ -1,40 +1,258 @@
 use anyhow::*;
+use embedded_svc::http::Headers;
 use embedded_svc::http::Method;
 use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::hal::prelude::Peripherals;
 use esp_idf_svc::http::server::EspHttpServer;
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
 use esp_idf_svc::timer::EspTaskTimerService;
 use esp_idf_svc::wifi::WifiEvent;
 use log::*;
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::result::Result::Ok;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::sync::RwLock;
+use std::thread;
 use std::time::Duration;
+use std::time::Instant;
 
+use alerts::AlertConfig;
+use alerts::AlertEngine;
+use animation::Indication;
 use board::Board;
+use board::LedDriver;
+use brightness::BrightnessArbiter;
+use brightness::BrightnessPreferences;
+use brightness::ManualBrightness;
 use clock::Clock;
+use clock::SyncState;
+use crash::CrashReport;
+use diagnostics::Diagnostics;
+use diagnostics::Health;
+use diagnostics::Subsystem;
+use display::DisplaySettings;
+use display::Measures;
+use filter::Summary;
+use filter::PM25_FILTER;
+use gamma::ColorPipeline;
+use humidity::HumidityCorrection;
 use http::BodyParser;
+use http::QueryParser;
 use http::SendJson;
+use leds::LedOverride;
 use leds::Leds;
-use leds::INITIAL_BRIGHTNESS;
+use liveness::Liveness;
+use logs::LogConfig;
+use logs::Remote;
+use mdns::Mdns;
+use network::Ipv4Config;
+use network::NetworkConfig;
+use ota::ImageInfo;
+use ota::Updater;
+use palette::PaletteSettings;
+use schedule::Schedule;
+use stats::DailyStatsHistory;
+use storage::Storage;
+use supervisor::Outcome;
+use supervisor::Policy;
+use supervisor::RestartReason;
+use supervisor::Supervised;
+use timezone::Timezone;
+use update::UpdateChecker;
+use update::UpdateConfig;
+use utils::device_id;
 use utils::sleep_ms;
 use wifi::WifiConnectFix;
 
+mod alerts;
+mod animation;
+mod bands;
 mod board;
+mod brightness;
 mod clock;
+mod color;
+mod crash;
+mod diagnostics;
+mod display;
 mod fan;
+mod filter;
+mod gamma;
 mod http;
+mod humidity;
 mod leds;
+mod liveness;
+mod logger;
 mod logging;
+mod logs;
+mod mdns;
+mod network;
+mod ota;
+mod palette;
 mod scd41;
+mod schedule;
+mod signature;
+mod solar;
+mod stats;
+mod storage;
+mod supervisor;
+mod timezone;
+mod update;
 mod utils;
+mod watchdog;
 mod wifi;
 
-fn httpd(state: Arc<RwLock<State>>, leds: Arc<RwLock<Leds>>) -> Result<EspHttpServer<'static>> {
+#[allow(clippy::too_many_arguments)]
+fn httpd(
+    state: Arc<RwLock<State>>,
+    leds: Arc<RwLock<Leds<LedDriver>>>,
+    clock: Arc<Mutex<Clock>>,
+    alerts: Arc<Mutex<AlertEngine>>,
+    storage: Arc<Mutex<Storage>>,
+    ota: Arc<Updater>,
+    update_checker: Arc<Mutex<UpdateChecker>>,
+    diagnostics: Arc<Mutex<Diagnostics>>,
+    liveness: Arc<Mutex<Liveness>>,
+    network: Arc<Mutex<NetworkConfig>>,
+    mdns: Option<Arc<Mutex<Mdns>>>,
+) -> Result<EspHttpServer<'static>> {
     let mut server = EspHttpServer::new(&Default::default())?;
 
+    server.fn_handler("/health", Method::Get, {
+        let clock = clock.clone();
+        let diagnostics = diagnostics.clone();
+        move |req| {
+            let subsystems = subsystem_health(&diagnostics, &clock);
+            let degraded = subsystems
+                .values()
+                .any(|report| report.health == Health::Degraded);
+            let health: BTreeMap<_, _> = subsystems
+                .iter()
+                .map(|(subsystem, report)| (subsystem, report.health))
+                .collect();
+            req.send_json_status(
+                if degraded { 503 } else { 200 },
+                &serde_json::json!({
+                    "status": if degraded { Health::Degraded } else { Health::Ok },
+                    "version": update::CURRENT_VERSION,
+                    "uptime_secs": diagnostics::uptime_secs(),
+                    "subsystems": health,
+                }),
+            )
+        }
+    })?;
+
+    server.fn_handler("/diagnostics", Method::Get, {
+        let clock = clock.clone();
+        let diagnostics = diagnostics.clone();
+        move |req| {
+            let firmware = ImageInfo::running();
+            let last_restart = diagnostics.lock().unwrap().last_restart().cloned();
+            req.send_json(&serde_json::json!({
+                "firmware": {
+                    "version": update::CURRENT_VERSION,
+                    "build": firmware.elf_sha256,
+                    "built": firmware.built,
+                    "idf_version": firmware.idf_version,
+                },
+                "uptime_secs": diagnostics::uptime_secs(),
+                "reset_reason": diagnostics::reset_reason(),
+                "last_restart": last_restart,
+                "heap": diagnostics::heap(),
+                "tasks": diagnostics::task_stacks(),
+                "loops": liveness.lock().unwrap().report(Instant::now()),
+                "wifi": wifi::status(),
+                "clock": clock.lock().unwrap().status(),
+                "subsystems": subsystem_health(&diagnostics, &clock),
+            }))
+        }
+    })?;
+
+    server.fn_handler("/diagnostics/last-crash", Method::Get, move |req| {
+        let last_crash = diagnostics.lock().unwrap().last_crash().cloned();
+        req.send_json(&last_crash)
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/logs", Method::Get, |req| {
+        let since = match req
+            .query("since")
+            .map(|since| since.parse::<u64>())
+            .transpose()
+        {
+            Ok(since) => since,
+            Err(e) => {
+                req.send_bad_request(&format!("Invalid since: {}", e))?;
+                return Ok(());
+            }
+        };
+        req.send_json(&logger::lines(since))?;
+        Ok(())
+    })?;
+
+    server.fn_handler("/logs/config", Method::Get, |req| {
+        req.send_json(&serde_json::json!({
+            "config": logger::config(),
+            "remote": logger::remote_status(),
+        }))
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/logs/config", Method::Put, {
+        let storage = storage.clone();
+        move |mut req| {
+            let config: LogConfig = req.parse_body()?;
+            if let Err(e) = config.validate() {
+                req.send_bad_request(&e)?;
+                return Ok(());
+            }
+            storage.lock().unwrap().store(LOGS_KEY, &config)?;
+            info!("Log config set to {:?}", config);
+            logger::configure(config)?;
+
+            req.into_ok_response()?;
+            Ok(())
+        }
+    })?;
+
+    server.fn_handler("/network", Method::Get, {
+        let network = network.clone();
+        move |req| {
+            let config = network.lock().unwrap().clone();
+            req.send_json(&serde_json::json!({
+                "hostname": config.hostname(&device_id()),
+                "config": config,
+                "interface": wifi::status(),
+            }))
+        }
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/network", Method::Put, {
+        let storage = storage.clone();
+        move |mut req| {
+            let config: NetworkConfig = req.parse_body()?;
+            if let Err(e) = config.validate() {
+                req.send_bad_request(&e)?;
+                return Ok(());
+            }
+            storage.lock().unwrap().store(NETWORK_KEY, &config)?;
+            info!("Network config set to {:?}", config);
+            let hostname = config.hostname(&device_id());
+            let previous = std::mem::replace(&mut *network.lock().unwrap(), config.clone());
+            if previous.ipv4 != config.ipv4 || previous.ipv6 != config.ipv6 {
+                warn!("The IP config applies after a restart");
+            }
+            // used by DHCP renewals from now on
+            wifi::set_hostname(&hostname)?;
+            logger::set_hostname(&hostname);
+            if let Some(mdns) = &mdns {
+                mdns.lock().unwrap().set_hostname(&hostname)?;
+            }
+
+            req.into_ok_response()?;
+            Ok(())
+        }
+    })?;
+
     server.fn_handler("/data", Method::Get, {
         let state = state.clone();
         move |req| {
@@ -43,71 +261,654 @@ fn httpd(state: Arc<RwLock<State>>, leds: Arc<RwLock<Leds>>) -> Result<EspHttpSe
         }
     })?;
 
-    server.fn_handler::<anyhow::Error, _>("/brightness", Method::Put, move |mut req| {
-        let brightness: u8 = req.parse_body().unwrap();
-        state.write().unwrap().settings.brightness = brightness;
-        info!("Brightness set to {}", brightness);
-        let mut leds = leds.write().unwrap();
-        leds.set_brightness(brightness).flush().unwrap();
+    server.fn_handler("/clock", Method::Get, {
+        let clock = clock.clone();
+        move |req| {
+            let status = clock.lock().unwrap().status();
+            req.send_json(&status)
+        }
+    })?;
+
+    server.fn_handler("/settings", Method::Get, {
+        let state = state.clone();
+        let clock = clock.clone();
+        move |req| {
+            let settings = &state.read().unwrap().settings;
+            let clock = clock.lock().unwrap();
+            let zone = clock.timezone();
+            let offset = zone.offset(time::OffsetDateTime::now_utc());
+            req.send_json(&serde_json::json!({
+                "brightness": settings.brightness,
+                "palette": settings.palette,
+                "display": settings.display,
+                "schedule": settings.schedule,
+                "timezone": {
+                    "name": zone,
+                    "utc_offset": timezone::format_offset(offset),
+                    "utc_offset_secs": offset.whole_seconds(),
+                },
+            }))
+        }
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/timezone", Method::Put, {
+        let state = state.clone();
+        let leds = leds.clone();
+        let clock = clock.clone();
+        let storage = storage.clone();
+        move |mut req| {
+            let name: String = req.parse_body()?;
+            let timezone: Timezone = match name.parse() {
+                Ok(timezone) => timezone,
+                Err(e) => {
+                    req.send_bad_request(&e)?;
+                    return Ok(());
+                }
+            };
+            storage.lock().unwrap().store(TIMEZONE_KEY, &timezone)?;
+            clock.lock().unwrap().set_timezone(timezone);
+            apply_schedule(&leds, &clock, &state);
+
+            req.into_ok_response()?;
+            Ok(())
+        }
+    })?;
+
+    server.fn_handler("/stats/daily", Method::Get, {
+        let state = state.clone();
+        move |req| {
+            let stats = &state.read().unwrap().daily_stats;
+            req.send_json(stats)
+        }
+    })?;
+
+    server.fn_handler("/alerts", Method::Get, {
+        let alerts = alerts.clone();
+        move |req| {
+            let alerts = alerts.lock().unwrap();
+            req.send_json(&serde_json::json!({
+                "active": alerts.active(Instant::now()),
+                "config": alerts.config(),
+            }))
+        }
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/alerts", Method::Put, {
+        let alerts = alerts.clone();
+        let clock = clock.clone();
+        let storage = storage.clone();
+        move |mut req| {
+            let config: AlertConfig = req.parse_body()?;
+            if let Err(e) = config.validate() {
+                req.send_bad_request(&e)?;
+                return Ok(());
+            }
+            storage.lock().unwrap().store(ALERTS_KEY, &config)?;
+            info!("Alert config updated: {:?}", config);
+            let (resolved, webhooks) = {
+                let mut alerts = alerts.lock().unwrap();
+                let webhooks = alerts.config().webhooks.clone();
+                (alerts.set_config(config), webhooks)
+            };
+            // the receivers of the removed alerts still have them open
+            let timestamp = clock.lock().unwrap().get_unix_timestamp();
+            for notification in &resolved {
+                if let Err(e) =
+                    logging::send_webhooks(&webhooks, notification, &device_id(), timestamp)
+                {
+                    error!("Error resolving alert: {}", e);
+                }
+            }
+
+            req.into_ok_response()?;
+            Ok(())
+        }
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/alerts/test", Method::Post, move |req| {
+        let webhooks = alerts.lock().unwrap().config().webhooks.clone();
+        let notification = alerts::Notification {
+            rule: "test".into(),
+            status: alerts::Status::Firing,
+            value: None,
+            threshold: None,
+        };
+        logging::send_webhooks(&webhooks, &notification, &device_id(), None)?;
 
-        req.into_ok_response().unwrap();
+        req.into_ok_response()?;
         Ok(())
     })?;
 
-    server.fn_handler::<anyhow::Error, _>("/restart", Method::Post, |_req| {
-        // panic will cause a restart of the device
-        panic!("User requested a restart!")
+    server.fn_handler("/palette", Method::Get, {
+        let state = state.clone();
+        move |req| {
+            let settings = &state.read().unwrap().settings.palette;
+            req.send_json(&serde_json::json!({
+                "name": settings.name,
+                "custom": settings.custom,
+                "colors": settings.palette(),
+            }))
+        }
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/palette", Method::Put, {
+        let state = state.clone();
+        let leds = leds.clone();
+        let storage = storage.clone();
+        move |mut req| {
+            let settings: PaletteSettings = req.parse_body()?;
+            if let Err(e) = settings.validate() {
+                req.send_bad_request(&e)?;
+                return Ok(());
+            }
+            storage.lock().unwrap().store(PALETTE_KEY, &settings)?;
+            info!("Palette set to {:?}", settings.name);
+            leds.write().unwrap().set_palette(settings.palette());
+            state.write().unwrap().settings.palette = settings;
+
+            req.into_ok_response()?;
+            Ok(())
+        }
+    })?;
+
+    server.fn_handler("/display", Method::Get, {
+        let state = state.clone();
+        let leds = leds.clone();
+        move |req| {
+            let settings = state.read().unwrap().settings.display;
+            req.send_json(&serde_json::json!({
+                "mode": settings.mode,
+                "night_mode": settings.night_mode,
+                "active": leds.read().unwrap().get_display_mode(),
+            }))
+        }
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/display", Method::Put, {
+        let state = state.clone();
+        let leds = leds.clone();
+        let clock = clock.clone();
+        let storage = storage.clone();
+        move |mut req| {
+            let settings: DisplaySettings = req.parse_body()?;
+            storage.lock().unwrap().store(DISPLAY_KEY, &settings)?;
+            info!("Display settings set to {:?}", settings);
+            state.write().unwrap().settings.display = settings;
+            apply_schedule(&leds, &clock, &state);
+
+            req.into_ok_response()?;
+            Ok(())
+        }
+    })?;
+
+    server.fn_handler("/schedule", Method::Get, {
+        let state = state.clone();
+        let clock = clock.clone();
+        move |req| {
+            let schedule = &state.read().unwrap().settings.schedule;
+            let datetime = clock.lock().unwrap().get_datetime();
+            let daylight = datetime
+                .zip(schedule.solar)
+                .map(|(datetime, solar)| solar.daylight(datetime));
+            req.send_json(&serde_json::json!({
+                "windows": schedule.windows,
+                "holidays": schedule.holidays,
+                "solar": schedule.solar,
+                "active": datetime.and_then(|datetime| schedule.evaluate(datetime)),
+                "daylight": daylight,
+            }))
+        }
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/schedule", Method::Put, {
+        let state = state.clone();
+        let leds = leds.clone();
+        let clock = clock.clone();
+        let storage = storage.clone();
+        move |mut req| {
+            let schedule: Schedule = req.parse_body()?;
+            if let Err(e) = schedule.validate() {
+                req.send_bad_request(&e)?;
+                return Ok(());
+            }
+            storage.lock().unwrap().store(SCHEDULE_KEY, &schedule)?;
+            info!("Schedule set to {:?}", schedule);
+            state.write().unwrap().settings.schedule = schedule;
+            apply_schedule(&leds, &clock, &state);
+
+            req.into_ok_response()?;
+            Ok(())
+        }
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/leds", Method::Put, {
+        let leds = leds.clone();
+        move |mut req| {
+            let led_override: LedOverride = req.parse_body()?;
+            if let Err(e) = led_override.validate() {
+                req.send_bad_request(&e)?;
+                return Ok(());
+            }
+            info!("LEDs overridden: {:?}", led_override);
+            leds.write()
+                .unwrap()
+                .override_colors(led_override)
+                .flush()
+                .map_err(|e| anyhow!("Error updating LEDs: {:?}", e))?;
+
+            req.into_ok_response()?;
+            Ok(())
+        }
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/identify", Method::Post, {
+        let state = state.clone();
+        let leds = leds.clone();
+        let clock = clock.clone();
+        move |req| {
+            info!("Identifying the device");
+            leds.write()
+                .unwrap()
+                .set_indication_for(Indication::Identify, IDENTIFY_DURATION);
+            apply_schedule(&leds, &clock, &state);
+            leds.write()
+                .unwrap()
+                .flush()
+                .map_err(|e| anyhow!("Error updating LEDs: {:?}", e))?;
+
+            req.into_ok_response()?;
+            Ok(())
+        }
+    })?;
+
+    server.fn_handler("/brightness", Method::Get, {
+        let state = state.clone();
+        move |req| {
+            let state = state.read().unwrap();
+            let now = Instant::now();
+            let manual = state.brightness.manual(now).map(|manual| {
+                serde_json::json!({
+                    "brightness": manual.brightness,
+                    "remaining_secs": manual.remaining(now).map(|remaining| remaining.as_secs()),
+                })
+            });
+            req.send_json(&serde_json::json!({
+                "effective": state.brightness.effective(),
+                "manual": manual,
+                "preferences": state.settings.brightness,
+            }))
+        }
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/brightness", Method::Put, {
+        let state = state.clone();
+        let leds = leds.clone();
+        let clock = clock.clone();
+        move |mut req| {
+            let manual: ManualBrightness = req.parse_body()?;
+            if let Err(e) = manual.validate() {
+                req.send_bad_request(&e)?;
+                return Ok(());
+            }
+            info!("Brightness set to {:?}", manual);
+            state
+                .write()
+                .unwrap()
+                .brightness
+                .set_manual(manual, Instant::now());
+            apply_schedule(&leds, &clock, &state);
+
+            req.into_ok_response()?;
+            Ok(())
+        }
     })?;
+
+    server.fn_handler::<anyhow::Error, _>("/brightness", Method::Delete, {
+        let state = state.clone();
+        let leds = leds.clone();
+        let clock = clock.clone();
+        move |req| {
+            info!("Manual brightness cleared");
+            state.write().unwrap().brightness.clear_manual();
+            apply_schedule(&leds, &clock, &state);
+
+            req.into_ok_response()?;
+            Ok(())
+        }
+    })?;
+
+    server.fn_handler("/ota", Method::Get, {
+        let ota = ota.clone();
+        let update_checker = update_checker.clone();
+        move |req| {
+            req.send_json(&serde_json::json!({
+                "progress": ota.progress(),
+                "running": ImageInfo::running(),
+                "pending_verify": ota.pending_verify(),
+                "check": update_checker.lock().unwrap().status(Instant::now()),
+                "config": update_checker.lock().unwrap().config(),
+            }))
+        }
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/ota/config", Method::Put, {
+        let update_checker = update_checker.clone();
+        let storage = storage.clone();
+        move |mut req| {
+            let config: UpdateConfig = req.parse_body()?;
+            if let Err(e) = config.validate() {
+                req.send_bad_request(&e)?;
+                return Ok(());
+            }
+            storage.lock().unwrap().store(OTA_KEY, &config)?;
+            info!("Update config set to {:?}", config);
+            update_checker.lock().unwrap().set_config(config);
+
+            req.into_ok_response()?;
+            Ok(())
+        }
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/ota/check", Method::Post, move |req| {
+        info!("Update check requested");
+        update_checker.lock().unwrap().request_check();
+
+        req.into_ok_response()?;
+        Ok(())
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/ota", Method::Post, {
+        let leds = leds.clone();
+        let storage = storage.clone();
+        move |mut req| {
+            let Some(total) = req
+                .header("Content-Length")
+                .and_then(|length| length.parse::<usize>().ok())
+            else {
+                req.send_bad_request(&"Content-Length is required")?;
+                return Ok(());
+            };
+            info!("Firmware upload started, {} bytes", total);
+
+            set_indication(&leds, Indication::OtaInProgress, true);
+            let result = ota.update(&mut req, total, None);
+            set_indication(&leds, Indication::OtaInProgress, false);
+            // unsigned, tampered, foreign or truncated images
+            let image = match result {
+                Ok(image) => image,
+                Err(e) => {
+                    req.send_bad_request(&e)?;
+                    return Ok(());
+                }
+            };
+
+            req.send_json(&image)?;
+            // let the response go out first
+            let storage = storage.clone();
+            thread::spawn(move || {
+                sleep_ms(RESTART_DELAY_MS);
+                restart(
+                    &storage,
+                    RestartReason::Update {
+                        version: image.version,
+                    },
+                )
+            });
+            Ok(())
+        }
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>("/restart", Method::Post, {
+        let storage = storage.clone();
+        move |req| {
+            req.into_ok_response()?;
+            // let the response go out first
+            thread::spawn(move || {
+                sleep_ms(RESTART_DELAY_MS);
+                restart(&storage, RestartReason::UserRequest)
+            });
+            Ok(())
+        }
+    })?;
+
+    server.fn_handler::<anyhow::Error, _>(
+        "/brightness/preferences",
+        Method::Put,
+        move |mut req| {
+            let preferences: BrightnessPreferences = req.parse_body()?;
+            storage
+                .lock()
+                .unwrap()
+                .store(BRIGHTNESS_KEY, &preferences)?;
+            info!("Brightness preferences set to {:?}", preferences);
+            state.write().unwrap().settings.brightness = preferences;
+            apply_schedule(&leds, &clock, &state);
+
+            req.into_ok_response()?;
+            Ok(())
+        },
+    )?;
     Ok(server)
 }
 
 #[derive(Serialize, Default)]
 struct MeasuredData {
     co2: u16,
+    temperature: Option<f32>,
+    humidity: Option<f32>,
+    /// humidity corrected
     pm25: u16,
+    pm25_raw: u16,
+    pm25_stats: Option<Summary>,
+    /// Unix time of the measurement, `None` until the clock has been synced
     timestamp: Option<i64>,
+    /// Whether `timestamp` can be trusted
+    clock: SyncState,
 }
 
+// The SCD41 is retried every minute at first, up to every 30 minutes, the device keeps showing
+// PM2.5 without it
+const CO2_SENSOR_POLICY: Policy = Policy::Degrade {
+    degrade_after: 3,
+    initial_backoff: Duration::from_secs(60),
+    max_backoff: Duration::from_secs(30 * 60),
+};
+// Iteration budgets of the liveness monitor, the device restarts when a loop takes longer
+const MAIN_LOOP_BUDGET: Duration = Duration::from_secs(3 * 60);
+const CLOCK_LOOP_BUDGET: Duration = Duration::from_secs(2 * 60);
+// Includes firmware downloads
+const UPDATE_LOOP_BUDGET: Duration = Duration::from_secs(15 * 60);
+const LIVENESS_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// Pause between two measurements
+const MEASUREMENT_PAUSE_MS: u64 = 50_000;
+// How long the fan runs before each measurement
+const FAN_DURATION: Duration = Duration::from_secs(10);
+// Delay between two PM2.5 readings while the fan is running
+const PM25_SAMPLE_INTERVAL_MS: u64 = 1_000;
+// White balance of the LEDs, e.g. "1.0,0.8,0.7" channel gains or "4000K", see ColorPipeline
+const LED_WHITE_BALANCE: Option<&str> = option_env!("LED_WHITE_BALANCE");
+// How long the LEDs flash after `POST /identify`
+const IDENTIFY_DURATION: Duration = Duration::from_secs(30);
+// Frame interval of the LED animations
+const ANIMATION_INTERVAL: Duration = Duration::from_millis(40);
+// How often the schedule and the brightness arbitration are evaluated
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(1);
+// How often the rollback deadline of a new firmware is checked
+const OTA_VERIFY_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+// Time for the `POST /ota` and `POST /restart` responses to be sent before the restart
+const RESTART_DELAY_MS: u64 = 1_000;
+// How often the update thread looks whether a manifest check is due
+const UPDATE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+// TLS needs a large stack
+const UPDATE_STACK_SIZE: usize = 12 * 1024;
+// How often the clock sync state is checked
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const CLOCK_STACK_SIZE: usize = 6 * 1024;
+// Persist the running daily statistics every N samples (~an hour), finished days are persisted
+// right away. The history is a few KB of the NVS partition shared with all the settings
+const DAILY_STATS_PERSIST_EVERY: u32 = 60;
+const DAILY_STATS_KEY: &str = "daily_stats";
+const ALERTS_KEY: &str = "alerts";
+const PALETTE_KEY: &str = "palette";
+const DISPLAY_KEY: &str = "display";
+const SCHEDULE_KEY: &str = "schedule";
+const TIMEZONE_KEY: &str = "timezone";
+const BRIGHTNESS_KEY: &str = "brightness";
+const OTA_KEY: &str = "ota";
+const RESTART_KEY: &str = "restart";
+const LAST_CRASH_KEY: &str = "last_crash";
+const LOGS_KEY: &str = "logs";
+const NETWORK_KEY: &str = "network";
+// IANA name or POSIX TZ string, used until one is set with `PUT /timezone`
+const TIMEZONE: Option<&str> = option_env!("TIMEZONE");
+// Webhook used with the default alert config
+const ALERT_WEBHOOK_URL: Option<&str> = option_env!("ALERT_WEBHOOK_URL");
+// e.g. "kappa:0.4" or "linear:0.8,-1.5", see HumidityCorrection
+const PM25_HUMIDITY_CORRECTION: Option<&str> = option_env!("PM25_HUMIDITY_CORRECTION");
+// e.g. "udp://192.168.1.10:514", see Remote
+const SYSLOG_URL: Option<&str> = option_env!("SYSLOG_URL");
+// Name on the local network, "vindriktning-<mac>" by default
+const DEVICE_HOSTNAME: Option<&str> = option_env!("DEVICE_HOSTNAME");
+// "dhcp" or e.g. "192.168.10.20/24,192.168.10.1,192.168.10.1", see Ipv4Config
+const IPV4: Option<&str> = option_env!("IPV4");
+// "true" enables IPv6 with SLAAC
+const IPV6_SLAAC: Option<&str> = option_env!("IPV6_SLAAC");
+
 #[derive(Serialize)]
 struct Settings {
-    brightness: u8,
+    brightness: BrightnessPreferences,
+    palette: PaletteSettings,
+    display: DisplaySettings,
+    schedule: Schedule,
 }
 
 #[derive(Serialize)]
 struct State {
     measured_data: MeasuredData,
     settings: Settings,
+    daily_stats: DailyStatsHistory,
+    brightness: BrightnessArbiter,
 }
 
-fn set_brightness(leds: &Arc<RwLock<Leds>>, clock: &Arc<Mutex<Clock>>) {
-    let datetime = clock.lock().unwrap().get_datetime().unwrap();
+fn set_indication(leds: &Arc<RwLock<Leds<LedDriver>>>, indication: Indication, active: bool) {
+    if let Err(e) = leds
+        .write()
+        .unwrap()
+        .set_indication(indication, active)
+        .flush()
+    {
+        error!("Error updating LEDs: {:?}", e);
+    }
+}
 
-    let new_brightness = if datetime.hour() >= 22 || datetime.hour() < 6 {
-        1
+/// Restarts the device on purpose, the reason is reported by `GET /diagnostics` after the boot
+fn restart(storage: &Mutex<Storage>, reason: RestartReason) -> ! {
+    warn!("Restarting, {}", reason);
+    if let Err(e) = storage.lock().unwrap().store(RESTART_KEY, &reason) {
+        error!("Failed to record the restart reason: {}", e);
+    }
+    unsafe { esp_idf_svc::sys::esp_restart() }
+}
+
+/// Counters of all subsystems, Wi-Fi and the clock are judged by their current state
+fn subsystem_health(
+    diagnostics: &Mutex<Diagnostics>,
+    clock: &Mutex<Clock>,
+) -> BTreeMap<Subsystem, diagnostics::SubsystemReport> {
+    let wifi = if wifi::status().connected {
+        Health::Ok
     } else {
-        INITIAL_BRIGHTNESS
+        Health::Degraded
+    };
+    let clock = {
+        let clock = clock.lock().unwrap();
+        match clock.state() {
+            SyncState::Synced => Health::Ok,
+            SyncState::NeverSynced if clock.is_starting() => Health::Unknown,
+            SyncState::NeverSynced | SyncState::Stale => Health::Degraded,
+        }
     };
+    diagnostics
+        .lock()
+        .unwrap()
+        .report(&[(Subsystem::Wifi, wifi), (Subsystem::Clock, clock)])
+}
+
+/// Sets the display mode of the schedule active now and the brightness picked by the
+/// `BrightnessArbiter`, the night display mode is used during night and off windows and after
+/// sunset.
+/// Until the clock has been synced the schedule is skipped and the day brightness applies.
+fn apply_schedule(
+    leds: &Arc<RwLock<Leds<LedDriver>>>,
+    clock: &Arc<Mutex<Clock>>,
+    state: &Arc<RwLock<State>>,
+) {
+    let datetime = clock.lock().unwrap().get_datetime();
+    let (alert, identify) = {
+        let leds = leds.read().unwrap();
+        (
+            leds.has_indication(Indication::Alert),
+            leds.has_indication(Indication::Identify),
+        )
+    };
+
+    let (display_mode, effective) = {
+        let mut state = state.write().unwrap();
+        let State {
+            settings,
+            brightness,
+            ..
+        } = &mut *state;
+        let preferences = settings.brightness;
+        let scheduled = datetime.and_then(|datetime| {
+            settings
+                .schedule
+                .brightness(datetime, preferences.day, preferences.night)
+        });
+        let night = scheduled.is_some_and(|scheduled| scheduled.night);
+        let effective =
+            brightness.resolve(&preferences, alert, identify, scheduled, Instant::now());
+        (settings.display.mode(night), effective)
+    };
+
+    if display_mode != leds.read().unwrap().get_display_mode() {
+        info!("Setting display mode to {:?}", display_mode);
+        leds.write().unwrap().set_display_mode(display_mode);
+    }
 
-    if new_brightness != leds.read().unwrap().get_brightness() {
-        info!("Setting brightness to {}", new_brightness);
+    if effective.brightness != leds.read().unwrap().get_brightness() {
+        info!(
+            "Setting brightness to {} ({:?})",
+            effective.brightness, effective.source
+        );
         leds.write()
             .unwrap()
-            .set_brightness(new_brightness)
-            .flush()
-            .unwrap();
+            .set_brightness(effective.brightness)
+            .show();
     }
 }
 
-fn main() -> Result<()> {
+fn main() {
     // Temporary. Will disappear once ESP-IDF 4.4 is released, but for now it is necessary to call this function once,
     // or else some patches to the runtime implemented by esp-idf-sys might not link properly.
     esp_idf_svc::sys::link_patches();
 
-    // Bind the log crate to the ESP Logging facilities
-    esp_idf_svc::log::EspLogger::initialize_default();
+    // Bind the log crate to the ESP Logging facilities, the log buffer and the remote syslog
+    logger::initialize();
 
-    let peripherals = Peripherals::take().unwrap();
+    // Keeps panics for `GET /diagnostics/last-crash` after the restart
+    crash::install_panic_hook();
+
+    if let Err(e) = run() {
+        // recorded like a panic, with the error chain
+        panic!("{:?}", e);
+    }
+}
+
+fn run() -> Result<()> {
+    let peripherals = Peripherals::take()?;
     let sysloop = EspSystemEventLoop::take()?;
+    let nvs = EspDefaultNvsPartition::take()?;
     let Peripherals {
         modem,
         pins,
@@ -117,93 +918,670 @@ fn main() -> Result<()> {
         ..
     } = peripherals;
 
-    // Create board
+    // Create board, runs without the peripherals that fail to set up
     let mut board = Board::new(pins, i2c1, uart1, rmt);
-    board.init();
+
+    let storage = Arc::new(Mutex::new(Storage::new(nvs)?));
+    let log_config = storage
+        .lock()
+        .unwrap()
+        .load::<LogConfig>(LOGS_KEY)
+        .unwrap_or_else(|e| {
+            error!("Failed to load log config: {}", e);
+            None
+        })
+        .unwrap_or_else(|| LogConfig {
+            remote: SYSLOG_URL.and_then(|value| {
+                value
+                    .parse::<Remote>()
+                    .map_err(|e| error!("Invalid SYSLOG_URL {:?}: {}", value, e))
+                    .ok()
+            }),
+            ..Default::default()
+        });
+    if let Err(e) = logger::configure(log_config) {
+        error!("Failed to configure logging: {}", e);
+    }
+    let stored_restart = storage
+        .lock()
+        .unwrap()
+        .take::<RestartReason>(RESTART_KEY)
+        .unwrap_or_else(|e| {
+            error!("Failed to load the restart reason: {}", e);
+            None
+        });
+    // A stall isn't in the storage, the stalled loop may have held it
+    let last_restart = crash::take_stall().or(stored_restart);
+    if let Some(reason) = &last_restart {
+        info!("Restarted on purpose, {}", reason);
+    }
+    // A new crash replaces the stored one
+    let last_crash = match crash::collect() {
+        Some(crash) => {
+            warn!("The previous run crashed: {:?}", crash);
+            if let Err(e) = storage.lock().unwrap().store(LAST_CRASH_KEY, &crash) {
+                error!("Failed to persist the crash report: {}", e);
+            }
+            Some(crash)
+        }
+        None => storage
+            .lock()
+            .unwrap()
+            .load::<CrashReport>(LAST_CRASH_KEY)
+            .unwrap_or_else(|e| {
+                error!("Failed to load the crash report: {}", e);
+                None
+            }),
+    };
+
+    // Runs without the CO2 sensor while it fails, the SCD41 needs a measurement interval after
+    // the start so the first measurement comes after the fan run
+    let mut co2_sensor = board
+        .scd41
+        .map(|scd41| Supervised::new("CO2 sensor", scd41, CO2_SENSOR_POLICY));
+    if let Some(Outcome::Restart(reason)) = co2_sensor
+        .as_mut()
+        .map(|co2_sensor| co2_sensor.start(Instant::now()))
+    {
+        restart(&storage, reason);
+    }
+
+    // Restarts with the stalled loop recorded, before the task watchdog would reset the device
+    // without a reason if the loop stopped feeding it too
+    let liveness = Arc::new(Mutex::new(Liveness::default()));
+    let liveness_timer = EspTaskTimerService::new()?.timer({
+        let liveness = liveness.clone();
+        move || {
+            let stall = liveness.lock().unwrap().stalled(Instant::now());
+            if let Some(stall) = stall {
+                // in RTC memory, the storage may be locked by the stalled loop
+                crash::record_stall(&stall);
+                warn!("Restarting, {} loop stalled", stall.name);
+                unsafe { esp_idf_svc::sys::esp_restart() }
+            }
+        }
+    })?;
+    liveness_timer.every(LIVENESS_CHECK_INTERVAL)?;
+
+    // A new firmware is rolled back unless confirmed in time, checked by a timer so it also
+    // happens when the firmware gets stuck, e.g. connecting to Wi-Fi
+    let ota = Arc::new(Updater::new()?);
+    let ota_timer = EspTaskTimerService::new()?.timer({
+        let ota = ota.clone();
+        move || ota.check_verify_deadline()
+    })?;
+    ota_timer.every(OTA_VERIFY_CHECK_INTERVAL)?;
 
     // Init color
+    let palette = storage
+        .lock()
+        .unwrap()
+        .load::<PaletteSettings>(PALETTE_KEY)
+        .unwrap_or_else(|e| {
+            error!("Failed to load palette: {}", e);
+            None
+        })
+        .unwrap_or_default();
+    board.leds.set_palette(palette.palette());
+    let display = storage
+        .lock()
+        .unwrap()
+        .load::<DisplaySettings>(DISPLAY_KEY)
+        .unwrap_or_else(|e| {
+            error!("Failed to load display settings: {}", e);
+            None
+        })
+        .unwrap_or_default();
+    let schedule = storage
+        .lock()
+        .unwrap()
+        .load::<Schedule>(SCHEDULE_KEY)
+        .unwrap_or_else(|e| {
+            error!("Failed to load schedule: {}", e);
+            None
+        })
+        .unwrap_or_default();
+    let brightness = storage
+        .lock()
+        .unwrap()
+        .load::<BrightnessPreferences>(BRIGHTNESS_KEY)
+        .unwrap_or_else(|e| {
+            error!("Failed to load brightness preferences: {}", e);
+            None
+        })
+        .unwrap_or_default();
+    if let Some(white_balance) = LED_WHITE_BALANCE {
+        match white_balance.parse::<ColorPipeline>() {
+            Ok(pipeline) => {
+                info!("LED color pipeline: {:?}", pipeline);
+                board.leds.set_pipeline(pipeline);
+            }
+            Err(e) => error!("Invalid LED white balance {:?}: {}", white_balance, e),
+        }
+    }
     board.leds.set_initial_color();
+    let leds = Arc::new(RwLock::new(board.leds));
+
+    // Run LED animations
+    let animation_timer = EspTaskTimerService::new()?.timer({
+        let leds = leds.clone();
+        let mut watched = false;
+        move || {
+            // the timer task runs all the timers, a blocked one stops the animation
+            if !watched {
+                watched = watchdog::subscribe()
+                    .map_err(|e| error!("Failed to watch the timer task: {}", e))
+                    .is_ok();
+            }
+            watchdog::feed();
+            if let Err(e) = leds.write().unwrap().tick() {
+                error!("Error rendering LED animation: {:?}", e);
+            }
+        }
+    })?;
+    animation_timer.every(ANIMATION_INTERVAL)?;
 
     // Setup wifi
-    let mut blocking_wifi = wifi::wifi(modem, sysloop.clone())?;
+    let diagnostics = Arc::new(Mutex::new(Diagnostics::new(last_restart, last_crash)));
+    if co2_sensor.is_none() {
+        diagnostics
+            .lock()
+            .unwrap()
+            .record_absent(Subsystem::Co2Sensor);
+    }
+    if board.pm1006.is_none() {
+        diagnostics
+            .lock()
+            .unwrap()
+            .record_absent(Subsystem::Pm25Sensor);
+    }
+    let network_config = storage
+        .lock()
+        .unwrap()
+        .load::<NetworkConfig>(NETWORK_KEY)
+        .unwrap_or_else(|e| {
+            error!("Failed to load network config: {}", e);
+            None
+        })
+        .map(NetworkConfig::sanitized)
+        .unwrap_or_else(|| NetworkConfig {
+            hostname: DEVICE_HOSTNAME
+                .filter(|value| {
+                    network::validate_hostname(value)
+                        .map_err(|e| {
+                            error!("Invalid DEVICE_HOSTNAME, using the default one: {}", e)
+                        })
+                        .is_ok()
+                })
+                .map(Into::into),
+            ipv4: IPV4
+                .map(|value| {
+                    value.parse::<Ipv4Config>().unwrap_or_else(|e| {
+                        error!("Invalid IPV4 {:?}, using DHCP: {}", value, e);
+                        Ipv4Config::Dhcp
+                    })
+                })
+                .unwrap_or_default(),
+            ipv6: IPV6_SLAAC
+                .map(|value| {
+                    value.parse::<bool>().unwrap_or_else(|e| {
+                        error!("Invalid IPV6_SLAAC {:?}, IPv6 disabled: {}", value, e);
+                        false
+                    })
+                })
+                .unwrap_or_default(),
+        });
+    let hostname = network_config.hostname(&device_id());
+    set_indication(&leds, Indication::Provisioning, true);
+    // before the first DHCP reply, the NTP server it offers isn't kept otherwise
+    clock::use_dhcp_server();
+    let mut blocking_wifi = wifi::wifi(modem, sysloop.clone(), &network_config, &hostname)?;
+    set_indication(&leds, Indication::Provisioning, false);
+    diagnostics.lock().unwrap().record_ok(Subsystem::Wifi);
+
+    // Found as <hostname>.local, runs without it when the responder fails
+    logger::set_hostname(&hostname);
+    let mdns = Mdns::start(&hostname)
+        .map(|mdns| Arc::new(Mutex::new(mdns)))
+        .map_err(|e| error!("Failed to start mDNS: {}", e))
+        .ok();
     // Try to reconnect if we get disconnected
     let _wifi_reconnect_sub = sysloop.subscribe::<WifiEvent, _>({
+        let leds = leds.clone();
+        let diagnostics = diagnostics.clone();
+        let storage = storage.clone();
+        let ipv6 = network_config.ipv6;
         move |parsed_event| {
             log::info!("Wifi event: {:?}", parsed_event);
             if let WifiEvent::StaDisconnected = parsed_event {
-                blocking_wifi.connect_with_retry().unwrap();
+                diagnostics
+                    .lock()
+                    .unwrap()
+                    .record_error(Subsystem::Wifi, "disconnected");
+                set_indication(&leds, Indication::Provisioning, true);
+                // only fails when the driver can't be restarted, retrying won't help
+                if let Err(e) = blocking_wifi.connect_with_retry(ipv6) {
+                    restart(
+                        &storage,
+                        RestartReason::Unrecoverable {
+                            subsystem: "wifi".into(),
+                            error: e.to_string(),
+                        },
+                    );
+                }
+                set_indication(&leds, Indication::Provisioning, false);
+                diagnostics.lock().unwrap().record_ok(Subsystem::Wifi);
             }
         }
     })?;
 
+    let network_config = Arc::new(Mutex::new(network_config));
+
     // Wait for data
-    board.leds.set_waiting_color();
+    leds.write().unwrap().set_waiting_color();
 
-    // NTP client
-    let clock = Arc::new(Mutex::new(Clock::new()));
-    clock.lock().unwrap().sync();
-    // Sync clock every minute
-    let clock_sync_timer = EspTaskTimerService::new()?.timer({
-        let clock = clock.clone();
-        move || {
-            clock.lock().unwrap().sync();
-        }
-    })?;
-    let one_hour_secs = 60 * 60;
-    clock_sync_timer.every(Duration::from_secs(one_hour_secs))?;
+    // NTP client, syncs the system time in the background
+    let timezone = storage
+        .lock()
+        .unwrap()
+        .load::<Timezone>(TIMEZONE_KEY)
+        .unwrap_or_else(|e| {
+            error!("Failed to load timezone: {}", e);
+            None
+        })
+        .unwrap_or_else(|| Timezone::from_config(TIMEZONE));
+    let clock = Arc::new(Mutex::new(Clock::new(timezone)?));
+    // Retry the sync until it succeeds and check the clock offset, in a thread as the offset
+    // measurement blocks
+    thread::Builder::new()
+        .name("clock".into())
+        .stack_size(CLOCK_STACK_SIZE)
+        .spawn({
+            let clock = clock.clone();
+            let diagnostics = diagnostics.clone();
+            let liveness = liveness.clone();
+            move || {
+                diagnostics::register_task("clock");
+                if let Err(e) = watchdog::subscribe() {
+                    error!("Failed to watch the clock task: {}", e);
+                }
+                liveness
+                    .lock()
+                    .unwrap()
+                    .register("clock", CLOCK_LOOP_BUDGET, Instant::now());
+                loop {
+                    thread::sleep(CLOCK_CHECK_INTERVAL);
+                    watchdog::feed();
+                    liveness.lock().unwrap().beat("clock", Instant::now());
+                    let server = {
+                        let mut clock = clock.lock().unwrap();
+                        clock.retry_sync();
+                        clock.probe_due()
+                    };
+                    if let Some(server) = server {
+                        let probe = clock::probe(&server);
+                        diagnostics.lock().unwrap().record(Subsystem::Clock, &probe);
+                        clock.lock().unwrap().set_probe(probe);
+                    }
+                }
+            }
+        })?;
+
+    let daily_stats = storage
+        .lock()
+        .unwrap()
+        .load::<DailyStatsHistory>(DAILY_STATS_KEY)
+        .unwrap_or_else(|e| {
+            error!("Failed to load daily stats: {}", e);
+            None
+        })
+        .unwrap_or_default();
+    let alert_config = storage
+        .lock()
+        .unwrap()
+        .load::<AlertConfig>(ALERTS_KEY)
+        .unwrap_or_else(|e| {
+            error!("Failed to load alert config: {}", e);
+            None
+        })
+        .unwrap_or_else(|| AlertConfig {
+            webhooks: ALERT_WEBHOOK_URL
+                .map(|url| alerts::Webhook {
+                    url: url.into(),
+                    template: None,
+                })
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        });
+    let alerts = Arc::new(Mutex::new(AlertEngine::new(alert_config)));
+    let update_config = storage
+        .lock()
+        .unwrap()
+        .load::<UpdateConfig>(OTA_KEY)
+        .unwrap_or_else(|e| {
+            error!("Failed to load update config: {}", e);
+            None
+        })
+        .unwrap_or_default();
+    info!("Update config: {:?}", update_config);
+    let update_checker = Arc::new(Mutex::new(UpdateChecker::new(update_config)));
 
     let state = State {
         measured_data: MeasuredData::default(),
         settings: Settings {
-            brightness: INITIAL_BRIGHTNESS,
+            brightness,
+            palette,
+            display,
+            schedule,
         },
+        daily_stats,
+        brightness: BrightnessArbiter::default(),
     };
     let state = Arc::new(RwLock::new(state));
-    let leds = Arc::new(RwLock::new(board.leds));
-    let _server = httpd(state.clone(), leds.clone())?;
+    let _server = httpd(
+        state.clone(),
+        leds.clone(),
+        clock.clone(),
+        alerts.clone(),
+        storage.clone(),
+        ota.clone(),
+        update_checker.clone(),
+        diagnostics.clone(),
+        liveness.clone(),
+        network_config,
+        mdns,
+    )?;
 
-    // Schedule timer for night mode
-    set_brightness(&leds, &clock);
-    let night_mode_timer = EspTaskTimerService::new()?.timer({
+    // Check the update manifest and install newer firmwares in the maintenance window
+    thread::Builder::new()
+        .name("update".into())
+        .stack_size(UPDATE_STACK_SIZE)
+        .spawn({
+            let ota = ota.clone();
+            let leds = leds.clone();
+            let clock = clock.clone();
+            let storage = storage.clone();
+            let liveness = liveness.clone();
+            move || {
+                diagnostics::register_task("update");
+                if let Err(e) = watchdog::subscribe() {
+                    error!("Failed to watch the update task: {}", e);
+                }
+                liveness
+                    .lock()
+                    .unwrap()
+                    .register("update", UPDATE_LOOP_BUDGET, Instant::now());
+                loop {
+                    thread::sleep(UPDATE_POLL_INTERVAL);
+                    watchdog::feed();
+                    liveness.lock().unwrap().beat("update", Instant::now());
+                    // a new firmware has to prove itself first
+                    if ota.pending_verify() {
+                        continue;
+                    }
+                    let datetime = clock.lock().unwrap().get_datetime();
+                    let check = {
+                        let mut update_checker = update_checker.lock().unwrap();
+                        let in_window = update_checker.in_window(datetime);
+                        if update_checker.due(in_window, Instant::now()) {
+                            update_checker
+                                .start_check()
+                                .map(|(url, forced)| (url, in_window || forced))
+                        } else {
+                            None
+                        }
+                    };
+                    let Some((url, may_install)) = check else {
+                        continue;
+                    };
+
+                    // downloads take minutes, the liveness budget still applies
+                    let result = watchdog::suspended(|| {
+                        update::check(
+                            update::CURRENT_VERSION,
+                            &device_id(),
+                            may_install,
+                            || ota::fetch_manifest(&url),
+                            |manifest| {
+                                set_indication(&leds, Indication::OtaInProgress, true);
+                                let result = ota.download(manifest);
+                                set_indication(&leds, Indication::OtaInProgress, false);
+                                result
+                            },
+                        )
+                    });
+                    let installed = match &result {
+                        update::CheckResult::Installed { version } => Some(version.to_string()),
+                        _ => None,
+                    };
+                    update_checker
+                        .lock()
+                        .unwrap()
+                        .set_result(result, Instant::now());
+                    if let Some(version) = installed {
+                        restart(&storage, RestartReason::Update { version });
+                    }
+                }
+            }
+        })?;
+
+    let humidity_correction = PM25_HUMIDITY_CORRECTION
+        .map(|value| {
+            value.parse::<HumidityCorrection>().unwrap_or_else(|e| {
+                error!("Invalid PM2.5 humidity correction {:?}: {}", value, e);
+                HumidityCorrection::None
+            })
+        })
+        .unwrap_or_default();
+    info!("PM2.5 humidity correction: {:?}", humidity_correction);
+
+    // Apply the schedule and the brightness changes, e.g. an expired identify
+    apply_schedule(&leds, &clock, &state);
+    let schedule_timer = EspTaskTimerService::new()?.timer({
         let leds = leds.clone();
         let clock = clock.clone();
+        let state = state.clone();
         move || {
-            set_brightness(&leds, &clock);
+            apply_schedule(&leds, &clock, &state);
         }
     })?;
-    night_mode_timer.every(Duration::from_secs(60))?;
+    schedule_timer.every(SCHEDULE_INTERVAL)?;
+
+    // A hung sensor read or network request resets the device via the task watchdog
+    if let Err(e) = watchdog::subscribe() {
+        error!("Failed to watch the main task: {}", e);
+    }
+    liveness
+        .lock()
+        .unwrap()
+        .register("main", MAIN_LOOP_BUDGET, Instant::now());
 
+    let mut samples_since_persist = 0;
     loop {
-        // Get fresh air
-        board.fan.enable().unwrap();
-        sleep_ms(10_000);
-        board.fan.disable().unwrap();
-
-        // Read data
-        let co2 = board.scd41.read_co2().unwrap_or_else(|e| {
-            error!("Error reading CO2: {:?}", e);
-            0
-        });
-        let pm25 = board.pm1006.read_pm25().unwrap_or_else(|e| {
-            error!("Error reading PM2.5: {:?}", e);
-            0
-        });
-        info!("CO2: {} ppm, PM2.5: {} ug/m3", co2, pm25);
+        liveness.lock().unwrap().beat("main", Instant::now());
+
+        // Get fresh air, sample PM2.5 meanwhile
+        if let Some(Err(e)) = board.fan.as_mut().map(|fan| fan.enable()) {
+            error!("Error starting the fan: {}", e);
+        }
+        let fan_started = Instant::now();
+        let mut pm25_samples: Vec<u16> = Vec::new();
+        while fan_started.elapsed() < FAN_DURATION {
+            if let Some(pm1006) = &mut board.pm1006 {
+                match pm1006.read_pm25() {
+                    Ok(pm25) => pm25_samples.push(pm25),
+                    Err(e) => warn!("Error reading PM2.5 sample: {:?}", e),
+                }
+            }
+            watchdog::feed();
+            sleep_ms(PM25_SAMPLE_INTERVAL_MS);
+        }
+        if let Some(Err(e)) = board.fan.as_mut().map(|fan| fan.disable()) {
+            error!("Error stopping the fan: {}", e);
+        }
+
+        // Read data, a sensor without its bus is reported absent instead
+        let climate = match &mut co2_sensor {
+            Some(co2_sensor) => {
+                let climate = match co2_sensor.run(Instant::now()) {
+                    Outcome::Ok(climate) => {
+                        diagnostics.lock().unwrap().record_ok(Subsystem::Co2Sensor);
+                        Some(climate)
+                    }
+                    Outcome::Unavailable => {
+                        // failed, backing off or settling after a restart of the sensor
+                        let error = co2_sensor.last_error().unwrap_or("unavailable");
+                        error!("No CO2 measurement: {}", error);
+                        diagnostics
+                            .lock()
+                            .unwrap()
+                            .record_error(Subsystem::Co2Sensor, error);
+                        None
+                    }
+                    Outcome::Restart(reason) => restart(&storage, reason),
+                };
+                diagnostics
+                    .lock()
+                    .unwrap()
+                    .record_state(Subsystem::Co2Sensor, co2_sensor.state());
+                climate
+            }
+            None => None,
+        };
+        watchdog::feed();
+        let co2 = climate.map(|m| m.co2).unwrap_or(0);
+        let pm25_stats = PM25_FILTER.apply(&pm25_samples);
+        let (pm25_raw, pm25) = match pm25_stats {
+            Some(stats) => {
+                info!("PM2.5 samples: {:?}", stats);
+                diagnostics.lock().unwrap().record_ok(Subsystem::Pm25Sensor);
+                let corrected = humidity_correction.apply(stats.value, climate.map(|m| m.humidity));
+                (stats.value.round() as u16, corrected.round() as u16)
+            }
+            None if board.pm1006.is_none() => (0, 0),
+            None => {
+                error!("Error reading PM2.5: no valid samples");
+                diagnostics
+                    .lock()
+                    .unwrap()
+                    .record_error(Subsystem::Pm25Sensor, "no valid samples");
+                (0, 0)
+            }
+        };
+        info!(
+            "CO2: {} ppm, PM2.5: {} ug/m3 (raw {} ug/m3), climate: {:?}",
+            co2, pm25, pm25_raw, climate
+        );
 
         // Store data
         state.write().unwrap().measured_data.co2 = co2;
+        state.write().unwrap().measured_data.temperature = climate.map(|m| m.temperature);
+        state.write().unwrap().measured_data.humidity = climate.map(|m| m.humidity);
         state.write().unwrap().measured_data.pm25 = pm25;
-        state.write().unwrap().measured_data.timestamp = clock.lock().unwrap().get_unix_timestamp();
+        state.write().unwrap().measured_data.pm25_raw = pm25_raw;
+        state.write().unwrap().measured_data.pm25_stats = pm25_stats;
+        let (timestamp, clock_state) = {
+            let clock = clock.lock().unwrap();
+            (clock.get_unix_timestamp(), clock.state())
+        };
+        state.write().unwrap().measured_data.timestamp = timestamp;
+        state.write().unwrap().measured_data.clock = clock_state;
+
+        // Update daily statistics, samples can't be assigned to a day before the first sync
+        if let Some(datetime) = clock.lock().unwrap().get_datetime() {
+            let mut state = state.write().unwrap();
+            let rolled_over = state.daily_stats.record(
+                datetime,
+                climate.map(|m| m.co2),
+                pm25_stats.map(|_| pm25),
+            );
+
+            samples_since_persist += 1;
+            if rolled_over || samples_since_persist >= DAILY_STATS_PERSIST_EVERY {
+                samples_since_persist = 0;
+                let result = storage
+                    .lock()
+                    .unwrap()
+                    .store(DAILY_STATS_KEY, &state.daily_stats);
+                if let Err(e) = result {
+                    error!("Failed to persist daily stats: {}", e);
+                }
+            }
+        }
+
+        // Evaluate alerts
+        let sample = alerts::Sample {
+            co2: climate.map(|m| m.co2),
+            pm25: pm25_stats.map(|_| pm25),
+            clock_synced: clock_state == SyncState::Synced,
+        };
+        let (notifications, webhooks) = {
+            let mut alerts = alerts.lock().unwrap();
+            let notifications = alerts.evaluate(&sample, Instant::now());
+            (notifications, alerts.config().webhooks.clone())
+        };
+        for notification in &notifications {
+            watchdog::feed();
+            warn!("Alert {:?}", notification);
+            let result = logging::send_webhooks(&webhooks, notification, &device_id(), timestamp);
+            if let Err(e) = &result {
+                error!("Error sending alert: {}", e);
+            }
+            diagnostics
+                .lock()
+                .unwrap()
+                .record(Subsystem::Webhooks, &result);
+        }
 
         // Update LEDs
-        leds.write().unwrap().visualize_measures(co2, pm25);
+        let sensor_fault = climate.is_none() || pm25_stats.is_none();
+        let alert_active = !alerts.lock().unwrap().active(Instant::now()).is_empty();
+        set_indication(&leds, Indication::SensorFault, sensor_fault);
+        set_indication(&leds, Indication::Alert, alert_active);
+
+        // Wi-Fi and the HTTP server are up by now, a reading of either sensor confirms a new
+        // firmware, units without the SCD41 or with a failing one have to update too
+        if climate.is_some() || pm25_stats.is_some() {
+            ota.mark_healthy();
+        }
+
+        leds.write().unwrap().visualize_measures(Measures {
+            co2,
+            pm25,
+            temperature: climate.map(|m| m.temperature),
+            humidity: climate.map(|m| m.humidity),
+        });
 
         // Log data
-        match logging::log_data(&logging::LogEntry::new(co2, pm25)) {
+        watchdog::feed();
+        let log_entry = logging::LogEntry::new(
+            co2,
+            pm25,
+            pm25_raw,
+            climate.map(|m| m.humidity),
+            pm25_stats,
+            timestamp,
+        )
+        .with_crash(diagnostics.lock().unwrap().unreported_crash().cloned());
+        let result = logging::log_data(&log_entry);
+        match &result {
             Ok(_) => info!("Data logged successfully"),
             Err(e) => error!("Error logging data: {}", e),
         }
+        if result.is_ok() && log_entry.has_crash() {
+            let crash = diagnostics.lock().unwrap().set_crash_reported().cloned();
+            if let Some(crash) = crash {
+                if let Err(e) = storage.lock().unwrap().store(LAST_CRASH_KEY, &crash) {
+                    error!("Failed to persist the crash report: {}", e);
+                }
+            }
+        }
+        diagnostics
+            .lock()
+            .unwrap()
+            .record(Subsystem::Logger, &result);
 
-        sleep_ms(50_000);
+        watchdog::sleep_fed(MEASUREMENT_PAUSE_MS);
     }
 }
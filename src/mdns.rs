@@ -0,0 +1,47 @@
+//! mDNS responder, answers `<hostname>.local` and advertises the REST API as `_http._tcp` on the
+//! netifs of `wifi::wifi()`, it follows their reconnects by itself.
+
+use anyhow::Result;
+use esp_idf_svc::mdns::EspMdns;
+use log::*;
+
+use crate::update::CURRENT_VERSION;
+use crate::utils::device_id;
+
+const HTTP_PORT: u16 = 80;
+// Advertised for clients looking for the API
+const API_PATH: &str = "/data";
+
+pub struct Mdns {
+    mdns: EspMdns,
+}
+
+impl Mdns {
+    /// Starts after `wifi::wifi()`, the netifs have to exist
+    pub fn start(hostname: &str) -> Result<Self> {
+        let mut mdns = EspMdns::take()?;
+        mdns.set_hostname(hostname)?;
+        mdns.set_instance_name(hostname)?;
+        let device_id = device_id();
+        mdns.add_service(
+            None,
+            "_http",
+            "_tcp",
+            HTTP_PORT,
+            &[
+                ("version", CURRENT_VERSION),
+                ("id", &device_id),
+                ("path", API_PATH),
+            ],
+        )?;
+        info!("mDNS: {}.local", hostname);
+        Ok(Self { mdns })
+    }
+
+    pub fn set_hostname(&mut self, hostname: &str) -> Result<()> {
+        self.mdns.set_hostname(hostname)?;
+        self.mdns.set_instance_name(hostname)?;
+        info!("mDNS: {}.local", hostname);
+        Ok(())
+    }
+}
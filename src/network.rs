@@ -0,0 +1,201 @@
+//! Network config of the station interface: hostname, IPv4 addressing and IPv6
+
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Result};
+use log::error;
+use serde::{Deserialize, Serialize};
+
+const HOSTNAME_PREFIX: &str = "vindriktning-";
+// ESP_NETIF_HOSTNAME_MAX_SIZE, the same hostname goes to mDNS, DHCP and syslog so the shortest
+// limit applies, a DNS label would allow 63
+const HOSTNAME_MAX_LEN: usize = 32;
+// Servers of the ESP-IDF netif, the main and the backup one
+const MAX_DNS_SERVERS: usize = 2;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    /// `vindriktning-<mac>` without it
+    pub hostname: Option<String>,
+    pub ipv4: Ipv4Config,
+    /// Link-local and SLAAC addresses from router advertisements
+    pub ipv6: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum Ipv4Config {
+    /// Address, gateway and DNS servers from the DHCP server
+    #[default]
+    Dhcp,
+    Static {
+        address: Ipv4Addr,
+        netmask: Ipv4Addr,
+        gateway: Ipv4Addr,
+        /// Up to two, names don't resolve without them
+        #[serde(default)]
+        dns: Vec<Ipv4Addr>,
+    },
+}
+
+impl Ipv4Config {
+    pub fn validate(&self) -> Result<()> {
+        let Self::Static {
+            address,
+            netmask,
+            gateway,
+            dns,
+        } = self
+        else {
+            return Ok(());
+        };
+        let prefix_len = prefix_len(*netmask)?;
+        if !is_unicast(*address) {
+            bail!("Invalid address {}", address);
+        }
+        if !is_unicast(*gateway) {
+            bail!("Invalid gateway {}", gateway);
+        }
+        let network = |ip: Ipv4Addr| u32::from(ip) & u32::from(*netmask);
+        if network(*address) != network(*gateway) {
+            bail!(
+                "Gateway {} is outside of {}/{}",
+                gateway,
+                address,
+                prefix_len
+            );
+        }
+        // /31 and /32 have no network and broadcast addresses
+        if prefix_len < 31 {
+            let host = u32::from(*address) & !u32::from(*netmask);
+            if host == 0 || host == !u32::from(*netmask) {
+                bail!(
+                    "{} is the network or broadcast address of the subnet",
+                    address
+                );
+            }
+        }
+        if dns.len() > MAX_DNS_SERVERS {
+            bail!("At most {} DNS servers are supported", MAX_DNS_SERVERS);
+        }
+        if let Some(server) = dns.iter().find(|&&server| !is_unicast(server)) {
+            bail!("Invalid DNS server {}", server);
+        }
+        Ok(())
+    }
+}
+
+/// `dhcp` or `<address>/<prefix length>,<gateway>[,<dns>[,<dns>]]`
+impl FromStr for Ipv4Config {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        if value == "dhcp" {
+            return Ok(Self::Dhcp);
+        }
+        let mut parts = value.split(',').map(str::trim);
+        let (address, prefix_len) = parts
+            .next()
+            .and_then(|cidr| cidr.split_once('/'))
+            .ok_or_else(|| anyhow!("Expected dhcp or <address>/<prefix length>,<gateway>"))?;
+        let prefix_len = prefix_len
+            .parse()
+            .map_err(|_| anyhow!("Invalid prefix length {:?}", prefix_len))?;
+        let gateway = parts.next().ok_or_else(|| anyhow!("Missing gateway"))?;
+        let parse = |ip: &str| {
+            ip.parse::<Ipv4Addr>()
+                .map_err(|_| anyhow!("Invalid IPv4 address {:?}", ip))
+        };
+        let config = Self::Static {
+            address: parse(address)?,
+            netmask: netmask(prefix_len)?,
+            gateway: parse(gateway)?,
+            dns: parts.map(parse).collect::<Result<_>>()?,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+pub fn netmask(prefix_len: u8) -> Result<Ipv4Addr> {
+    if prefix_len > 32 {
+        bail!("Invalid prefix length {}", prefix_len);
+    }
+    Ok(Ipv4Addr::from(
+        u32::MAX
+            .checked_shl(32 - u32::from(prefix_len))
+            .unwrap_or(0),
+    ))
+}
+
+/// Ones followed by zeros only, `/0` isn't a usable subnet
+pub fn prefix_len(netmask: Ipv4Addr) -> Result<u8> {
+    let mask = u32::from(netmask);
+    let prefix_len = mask.leading_ones();
+    if prefix_len == 0 || mask.checked_shl(prefix_len).unwrap_or(0) != 0 {
+        bail!("Invalid netmask {}", netmask);
+    }
+    Ok(prefix_len as u8)
+}
+
+fn is_unicast(ip: Ipv4Addr) -> bool {
+    !(ip.is_unspecified() || ip.is_broadcast() || ip.is_multicast() || ip.is_loopback())
+}
+
+impl NetworkConfig {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(hostname) = &self.hostname {
+            validate_hostname(hostname)?;
+        }
+        self.ipv4.validate()
+    }
+
+    /// Replaces the invalid parts of a stored config with the defaults, e.g. a hostname longer
+    /// than an earlier firmware allowed, the interface can't be set up with them
+    pub fn sanitized(mut self) -> Self {
+        if let Some(Err(e)) = self.hostname.as_deref().map(validate_hostname) {
+            error!("Invalid stored hostname, using the default one: {}", e);
+            self.hostname = None;
+        }
+        if let Err(e) = self.ipv4.validate() {
+            error!("Invalid stored IPv4 config, using DHCP: {}", e);
+            self.ipv4 = Ipv4Config::Dhcp;
+        }
+        self
+    }
+
+    pub fn hostname(&self, device_id: &str) -> String {
+        self.hostname
+            .clone()
+            .unwrap_or_else(|| format!("{}{}", HOSTNAME_PREFIX, device_id))
+    }
+}
+
+/// Letters, digits and hyphens, not at the start or the end (RFC 1123)
+pub fn validate_hostname(hostname: &str) -> Result<()> {
+    if hostname.is_empty() || hostname.len() > HOSTNAME_MAX_LEN {
+        bail!(
+            "Hostname must have 1 to {} characters, got {}",
+            HOSTNAME_MAX_LEN,
+            hostname.len()
+        );
+    }
+    if !hostname
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        bail!(
+            "Invalid hostname {:?}, only letters, digits and hyphens are allowed",
+            hostname
+        );
+    }
+    if hostname.starts_with('-') || hostname.ends_with('-') {
+        bail!(
+            "Invalid hostname {:?}, it can't start or end with a hyphen",
+            hostname
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,396 @@
+use std::ffi::CStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use ed25519_dalek::VerifyingKey;
+use embedded_svc::http::client::Client;
+use embedded_svc::http::{Headers, Method};
+use embedded_svc::io::Read;
+use esp_idf_svc::ota::{EspOta, EspOtaUpdate, SlotState};
+use esp_idf_svc::sys;
+use log::*;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::logging;
+use crate::signature::{self, TRAILER_LEN};
+use crate::update::{self, Manifest, MANIFEST_MAX_LEN};
+
+// esp_image_header_t
+const IMAGE_MAGIC: u8 = 0xE9;
+const CHIP_ID_OFFSET: usize = 12;
+// esp_app_desc_t, after the image header (24 bytes) and the first segment header (8 bytes)
+const APP_DESC_OFFSET: usize = 32;
+const APP_DESC_MAGIC: u32 = 0xABCD_5432;
+const APP_DESC_LEN: usize = 256;
+
+// Hex Ed25519 key the images are signed with, see tools/sign-image, updates are rejected without it
+const OTA_PUBLIC_KEY: Option<&str> = option_env!("OTA_PUBLIC_KEY");
+
+// Write size, the image is streamed to flash
+const CHUNK_SIZE: usize = 4096;
+// How long a new image has to become healthy before it is rolled back
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Identification of a firmware image, from its header and app description
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageInfo {
+    pub chip_id: u16,
+    pub project_name: String,
+    pub version: String,
+    pub idf_version: String,
+    /// Build date and time
+    pub built: String,
+    /// SHA-256 of the ELF file, identifies the build
+    pub elf_sha256: String,
+}
+
+/// NUL terminated string of a fixed size field
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl ImageInfo {
+    /// Bytes needed by `parse()`
+    pub const LEN: usize = APP_DESC_OFFSET + APP_DESC_LEN;
+
+    pub fn parse(header: &[u8]) -> Result<Self> {
+        if header.len() < Self::LEN {
+            bail!("Image too short: {} bytes", header.len());
+        }
+        if header[0] != IMAGE_MAGIC {
+            bail!("Not an ESP image, magic {:#04x}", header[0]);
+        }
+        let desc = &header[APP_DESC_OFFSET..Self::LEN];
+        let magic = u32::from_le_bytes([desc[0], desc[1], desc[2], desc[3]]);
+        if magic != APP_DESC_MAGIC {
+            bail!("Missing app description, magic {:#010x}", magic);
+        }
+
+        Ok(Self {
+            chip_id: u16::from_le_bytes([header[CHIP_ID_OFFSET], header[CHIP_ID_OFFSET + 1]]),
+            version: c_string(&desc[16..48]),
+            project_name: c_string(&desc[48..80]),
+            built: format!("{} {}", c_string(&desc[96..112]), c_string(&desc[80..96])),
+            idf_version: c_string(&desc[112..144]),
+            elf_sha256: hex(&desc[144..176]),
+        })
+    }
+
+    /// The running firmware
+    pub fn running() -> Self {
+        let desc = unsafe { &*sys::esp_app_get_description() };
+        let field = |chars: &[core::ffi::c_char]| {
+            unsafe { CStr::from_ptr(chars.as_ptr()) }
+                .to_string_lossy()
+                .into_owned()
+        };
+        Self {
+            chip_id: sys::esp_chip_id_t_ESP_CHIP_ID_ESP32 as u16,
+            project_name: field(&desc.project_name),
+            version: field(&desc.version),
+            idf_version: field(&desc.idf_ver),
+            built: format!("{} {}", field(&desc.date), field(&desc.time)),
+            elf_sha256: hex(&desc.app_elf_sha256),
+        }
+    }
+
+    /// Checks that the image is built for this chip and project
+    pub fn check_compatible(&self, running: &Self) -> Result<()> {
+        if self.chip_id != running.chip_id {
+            bail!(
+                "Image is built for chip {}, expected {}",
+                self.chip_id,
+                running.chip_id
+            );
+        }
+        if self.project_name != running.project_name {
+            bail!(
+                "Image is {:?}, expected {:?}",
+                self.project_name,
+                running.project_name
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum Progress {
+    #[default]
+    Idle,
+    Receiving {
+        version: String,
+        received: usize,
+        total: usize,
+    },
+    /// Written, the device restarts into the new image
+    Complete {
+        version: String,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+/// Writes signed images to the inactive OTA slot and confirms the running one once healthy
+pub struct Updater {
+    ota: Mutex<EspOta>,
+    public_key: Option<VerifyingKey>,
+    progress: Mutex<Progress>,
+    /// Set while the running image waits for its verification
+    verify_deadline: Mutex<Option<Instant>>,
+}
+
+impl Updater {
+    pub fn new() -> Result<Self> {
+        let ota = EspOta::new()?;
+        let running = ota.get_running_slot()?;
+        info!(
+            "Running {} from {}, {:?}",
+            ImageInfo::running().version,
+            running.label,
+            running.state
+        );
+        let verify_deadline =
+            matches!(running.state, SlotState::Unverified).then(|| Instant::now() + VERIFY_TIMEOUT);
+
+        let public_key = match OTA_PUBLIC_KEY.map(signature::parse_public_key) {
+            Some(Ok(public_key)) => Some(public_key),
+            Some(Err(e)) => {
+                error!("Invalid OTA_PUBLIC_KEY, updates are disabled: {}", e);
+                None
+            }
+            None => {
+                warn!("No OTA_PUBLIC_KEY built in, updates are disabled");
+                None
+            }
+        };
+
+        Ok(Self {
+            ota: Mutex::new(ota),
+            public_key,
+            progress: Mutex::new(Progress::Idle),
+            verify_deadline: Mutex::new(verify_deadline),
+        })
+    }
+
+    pub fn progress(&self) -> Progress {
+        self.progress.lock().unwrap().clone()
+    }
+
+    /// Whether the running image still has to be confirmed with `mark_healthy()`
+    pub fn pending_verify(&self) -> bool {
+        self.verify_deadline.lock().unwrap().is_some()
+    }
+
+    /// Confirms the running image, it won't be rolled back anymore
+    pub fn mark_healthy(&self) {
+        let mut verify_deadline = self.verify_deadline.lock().unwrap();
+        if verify_deadline.is_none() {
+            return;
+        }
+        match self.ota.lock().unwrap().mark_running_slot_valid() {
+            Ok(()) => {
+                info!("Firmware {} marked valid", ImageInfo::running().version);
+                *verify_deadline = None;
+            }
+            Err(e) => error!("Failed to mark the firmware valid: {}", e),
+        }
+    }
+
+    /// Rolls back to the previous image when the running one hasn't become healthy in time
+    pub fn check_verify_deadline(&self) {
+        if self
+            .verify_deadline
+            .lock()
+            .unwrap()
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            error!("Firmware not healthy in {:?}, rolling back", VERIFY_TIMEOUT);
+            // only returns when there is no image to roll back to
+            let e = self
+                .ota
+                .lock()
+                .unwrap()
+                .mark_running_slot_invalid_and_reboot();
+            error!("Failed to roll back: {}", e);
+        }
+    }
+
+    /// Downloads the image of a pull update and writes it like an upload
+    pub fn download(&self, manifest: &Manifest) -> Result<()> {
+        info!(
+            "Downloading firmware {} from {}",
+            manifest.version, manifest.url
+        );
+        let mut client = Client::wrap(logging::connection()?);
+        let mut response = client.request(Method::Get, &manifest.url, &[])?.submit()?;
+        let status = response.status();
+        if !(200..=299).contains(&status) {
+            bail!("Unexpected image response code: {}", status);
+        }
+        let total = response
+            .content_len()
+            .ok_or_else(|| anyhow!("Image response without Content-Length"))?;
+        self.update(&mut response, total as usize, Some(manifest.sha256()?))?;
+        Ok(())
+    }
+
+    /// Streams a signed image of `total` bytes to the inactive slot and makes it the boot one
+    /// if the signature is valid, the caller restarts the device. With `sha256` the whole
+    /// signed image has to match it too.
+    pub fn update<R: Read>(
+        &self,
+        reader: &mut R,
+        total: usize,
+        sha256: Option<[u8; 32]>,
+    ) -> Result<ImageInfo> {
+        let result = self.receive(reader, total, sha256);
+        *self.progress.lock().unwrap() = match &result {
+            Ok(info) => Progress::Complete {
+                version: info.version.clone(),
+            },
+            Err(e) => Progress::Failed {
+                error: e.to_string(),
+            },
+        };
+        result
+    }
+
+    fn receive<R: Read>(
+        &self,
+        reader: &mut R,
+        total: usize,
+        sha256: Option<[u8; 32]>,
+    ) -> Result<ImageInfo> {
+        let mut ota = self
+            .ota
+            .try_lock()
+            .map_err(|_| anyhow!("Another update is in progress"))?;
+        let public_key = self
+            .public_key
+            .as_ref()
+            .ok_or_else(|| anyhow!("Updates are disabled, no public key built in"))?;
+        if total < ImageInfo::LEN + TRAILER_LEN {
+            bail!("Image too short to be signed: {} bytes", total);
+        }
+
+        // validate before the slot gets erased
+        let mut header = vec![0; ImageInfo::LEN];
+        reader
+            .read_exact(&mut header)
+            .map_err(|e| anyhow!("Failed to read the image header: {:?}", e))?;
+        let info = ImageInfo::parse(&header)?;
+        info.check_compatible(&ImageInfo::running())?;
+        info!("Receiving firmware {} ({} bytes)", info.version, total);
+
+        let mut update = ota.initiate_update()?;
+        let result = self
+            .write(reader, &mut update, &header, &info, total, sha256)
+            .and_then(|(image_sha256, trailer)| {
+                signature::verify(public_key, &image_sha256, &trailer)
+            });
+        match result {
+            Ok(()) => update.complete()?,
+            Err(e) => {
+                update.abort()?;
+                return Err(e);
+            }
+        }
+        info!("Firmware {} written", info.version);
+        Ok(info)
+    }
+
+    /// Writes the image, returns its SHA-256 and the signature trailer
+    fn write<R: Read>(
+        &self,
+        reader: &mut R,
+        update: &mut EspOtaUpdate,
+        header: &[u8],
+        info: &ImageInfo,
+        total: usize,
+        sha256: Option<[u8; 32]>,
+    ) -> Result<([u8; 32], Vec<u8>)> {
+        // the signature trailer isn't written to flash
+        let image_len = total - TRAILER_LEN;
+        let mut trailer = Vec::with_capacity(TRAILER_LEN);
+        // over the whole upload, for `sha256`
+        let mut hasher = Sha256::new();
+        let mut image_hasher = Sha256::new();
+        hasher.update(header);
+        image_hasher.update(header);
+        update.write(header)?;
+        let mut received = header.len();
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let mut logged_percent = 0;
+
+        while received < total {
+            let length = reader
+                .read(&mut buffer[..CHUNK_SIZE.min(total - received)])
+                .map_err(|e| anyhow!("Failed to read the image: {:?}", e))?;
+            if length == 0 {
+                bail!("Upload ended after {} of {} bytes", received, total);
+            }
+            let chunk = &buffer[..length];
+            let (image, rest) = chunk.split_at(length.min(image_len.saturating_sub(received)));
+            hasher.update(chunk);
+            image_hasher.update(image);
+            if !image.is_empty() {
+                update.write(image)?;
+            }
+            trailer.extend_from_slice(rest);
+            received += length;
+
+            *self.progress.lock().unwrap() = Progress::Receiving {
+                version: info.version.clone(),
+                received,
+                total,
+            };
+            let percent = received * 100 / total;
+            if percent >= logged_percent + 10 {
+                logged_percent = percent;
+                info!("Firmware upload {}%", percent);
+            }
+        }
+
+        if let Some(expected) = sha256 {
+            let actual: [u8; 32] = hasher.finalize().into();
+            if actual != expected {
+                bail!("SHA-256 mismatch, the image is corrupted");
+            }
+        }
+        Ok((image_hasher.finalize().into(), trailer))
+    }
+}
+
+/// Fetches the pull update manifest with the TLS client config of the logger
+pub fn fetch_manifest(url: &str) -> Result<Manifest> {
+    let mut client = Client::wrap(logging::connection()?);
+    let mut response = client.request(Method::Get, url, &[])?.submit()?;
+    let status = response.status();
+    if !(200..=299).contains(&status) {
+        bail!("Unexpected manifest response code: {}", status);
+    }
+
+    let mut body = Vec::new();
+    let mut buffer = [0; 256];
+    // one byte over the limit is enough to reject it
+    while body.len() <= MANIFEST_MAX_LEN {
+        let length = response
+            .read(&mut buffer)
+            .map_err(|e| anyhow!("Failed to read the manifest: {:?}", e))?;
+        if length == 0 {
+            break;
+        }
+        body.extend_from_slice(&buffer[..length]);
+    }
+    update::parse_manifest(&body)
+}
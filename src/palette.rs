@@ -0,0 +1,155 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::color::Color;
+
+const fn rgb(r: u8, g: u8, b: u8) -> Color {
+    Color {
+        r,
+        g,
+        b,
+        brightness: None,
+    }
+}
+
+// Colors are gamma encoded, see ColorPipeline
+const AQUA: Color = rgb(0, 255, 255);
+const GREEN: Color = rgb(0, 255, 0);
+const YELLOW: Color = rgb(255, 255, 0);
+// 69 green before gamma correction, gamma encoded to keep the same hue
+const ORANGE: Color = rgb(255, 140, 0);
+const RED: Color = rgb(255, 0, 0);
+const DARK_RED: Color = rgb(128, 0, 0);
+const BLUE: Color = rgb(0, 0, 255);
+const MAGENTA: Color = rgb(255, 0, 255);
+const WHITE: Color = rgb(255, 255, 255);
+
+// https://jfly.uni-koeln.de/color/ (Okabe & Ito), distinguishable with deuteranopia and protanopia
+const OI_SKY_BLUE: Color = rgb(86, 180, 233);
+const OI_BLUE: Color = rgb(0, 114, 178);
+const OI_YELLOW: Color = rgb(240, 228, 66);
+const OI_ORANGE: Color = rgb(230, 159, 0);
+const OI_VERMILLION: Color = rgb(213, 94, 0);
+const OI_REDDISH_PURPLE: Color = rgb(204, 121, 167);
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaletteName {
+    #[default]
+    Default,
+    /// Safe for deuteranopia and protanopia
+    ColorBlind,
+    /// White, the worse the brighter
+    Monochrome,
+    BlueToRed,
+    Custom,
+}
+
+/// Colors of everything the LEDs show
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Palette {
+    /// Indexed by `get_co2_band()`
+    pub co2: [Color; 5],
+    /// Indexed by `get_pm25_band()`
+    pub pm25: [Color; 5],
+    pub initial: Color,
+    pub waiting: Color,
+    pub provisioning: Color,
+    pub sensor_fault: Color,
+    pub alert: Color,
+    pub identify: Color,
+    pub ota: Color,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            co2: [AQUA, GREEN, YELLOW, ORANGE, RED],
+            pm25: [GREEN, YELLOW, ORANGE, RED, DARK_RED],
+            initial: MAGENTA,
+            waiting: AQUA,
+            provisioning: BLUE,
+            sensor_fault: MAGENTA,
+            alert: RED,
+            identify: WHITE,
+            ota: AQUA,
+        }
+    }
+}
+
+/// Persisted palette choice, `custom` is used with `PaletteName::Custom` only
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PaletteSettings {
+    pub name: PaletteName,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub custom: Option<Palette>,
+}
+
+impl PaletteSettings {
+    pub fn validate(&self) -> Result<()> {
+        if self.name == PaletteName::Custom && self.custom.is_none() {
+            bail!("The custom palette needs the custom colors");
+        }
+        Ok(())
+    }
+
+    pub fn palette(&self) -> Palette {
+        match self.name {
+            PaletteName::Default => Palette::default(),
+            PaletteName::ColorBlind => Palette {
+                co2: [OI_SKY_BLUE, OI_BLUE, OI_YELLOW, OI_ORANGE, OI_VERMILLION],
+                pm25: [
+                    OI_BLUE,
+                    OI_YELLOW,
+                    OI_ORANGE,
+                    OI_VERMILLION,
+                    OI_REDDISH_PURPLE,
+                ],
+                waiting: OI_SKY_BLUE,
+                provisioning: OI_BLUE,
+                sensor_fault: OI_REDDISH_PURPLE,
+                alert: OI_VERMILLION,
+                ota: OI_SKY_BLUE,
+                ..Default::default()
+            },
+            PaletteName::Monochrome => {
+                // 1/8, 1/4, 1/2, 3/4 and the full light output, distinct down to the lowest
+                // brightness
+                let levels = [
+                    rgb(99, 99, 99),
+                    rgb(136, 136, 136),
+                    rgb(186, 186, 186),
+                    rgb(224, 224, 224),
+                    WHITE,
+                ];
+                Palette {
+                    co2: levels,
+                    pm25: levels,
+                    initial: WHITE,
+                    waiting: levels[1],
+                    provisioning: WHITE,
+                    sensor_fault: WHITE,
+                    alert: WHITE,
+                    identify: WHITE,
+                    ota: WHITE,
+                }
+            }
+            PaletteName::BlueToRed => {
+                let levels = [
+                    rgb(0, 0, 255),
+                    rgb(90, 0, 220),
+                    rgb(160, 0, 160),
+                    rgb(220, 0, 90),
+                    rgb(255, 0, 0),
+                ];
+                Palette {
+                    co2: levels,
+                    pm25: levels,
+                    ..Default::default()
+                }
+            }
+            PaletteName::Custom => self.custom.clone().unwrap_or_default(),
+        }
+    }
+}
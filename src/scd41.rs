@@ -1,12 +1,25 @@
+use std::fmt::Debug;
+
 use embedded_hal::delay::DelayNs;
 use embedded_hal::i2c::I2c;
 use scd4x::Scd4x;
 
+use crate::supervisor::Driver;
+
 #[derive(Debug)]
 pub struct Scd41<I2C, D> {
     sensor: Scd4x<I2C, D>,
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+    pub co2: u16,
+    /// °C
+    pub temperature: f32,
+    /// %RH
+    pub humidity: f32,
+}
+
 impl<I2C, D, E> Scd41<I2C, D>
 where
     I2C: I2c<Error = E>,
@@ -25,8 +38,30 @@ where
         Ok(())
     }
 
-    pub fn read_co2(&mut self) -> Result<u16, scd4x::Error<E>> {
+    pub fn measure(&mut self) -> Result<Measurement, scd4x::Error<E>> {
         let m = self.sensor.measurement()?;
-        Ok(m.co2)
+        Ok(Measurement {
+            co2: m.co2,
+            temperature: m.temperature,
+            humidity: m.humidity,
+        })
+    }
+}
+
+impl<I2C, D, E> Driver for Scd41<I2C, D>
+where
+    I2C: I2c<Error = E>,
+    D: DelayNs,
+    E: Debug,
+{
+    type Output = Measurement;
+    type Error = scd4x::Error<E>;
+
+    fn init(&mut self) -> Result<(), Self::Error> {
+        Scd41::init(self)
+    }
+
+    fn run(&mut self) -> Result<Measurement, Self::Error> {
+        self.measure()
     }
 }
@@ -0,0 +1,243 @@
+//! Weekly brightness schedule in local time with holidays and the solar dimming
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Error};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use time::{Date, Duration, Month, OffsetDateTime, Weekday};
+
+use crate::solar::SolarDimming;
+use crate::stats::format_date;
+
+/// What the LEDs do while a window is active
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    /// The night brightness preference
+    Night,
+    Brightness {
+        brightness: u8,
+    },
+    Off,
+}
+
+/// Brightness picked by the schedule
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scheduled {
+    pub brightness: u8,
+    /// A night or off window is active or the sun is down, the night display mode applies
+    pub night: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Day {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl From<Weekday> for Day {
+    fn from(weekday: Weekday) -> Self {
+        match weekday {
+            Weekday::Monday => Self::Mon,
+            Weekday::Tuesday => Self::Tue,
+            Weekday::Wednesday => Self::Wed,
+            Weekday::Thursday => Self::Thu,
+            Weekday::Friday => Self::Fri,
+            Weekday::Saturday => Self::Sat,
+            Weekday::Sunday => Self::Sun,
+        }
+    }
+}
+
+/// Local wall clock time, `HH:MM`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LocalTime {
+    minutes: u16,
+}
+
+impl LocalTime {
+    pub const fn new(hour: u8, minute: u8) -> Self {
+        Self {
+            minutes: hour as u16 * 60 + minute as u16,
+        }
+    }
+}
+
+impl FromStr for LocalTime {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (hour, minute) = s
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Expected HH:MM, got {:?}", s))?;
+        let hour: u8 = hour.parse()?;
+        let minute: u8 = minute.parse()?;
+        // 24:00 allows windows ending at midnight
+        if hour > 24 || minute > 59 || (hour == 24 && minute > 0) {
+            bail!("Time {:?} out of range", s);
+        }
+        Ok(Self::new(hour, minute))
+    }
+}
+
+impl fmt::Display for LocalTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.minutes / 60, self.minutes % 60)
+    }
+}
+
+impl Serialize for LocalTime {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for LocalTime {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Time span starting on the given days, `end` at or before `start` spans over midnight
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Window {
+    pub days: Vec<Day>,
+    pub start: LocalTime,
+    pub end: LocalTime,
+    pub action: Action,
+}
+
+/// The date uses the windows of another day, e.g. a public holiday behaves like Sunday
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Holiday {
+    /// YYYY-MM-DD
+    pub date: String,
+    pub as_day: Day,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    /// The first active window wins
+    pub windows: Vec<Window>,
+    #[serde(default)]
+    pub holidays: Vec<Holiday>,
+    /// Dims with the sun outside of the windows
+    #[serde(default)]
+    pub solar: Option<SolarDimming>,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self {
+            windows: vec![Window {
+                days: vec![
+                    Day::Mon,
+                    Day::Tue,
+                    Day::Wed,
+                    Day::Thu,
+                    Day::Fri,
+                    Day::Sat,
+                    Day::Sun,
+                ],
+                start: LocalTime::new(22, 0),
+                end: LocalTime::new(6, 0),
+                action: Action::Night,
+            }],
+            holidays: Vec::new(),
+            solar: None,
+        }
+    }
+}
+
+impl Window {
+    fn overnight(&self) -> bool {
+        self.end <= self.start
+    }
+}
+
+impl Schedule {
+    /// Action of the active window at the local `datetime`, `None` outside of all windows
+    ///
+    /// Works with the wall clock time, so a DST change shortens or lengthens the windows it
+    /// falls into instead of shifting them.
+    pub fn evaluate(&self, datetime: OffsetDateTime) -> Option<Action> {
+        let now = LocalTime::new(datetime.hour(), datetime.minute());
+        let today = self.day(datetime);
+        let yesterday = self.day(datetime - Duration::days(1));
+
+        self.windows
+            .iter()
+            .find(|window| {
+                let started_today = window.days.contains(&today)
+                    && now >= window.start
+                    && (window.overnight() || now < window.end);
+                let started_yesterday =
+                    window.days.contains(&yesterday) && window.overnight() && now < window.end;
+                started_today || started_yesterday
+            })
+            .map(|window| window.action)
+    }
+
+    /// Brightness at the local `datetime`, the window action wins over the solar dimming, `None`
+    /// outside of both when the day brightness applies
+    pub fn brightness(&self, datetime: OffsetDateTime, day: u8, night: u8) -> Option<Scheduled> {
+        let scheduled = |brightness, night| Some(Scheduled { brightness, night });
+        match self.evaluate(datetime) {
+            Some(Action::Night) => scheduled(night, true),
+            Some(Action::Brightness { brightness }) => scheduled(brightness, false),
+            Some(Action::Off) => scheduled(0, true),
+            None => self
+                .solar
+                .filter(|solar| solar.daylight(datetime) < 1.0)
+                .and_then(|solar| scheduled(solar.brightness(datetime, day, night), true)),
+        }
+    }
+
+    /// Day of the week, unless the date is a holiday
+    fn day(&self, datetime: OffsetDateTime) -> Day {
+        let date = format_date(&datetime);
+        self.holidays
+            .iter()
+            .find(|holiday| holiday.date == date)
+            .map_or_else(|| datetime.weekday().into(), |holiday| holiday.as_day)
+    }
+
+    /// Checks the holiday dates, they are compared as strings when evaluating, and the location
+    pub fn validate(&self) -> Result<(), Error> {
+        for holiday in &self.holidays {
+            let valid = parse_date(&holiday.date)
+                .is_some_and(|date| format_date(&date.midnight().assume_utc()) == holiday.date);
+            if !valid {
+                bail!(
+                    "Invalid holiday date {:?}, expected YYYY-MM-DD",
+                    holiday.date
+                );
+            }
+        }
+        if let Some(solar) = &self.solar {
+            if !(-90.0..=90.0).contains(&solar.latitude)
+                || !(-180.0..=180.0).contains(&solar.longitude)
+            {
+                bail!("Invalid location {}, {}", solar.latitude, solar.longitude);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_date(s: &str) -> Option<Date> {
+    let mut parts = s.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = Month::try_from(parts.next()?.parse::<u8>().ok()?).ok()?;
+    let day = parts.next()?.parse().ok()?;
+    Date::from_calendar_date(year, month, day).ok()
+}
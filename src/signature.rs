@@ -0,0 +1,50 @@
+//! Signed OTA images: `<ESP image><TRAILER_MAGIC><Ed25519 signature>`. The signature covers
+//! `SIGNED_PREFIX` followed by the SHA-256 of the ESP image, so the image can be verified
+//! while it is streamed. Shared with `tools/sign-image`, which signs and tests it on the host.
+
+use anyhow::{anyhow, bail, Result};
+use ed25519_dalek::{Signature, VerifyingKey, SIGNATURE_LENGTH};
+
+/// Identifies the trailer and its format version
+pub const TRAILER_MAGIC: [u8; 8] = *b"VNDSIG\x00\x01";
+pub const TRAILER_LEN: usize = TRAILER_MAGIC.len() + SIGNATURE_LENGTH;
+// Keeps the signatures from being valid for anything else signed with the same key
+pub const SIGNED_PREFIX: &[u8] = b"esp-vindriktning firmware sha256:";
+
+/// What the signature is made over
+pub fn signed_message(image_sha256: &[u8; 32]) -> Vec<u8> {
+    [SIGNED_PREFIX, image_sha256].concat()
+}
+
+pub fn parse_hex<const N: usize>(hex: &str) -> Result<[u8; N]> {
+    let hex = hex.trim();
+    if hex.len() != N * 2 || !hex.is_ascii() {
+        bail!("Expected {} hex digits, got {:?}", N * 2, hex);
+    }
+    let mut bytes = [0; N];
+    for (byte, digits) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        // ASCII checked above
+        let digits = std::str::from_utf8(digits).unwrap();
+        *byte = u8::from_str_radix(digits, 16)
+            .map_err(|_| anyhow!("Invalid hex digits {:?}", digits))?;
+    }
+    Ok(bytes)
+}
+
+pub fn parse_public_key(hex: &str) -> Result<VerifyingKey> {
+    VerifyingKey::from_bytes(&parse_hex(hex)?).map_err(|_| anyhow!("Invalid Ed25519 public key"))
+}
+
+/// Checks the trailer of an image with the given SHA-256
+pub fn verify(public_key: &VerifyingKey, image_sha256: &[u8; 32], trailer: &[u8]) -> Result<()> {
+    if trailer.len() != TRAILER_LEN || trailer[..TRAILER_MAGIC.len()] != TRAILER_MAGIC {
+        bail!("Image is not signed");
+    }
+    let signature = Signature::from_slice(&trailer[TRAILER_MAGIC.len()..])
+        .map_err(|_| anyhow!("Malformed image signature"))?;
+    public_key
+        .verify_strict(&signed_message(image_sha256), &signature)
+        .map_err(|_| {
+            anyhow!("Invalid image signature, the image is tampered or signed with another key")
+        })
+}
@@ -0,0 +1,179 @@
+//! Sunrise, sunset and twilight times and the dimming that follows them
+
+use serde::{Deserialize, Serialize};
+use time::{Date, Duration, OffsetDateTime, Time, UtcOffset};
+
+// Zenith of the sun center at sunrise, includes the refraction and the radius of the solar disc
+const SUNRISE_ZENITH: f64 = 90.833;
+const CIVIL_TWILIGHT_ZENITH: f64 = 96.0;
+
+// Ramp length when the sun does not get 6° below the horizon (summer at high latitudes)
+const DEFAULT_TWILIGHT: Duration = Duration::minutes(30);
+
+/// The sun crossing a zenith angle during a day
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Crossing {
+    Times {
+        rise: OffsetDateTime,
+        set: OffsetDateTime,
+    },
+    /// Midnight sun
+    AlwaysAbove,
+    /// Polar night
+    AlwaysBelow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SunTimes {
+    pub noon: OffsetDateTime,
+    pub sun: Crossing,
+    /// Civil twilight, dawn and dusk
+    pub civil: Crossing,
+}
+
+fn julian_day(datetime: OffsetDateTime) -> f64 {
+    datetime.unix_timestamp() as f64 / 86_400.0 + 2_440_587.5
+}
+
+/// Sun times of the local `date`, NOAA solar calculator algorithm
+/// (https://gml.noaa.gov/grad/solcalc/calcdetails.html), accurate to about a minute
+/// between ±72° latitude. Latitude is north positive, longitude east positive.
+pub fn sun_times(date: Date, offset: UtcOffset, latitude: f64, longitude: f64) -> SunTimes {
+    let utc_midnight = date.with_time(Time::MIDNIGHT).assume_utc();
+    // evaluate at the approximate solar noon, the declination barely changes during the day
+    let approximate_noon =
+        utc_midnight + Duration::seconds_f64((0.5 - longitude / 360.0) * 86_400.0);
+    let position = SunPosition::at(approximate_noon);
+
+    let minutes =
+        |minutes: f64| (utc_midnight + Duration::seconds_f64(minutes * 60.0)).to_offset(offset);
+    let noon_minutes = 720.0 - 4.0 * longitude - position.equation_of_time;
+    let noon = minutes(noon_minutes);
+    // keep the noon on the local date, e.g. with a time zone far from the longitude
+    let shift = noon.date() - date;
+
+    let crossing = |zenith: f64| {
+        let cos_hour_angle = zenith.to_radians().cos()
+            / (latitude.to_radians().cos() * position.declination.cos())
+            - latitude.to_radians().tan() * position.declination.tan();
+        if cos_hour_angle > 1.0 {
+            Crossing::AlwaysBelow
+        } else if cos_hour_angle < -1.0 {
+            Crossing::AlwaysAbove
+        } else {
+            let hour_angle = cos_hour_angle.acos().to_degrees();
+            Crossing::Times {
+                rise: minutes(noon_minutes - 4.0 * hour_angle) - shift,
+                set: minutes(noon_minutes + 4.0 * hour_angle) - shift,
+            }
+        }
+    };
+
+    SunTimes {
+        noon: noon - shift,
+        sun: crossing(SUNRISE_ZENITH),
+        civil: crossing(CIVIL_TWILIGHT_ZENITH),
+    }
+}
+
+struct SunPosition {
+    /// radians
+    declination: f64,
+    /// minutes
+    equation_of_time: f64,
+}
+
+impl SunPosition {
+    fn at(datetime: OffsetDateTime) -> Self {
+        let century = (julian_day(datetime) - 2_451_545.0) / 36_525.0;
+
+        let mean_longitude =
+            (280.46646 + century * (36_000.769_83 + century * 0.0003032)).rem_euclid(360.0);
+        let mean_anomaly = 357.52911 + century * (35_999.050_29 - 0.0001537 * century);
+        let eccentricity = 0.016708634 - century * (0.000042037 + 0.0000001267 * century);
+
+        let anomaly = mean_anomaly.to_radians();
+        let equation_of_center = anomaly.sin()
+            * (1.914602 - century * (0.004817 + 0.000014 * century))
+            + (2.0 * anomaly).sin() * (0.019993 - 0.000101 * century)
+            + (3.0 * anomaly).sin() * 0.000289;
+        let true_longitude = mean_longitude + equation_of_center;
+        let omega = (125.04 - 1934.136 * century).to_radians();
+        let apparent_longitude = true_longitude - 0.00569 - 0.00478 * omega.sin();
+
+        let mean_obliquity = 23.0
+            + (26.0
+                + (21.448 - century * (46.815 + century * (0.00059 - century * 0.001813))) / 60.0)
+                / 60.0;
+        let obliquity = (mean_obliquity + 0.00256 * omega.cos()).to_radians();
+
+        let declination = (obliquity.sin() * apparent_longitude.to_radians().sin()).asin();
+
+        let y = (obliquity / 2.0).tan().powi(2);
+        let l0 = mean_longitude.to_radians();
+        let equation_of_time = 4.0
+            * (y * (2.0 * l0).sin() - 2.0 * eccentricity * anomaly.sin()
+                + 4.0 * eccentricity * y * anomaly.sin() * (2.0 * l0).cos()
+                - 0.5 * y * y * (4.0 * l0).sin()
+                - 1.25 * eccentricity * eccentricity * (2.0 * anomaly).sin())
+            .to_degrees();
+
+        Self {
+            declination,
+            equation_of_time,
+        }
+    }
+}
+
+/// Dims the LEDs between sunset and sunrise, the brightness ramps during the civil twilight
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SolarDimming {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Shifts the ramps, e.g. 30 dims half an hour after sunset
+    #[serde(default)]
+    pub sunrise_offset_mins: i32,
+    #[serde(default)]
+    pub sunset_offset_mins: i32,
+}
+
+/// 0.0 before `start`, 1.0 after `end`, linear in between
+fn ramp(datetime: OffsetDateTime, start: OffsetDateTime, end: OffsetDateTime) -> f32 {
+    if end <= start {
+        return if datetime >= end { 1.0 } else { 0.0 };
+    }
+    ((datetime - start) / (end - start)).clamp(0.0, 1.0) as f32
+}
+
+impl SolarDimming {
+    /// 1.0 during the day, 0.0 at night and in between during the twilight
+    pub fn daylight(&self, datetime: OffsetDateTime) -> f32 {
+        let times = sun_times(
+            datetime.date(),
+            datetime.offset(),
+            self.latitude,
+            self.longitude,
+        );
+        let (sunrise, sunset) = match times.sun {
+            Crossing::Times { rise, set } => (rise, set),
+            Crossing::AlwaysAbove => return 1.0,
+            Crossing::AlwaysBelow => return 0.0,
+        };
+        let (dawn, dusk) = match times.civil {
+            Crossing::Times { rise, set } => (rise, set),
+            _ => (sunrise - DEFAULT_TWILIGHT, sunset + DEFAULT_TWILIGHT),
+        };
+
+        let sunrise_offset = Duration::minutes(self.sunrise_offset_mins.into());
+        let sunset_offset = Duration::minutes(self.sunset_offset_mins.into());
+        let morning = ramp(datetime, dawn + sunrise_offset, sunrise + sunrise_offset);
+        let evening = 1.0 - ramp(datetime, sunset + sunset_offset, dusk + sunset_offset);
+        morning.min(evening)
+    }
+
+    /// Brightness between `night` and `day`, perceptually linear during the ramps
+    pub fn brightness(&self, datetime: OffsetDateTime, day: u8, night: u8) -> u8 {
+        let daylight = self.daylight(datetime);
+        (night as f32 + (day as f32 - night as f32) * daylight).round() as u8
+    }
+}
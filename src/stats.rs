@@ -0,0 +1,147 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+// Same bands as the LED colors, see get_co2_band() and get_pm25_band()
+pub const CO2_THRESHOLDS: [u16; 3] = [1000, 1500, 2000];
+pub const PM25_THRESHOLDS: [u16; 4] = [12, 35, 55, 150];
+
+/// Number of finished days kept in the history
+pub const DAYS_KEPT: usize = 7;
+
+// A sample represents the time since the previous one, but at most this long (e.g. after a reboot)
+const MAX_SAMPLE_SECS: i64 = 5 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BandMinutes {
+    pub threshold: u16,
+    pub minutes: f32,
+}
+
+/// Time weighted aggregate of one measured quantity
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricStats {
+    pub min: Option<u16>,
+    pub max: Option<u16>,
+    pub mean: Option<f32>,
+    /// Time weighted exposure, ppm·h or µg/m³·h
+    pub exposure: f32,
+    /// Time covered by the samples
+    pub hours: f32,
+    /// Time spent above each threshold
+    pub above: Vec<BandMinutes>,
+}
+
+impl MetricStats {
+    fn new(thresholds: &[u16]) -> Self {
+        Self {
+            min: None,
+            max: None,
+            mean: None,
+            exposure: 0.0,
+            hours: 0.0,
+            above: thresholds
+                .iter()
+                .map(|&threshold| BandMinutes {
+                    threshold,
+                    minutes: 0.0,
+                })
+                .collect(),
+        }
+    }
+
+    fn record(&mut self, value: u16, secs: i64) {
+        self.min = Some(self.min.map_or(value, |min| min.min(value)));
+        self.max = Some(self.max.map_or(value, |max| max.max(value)));
+
+        let hours = secs as f32 / 3600.0;
+        self.exposure += value as f32 * hours;
+        self.hours += hours;
+        if self.hours > 0.0 {
+            self.mean = Some(self.exposure / self.hours);
+        }
+
+        self.above
+            .iter_mut()
+            .filter(|band| value > band.threshold)
+            .for_each(|band| band.minutes += secs as f32 / 60.0);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DailyStats {
+    /// Local date, YYYY-MM-DD
+    pub date: String,
+    pub co2: MetricStats,
+    pub pm25: MetricStats,
+}
+
+impl DailyStats {
+    fn new(date: String) -> Self {
+        Self {
+            date,
+            co2: MetricStats::new(&CO2_THRESHOLDS),
+            pm25: MetricStats::new(&PM25_THRESHOLDS),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DailyStatsHistory {
+    pub today: Option<DailyStats>,
+    /// Finished days, the most recent first
+    pub days: VecDeque<DailyStats>,
+    last_sample_at: Option<i64>,
+}
+
+pub fn format_date(datetime: &OffsetDateTime) -> String {
+    format!(
+        "{}-{:02}-{:02}",
+        datetime.year(),
+        datetime.month() as u8,
+        datetime.day()
+    )
+}
+
+impl DailyStatsHistory {
+    /// Adds a sample taken at local `datetime`, returns true when a day has been finished
+    pub fn record(
+        &mut self,
+        datetime: OffsetDateTime,
+        co2: Option<u16>,
+        pm25: Option<u16>,
+    ) -> bool {
+        let date = format_date(&datetime);
+        let timestamp = datetime.unix_timestamp();
+
+        let mut rolled_over = false;
+        if self.today.as_ref().map(|today| &today.date) != Some(&date) {
+            if let Some(finished) = self.today.take() {
+                self.days.push_front(finished);
+                self.days.truncate(DAYS_KEPT);
+                rolled_over = true;
+            }
+            self.today = Some(DailyStats::new(date));
+        }
+
+        // the first sample of the day only covers the time since midnight
+        let since_midnight = (datetime.time() - time::Time::MIDNIGHT).whole_seconds();
+        let secs = self
+            .last_sample_at
+            .map_or(0, |last| timestamp - last)
+            .clamp(0, MAX_SAMPLE_SECS)
+            .min(since_midnight);
+        self.last_sample_at = Some(timestamp);
+
+        let today = self.today.as_mut().unwrap();
+        if let Some(co2) = co2 {
+            today.co2.record(co2, secs);
+        }
+        if let Some(pm25) = pm25 {
+            today.pm25.record(pm25, secs);
+        }
+
+        rolled_over
+    }
+}
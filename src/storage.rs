@@ -0,0 +1,52 @@
+use anyhow::Result;
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use serde::{de::DeserializeOwned, Serialize};
+
+const NAMESPACE: &str = "vindriktning";
+
+/// JSON values persisted in the default NVS partition, keys are limited to 15 characters
+pub struct Storage {
+    nvs: EspNvs<NvsDefault>,
+}
+
+impl Storage {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self> {
+        let nvs = EspNvs::new(partition, NAMESPACE, true)?;
+        Ok(Self { nvs })
+    }
+
+    pub fn load<T>(&self, key: &str) -> Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let Some(length) = self.nvs.blob_len(key)? else {
+            return Ok(None);
+        };
+
+        let mut buffer = vec![0; length];
+        match self.nvs.get_blob(key, &mut buffer)? {
+            Some(data) => Ok(Some(serde_json::from_slice(data)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn store<T>(&mut self, key: &str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        let data = serde_json::to_vec(value)?;
+        self.nvs.set_blob(key, &data)?;
+        Ok(())
+    }
+
+    /// Loads and removes a value, for the ones meant for the next boot only
+    pub fn take<T>(&mut self, key: &str) -> Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        // removed even when it can't be parsed, it would fail on every boot
+        let value = self.load(key);
+        self.nvs.remove(key)?;
+        value
+    }
+}
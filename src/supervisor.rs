@@ -0,0 +1,225 @@
+//! Failure policies of the subsystems: retried with a backoff, left out or restarting
+
+use std::fmt::{self, Debug, Display};
+use std::time::{Duration, Instant};
+
+use log::*;
+use serde::{Deserialize, Serialize};
+
+/// A device the supervisor (re)initializes and uses
+pub trait Driver {
+    type Output;
+    type Error: Debug;
+
+    /// Brings the device to a known state, called again after any failure as the device may
+    /// have reset meanwhile
+    fn init(&mut self) -> Result<(), Self::Error>;
+
+    fn run(&mut self) -> Result<Self::Output, Self::Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    /// Retries with an exponential backoff from `initial_backoff` up to `max_backoff`, after
+    /// `degrade_after` failures in a row the subsystem counts as degraded but is still retried
+    Degrade {
+        degrade_after: u32,
+        initial_backoff: Duration,
+        max_backoff: Duration,
+    },
+    /// Retries right away, restarts the device after `restart_after` failures in a row
+    Restart { restart_after: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    /// Not initialized yet
+    Starting,
+    Running,
+    /// Failed, retried after the backoff
+    Retrying,
+    /// Failed too often, the device runs without it
+    Degraded,
+}
+
+/// Why the firmware restarted the device on purpose, recorded for the next boot
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum RestartReason {
+    /// `POST /restart`
+    UserRequest,
+    /// Into a new firmware
+    Update { version: String },
+    /// A subsystem kept failing
+    Unrecoverable { subsystem: String, error: String },
+    /// A loop took longer than its liveness budget
+    Stalled {
+        task: String,
+        elapsed_secs: u64,
+        budget_secs: u64,
+    },
+}
+
+impl Display for RestartReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UserRequest => write!(f, "requested by the user"),
+            Self::Update { version } => write!(f, "update to {}", version),
+            Self::Unrecoverable { subsystem, error } => {
+                write!(f, "{} failed: {}", subsystem, error)
+            }
+            Self::Stalled {
+                task,
+                elapsed_secs,
+                budget_secs,
+            } => write!(
+                f,
+                "{} loop stalled for {} s, budget {} s",
+                task, elapsed_secs, budget_secs
+            ),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome<T> {
+    Ok(T),
+    /// Failed, backing off, or just (re)initialized
+    Unavailable,
+    /// The device has to be restarted
+    Restart(RestartReason),
+}
+
+/// A driver run under a `Policy`
+pub struct Supervised<D: Driver> {
+    name: &'static str,
+    driver: D,
+    policy: Policy,
+    initialized: bool,
+    consecutive_failures: u32,
+    retry_at: Option<Instant>,
+    last_error: Option<String>,
+}
+
+impl<D: Driver> Supervised<D> {
+    pub fn new(name: &'static str, driver: D, policy: Policy) -> Self {
+        Self {
+            name,
+            driver,
+            policy,
+            initialized: false,
+            consecutive_failures: 0,
+            retry_at: None,
+            last_error: None,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        match self.policy {
+            _ if self.consecutive_failures == 0 && self.initialized => State::Running,
+            _ if self.consecutive_failures == 0 => State::Starting,
+            Policy::Degrade { degrade_after, .. } if self.consecutive_failures >= degrade_after => {
+                State::Degraded
+            }
+            _ => State::Retrying,
+        }
+    }
+
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    fn backing_off(&self, now: Instant) -> bool {
+        self.retry_at.is_some_and(|retry_at| now < retry_at)
+    }
+
+    /// Initializes the driver unless it is backing off, `run()` does it too when needed
+    pub fn start(&mut self, now: Instant) -> Outcome<()> {
+        if self.backing_off(now) {
+            return Outcome::Unavailable;
+        }
+        match self.driver.init() {
+            Ok(()) => {
+                if self.consecutive_failures > 0 {
+                    info!("{} reinitialized", self.name);
+                }
+                self.initialized = true;
+                Outcome::Ok(())
+            }
+            Err(e) => self.failed(now, e),
+        }
+    }
+
+    /// Runs the driver, a (re)initialized one is only run on the next call as sensors need a
+    /// measurement interval to settle
+    pub fn run(&mut self, now: Instant) -> Outcome<D::Output> {
+        if !self.initialized {
+            return match self.start(now) {
+                Outcome::Restart(reason) => Outcome::Restart(reason),
+                Outcome::Ok(()) | Outcome::Unavailable => Outcome::Unavailable,
+            };
+        }
+        if self.backing_off(now) {
+            return Outcome::Unavailable;
+        }
+        match self.driver.run() {
+            Ok(output) => {
+                if self.consecutive_failures > 0 {
+                    info!(
+                        "{} recovered after {} failures",
+                        self.name, self.consecutive_failures
+                    );
+                }
+                self.consecutive_failures = 0;
+                self.retry_at = None;
+                Outcome::Ok(output)
+            }
+            Err(e) => self.failed(now, e),
+        }
+    }
+
+    fn failed<T>(&mut self, now: Instant, error: D::Error) -> Outcome<T> {
+        let error = format!("{:?}", error);
+        self.initialized = false;
+        self.consecutive_failures += 1;
+        self.last_error = Some(error.clone());
+
+        match self.policy {
+            Policy::Degrade {
+                degrade_after,
+                initial_backoff,
+                max_backoff,
+            } => {
+                let exponent = (self.consecutive_failures - 1).min(16);
+                let backoff = initial_backoff
+                    .saturating_mul(1 << exponent)
+                    .min(max_backoff);
+                self.retry_at = Some(now + backoff);
+                if self.consecutive_failures == degrade_after {
+                    error!(
+                        "{} failed {} times in a row, running without it: {}",
+                        self.name, self.consecutive_failures, error
+                    );
+                } else {
+                    warn!("{} failed, retrying in {:?}: {}", self.name, backoff, error);
+                }
+                Outcome::Unavailable
+            }
+            Policy::Restart { restart_after } if self.consecutive_failures >= restart_after => {
+                error!(
+                    "{} failed {} times in a row: {}",
+                    self.name, self.consecutive_failures, error
+                );
+                Outcome::Restart(RestartReason::Unrecoverable {
+                    subsystem: self.name.into(),
+                    error,
+                })
+            }
+            Policy::Restart { .. } => {
+                warn!("{} failed, retrying: {}", self.name, error);
+                Outcome::Unavailable
+            }
+        }
+    }
+}
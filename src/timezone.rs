@@ -0,0 +1,107 @@
+//! Time zone of the schedules and the local time, an IANA name or a POSIX TZ string
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use log::error;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use time::{OffsetDateTime, UtcOffset};
+use time_tz::posix_tz::PosixTz;
+use time_tz::{timezones, Offset, OffsetDateTimeExt, TimeZone, Tz};
+
+/// IANA time zone, e.g. `Europe/Prague`, or a POSIX TZ string, e.g. `CET-1CEST,M3.5.0,M10.5.0/3`
+#[derive(Debug, Clone)]
+pub enum Timezone {
+    Iana(&'static Tz),
+    /// Validated when parsed, `PosixTz` borrows the string so it is parsed again when used
+    Posix(String),
+}
+
+impl Default for Timezone {
+    fn default() -> Self {
+        Self::Iana(timezones::db::UTC)
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(tz) = timezones::get_by_name(s) {
+            return Ok(Self::Iana(tz));
+        }
+        match PosixTz::parse(s) {
+            Ok(_) => Ok(Self::Posix(s.into())),
+            Err(e) => Err(anyhow!(
+                "{:?} is neither an IANA time zone nor a POSIX TZ string: {}",
+                s,
+                e
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Timezone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Iana(tz) => f.write_str(tz.name()),
+            Self::Posix(spec) => f.write_str(spec),
+        }
+    }
+}
+
+impl Serialize for Timezone {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timezone {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+impl Timezone {
+    /// `TIMEZONE` of `.env`, UTC without it or when it's invalid
+    pub fn from_config(value: Option<&str>) -> Self {
+        value
+            .and_then(|value| {
+                value
+                    .parse()
+                    .map_err(|e| error!("Invalid TIMEZONE, using UTC: {}", e))
+                    .ok()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Offset from UTC at the given instant
+    pub fn offset(&self, datetime: OffsetDateTime) -> UtcOffset {
+        match self {
+            Self::Iana(tz) => tz.get_offset_utc(&datetime).to_utc(),
+            Self::Posix(spec) => PosixTz::parse(spec)
+                .ok()
+                .and_then(|tz| tz.get_offset(&datetime).ok())
+                // only fails for dates time can't represent
+                .map_or(UtcOffset::UTC, |offset| offset.to_utc()),
+        }
+    }
+
+    /// The same instant in local time
+    pub fn convert(&self, datetime: OffsetDateTime) -> OffsetDateTime {
+        match self {
+            Self::Iana(tz) => datetime.to_timezone(*tz),
+            Self::Posix(_) => datetime.to_offset(self.offset(datetime)),
+        }
+    }
+}
+
+/// `+01:00` style
+pub fn format_offset(offset: UtcOffset) -> String {
+    let (hours, minutes, _) = offset.as_hms();
+    let sign = if offset.is_negative() { '-' } else { '+' };
+    format!("{}{:02}:{:02}", sign, hours.abs(), minutes.abs())
+}
@@ -0,0 +1,300 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use log::*;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+
+use crate::schedule::LocalTime;
+use crate::signature;
+
+/// Version the running firmware was built as, compared against the manifest
+pub const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+// Used until one is set with `PUT /ota/config`
+const MANIFEST_URL: Option<&str> = option_env!("OTA_MANIFEST_URL");
+
+pub const MANIFEST_MAX_LEN: usize = 4096;
+
+/// Latest firmware, published next to the image
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: Version,
+    /// Image URL, `make bin` output
+    pub url: String,
+    /// Hex SHA-256 of the image
+    pub sha256: String,
+    /// Older firmwares must update to an intermediate version first
+    #[serde(default)]
+    pub min_version: Option<Version>,
+    /// Share of the devices that install it, 0-100
+    #[serde(default = "full_rollout")]
+    pub rollout_percent: u8,
+}
+
+fn full_rollout() -> u8 {
+    100
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decision {
+    UpToDate,
+    /// The running version is older than `min_version`
+    Unsupported,
+    /// The device is not part of the rollout yet
+    NotInRollout,
+    Install,
+}
+
+impl Manifest {
+    pub fn decide(&self, current: &Version, device_id: &str) -> Decision {
+        if self.version <= *current {
+            Decision::UpToDate
+        } else if self.min_version.as_ref().is_some_and(|min| current < min) {
+            Decision::Unsupported
+        } else if rollout_bucket(device_id, &self.version) >= self.rollout_percent {
+            Decision::NotInRollout
+        } else {
+            Decision::Install
+        }
+    }
+
+    pub fn sha256(&self) -> Result<[u8; 32]> {
+        parse_sha256(&self.sha256)
+    }
+}
+
+/// Stable 0-99 bucket of the device for a version, raising the percentage only adds devices
+/// and each version picks different early adopters
+pub fn rollout_bucket(device_id: &str, version: &Version) -> u8 {
+    let hash = Sha256::new()
+        .chain_update(device_id)
+        .chain_update(":")
+        .chain_update(version.to_string())
+        .finalize();
+    (u16::from_be_bytes([hash[0], hash[1]]) % 100) as u8
+}
+
+pub fn parse_sha256(hex: &str) -> Result<[u8; 32]> {
+    signature::parse_hex(hex).map_err(|e| anyhow!("Invalid SHA-256: {}", e))
+}
+
+/// Parses a fetched manifest, one with an invalid SHA-256 fails before anything is downloaded
+pub fn parse_manifest(body: &[u8]) -> Result<Manifest> {
+    if body.len() > MANIFEST_MAX_LEN {
+        bail!("Manifest larger than {} bytes", MANIFEST_MAX_LEN);
+    }
+    let manifest: Manifest = serde_json::from_slice(body)?;
+    manifest.sha256()?;
+    Ok(manifest)
+}
+
+/// Local time span the updates are installed in, `end` at or before `start` spans over midnight
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MaintenanceWindow {
+    pub start: LocalTime,
+    pub end: LocalTime,
+}
+
+impl MaintenanceWindow {
+    pub fn contains(&self, datetime: OffsetDateTime) -> bool {
+        let now = LocalTime::new(datetime.hour(), datetime.minute());
+        if self.end <= self.start {
+            now >= self.start || now < self.end
+        } else {
+            now >= self.start && now < self.end
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpdateConfig {
+    /// Checks are disabled without it
+    pub manifest_url: Option<String>,
+    pub check_interval_mins: u32,
+    /// Installs any time without it
+    pub window: Option<MaintenanceWindow>,
+}
+
+impl Default for UpdateConfig {
+    fn default() -> Self {
+        Self {
+            manifest_url: MANIFEST_URL.map(Into::into),
+            check_interval_mins: 6 * 60,
+            window: Some(MaintenanceWindow {
+                start: LocalTime::new(2, 0),
+                end: LocalTime::new(5, 0),
+            }),
+        }
+    }
+}
+
+impl UpdateConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.check_interval_mins == 0 {
+            bail!("check_interval_mins must be positive");
+        }
+        if let Some(url) = &self.manifest_url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                bail!("Invalid manifest URL {:?}", url);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum CheckResult {
+    UpToDate,
+    Unsupported {
+        version: Version,
+        min_version: Option<Version>,
+    },
+    NotInRollout {
+        version: Version,
+        rollout_percent: u8,
+    },
+    /// Waits for the maintenance window
+    Pending {
+        version: Version,
+    },
+    /// Written, the device restarts into it
+    Installed {
+        version: Version,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckStatus {
+    pub current_version: &'static str,
+    pub last_check_age_secs: Option<u64>,
+    pub last_check: Option<CheckResult>,
+    pub check_requested: bool,
+}
+
+/// Periodic manifest checks, the checks themselves run in the caller's thread as they block
+pub struct UpdateChecker {
+    config: UpdateConfig,
+    last_check: Option<(Instant, CheckResult)>,
+    /// `POST /ota/check`, installs outside of the maintenance window too
+    requested: bool,
+}
+
+impl UpdateChecker {
+    pub fn new(config: UpdateConfig) -> Self {
+        Self {
+            config,
+            last_check: None,
+            requested: false,
+        }
+    }
+
+    pub fn config(&self) -> &UpdateConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: UpdateConfig) {
+        self.config = config;
+        // check the new manifest right away
+        self.last_check = None;
+    }
+
+    pub fn request_check(&mut self) {
+        self.requested = true;
+    }
+
+    /// Whether a check should run now, an update waiting for the window is checked again once
+    /// the window opens
+    pub fn due(&self, in_window: bool, now: Instant) -> bool {
+        if self.config.manifest_url.is_none() {
+            return false;
+        }
+        match &self.last_check {
+            _ if self.requested => true,
+            None => true,
+            Some((_, CheckResult::Pending { .. })) if in_window => true,
+            Some((checked_at, _)) => {
+                now.saturating_duration_since(*checked_at)
+                    >= Duration::from_secs(self.config.check_interval_mins as u64 * 60)
+            }
+        }
+    }
+
+    /// Whether an update may be installed at the local `datetime`, `None` when the time is unknown
+    pub fn in_window(&self, datetime: Option<OffsetDateTime>) -> bool {
+        match (&self.config.window, datetime) {
+            (None, _) => true,
+            (Some(window), Some(datetime)) => window.contains(datetime),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Starts a check, returns the manifest URL and whether the window may be ignored
+    pub fn start_check(&mut self) -> Option<(String, bool)> {
+        let url = self.config.manifest_url.clone()?;
+        let forced = std::mem::take(&mut self.requested);
+        Some((url, forced))
+    }
+
+    pub fn set_result(&mut self, result: CheckResult, now: Instant) {
+        match &result {
+            CheckResult::Failed { error } => error!("Update check failed: {}", error),
+            result => info!("Update check: {:?}", result),
+        }
+        self.last_check = Some((now, result));
+    }
+
+    pub fn status(&self, now: Instant) -> CheckStatus {
+        CheckStatus {
+            current_version: CURRENT_VERSION,
+            last_check_age_secs: self
+                .last_check
+                .as_ref()
+                .map(|(checked_at, _)| now.saturating_duration_since(*checked_at).as_secs()),
+            last_check: self.last_check.as_ref().map(|(_, result)| result.clone()),
+            check_requested: self.requested,
+        }
+    }
+}
+
+/// Checks the manifest against the `current` version and installs a newer firmware if
+/// `may_install`, e.g. in the maintenance window. `fetch_manifest` and `install` do the HTTP
+/// requests and the flash writes, the caller restarts the device after `CheckResult::Installed`.
+pub fn check(
+    current: &str,
+    device_id: &str,
+    may_install: bool,
+    fetch_manifest: impl FnOnce() -> Result<Manifest>,
+    install: impl FnOnce(&Manifest) -> Result<()>,
+) -> CheckResult {
+    let result = || -> Result<CheckResult> {
+        let manifest = fetch_manifest()?;
+        let current = Version::parse(current)?;
+        let version = manifest.version.clone();
+        Ok(match manifest.decide(&current, device_id) {
+            Decision::UpToDate => CheckResult::UpToDate,
+            Decision::Unsupported => CheckResult::Unsupported {
+                version,
+                min_version: manifest.min_version,
+            },
+            Decision::NotInRollout => CheckResult::NotInRollout {
+                version,
+                rollout_percent: manifest.rollout_percent,
+            },
+            Decision::Install if !may_install => CheckResult::Pending { version },
+            Decision::Install => {
+                install(&manifest)?;
+                CheckResult::Installed { version }
+            }
+        })
+    };
+    result().unwrap_or_else(|e| CheckResult::Failed {
+        error: e.to_string(),
+    })
+}
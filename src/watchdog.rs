@@ -0,0 +1,43 @@
+//! ESP-IDF task watchdog, it panics when a subscribed task isn't fed within
+//! `CONFIG_ESP_TASK_WDT_TIMEOUT_S`. Subscriptions belong to the calling task.
+
+use std::ptr;
+use std::time::Duration;
+
+use esp_idf_svc::sys::{self, esp, EspError};
+
+use crate::utils::sleep_ms;
+
+// Well below `CONFIG_ESP_TASK_WDT_TIMEOUT_S`
+const FEED_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Subscribes the calling task
+pub fn subscribe() -> Result<(), EspError> {
+    esp!(unsafe { sys::esp_task_wdt_add(ptr::null_mut()) })
+}
+
+/// Feeds the subscription of the calling task
+pub fn feed() {
+    unsafe { sys::esp_task_wdt_reset() };
+}
+
+/// Sleeps for longer than the watchdog timeout, feeding it meanwhile
+pub fn sleep_fed(ms: u64) {
+    let mut remaining = ms;
+    while remaining > 0 {
+        let chunk = remaining.min(FEED_INTERVAL.as_millis() as u64);
+        sleep_ms(chunk);
+        feed();
+        remaining -= chunk;
+    }
+}
+
+/// Runs `f` unwatched, for work with its own timeouts that may take longer than the watchdog
+pub fn suspended<T>(f: impl FnOnce() -> T) -> T {
+    unsafe { sys::esp_task_wdt_delete(ptr::null_mut()) };
+    let result = f();
+    if let Err(e) = subscribe() {
+        log::error!("Failed to subscribe to the task watchdog again: {}", e);
+    }
+    result
+}
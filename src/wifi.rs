@@ -1,12 +1,19 @@
 use embedded_svc::wifi::{ClientConfiguration, Configuration};
 use esp_idf_svc::hal::peripheral;
-use esp_idf_svc::sys::esp_wifi_set_max_tx_power;
+use esp_idf_svc::ipv4;
+use esp_idf_svc::netif::{EspNetif, NetifConfiguration, NetifStack};
+use esp_idf_svc::sys;
+use esp_idf_svc::sys::{esp, esp_wifi_set_max_tx_power};
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
-    wifi::{BlockingWifi, EspWifi},
+    wifi::{BlockingWifi, EspWifi, WifiDriver},
 };
 use log::*;
+use serde::Serialize;
+use std::ffi::CString;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
+use crate::network::{self, Ipv4Config, NetworkConfig};
 use crate::utils::sleep_ms;
 
 const WIFI_SSID: &str = env!("WIFI_SSID");
@@ -17,11 +24,12 @@ const WIFI_PASSWORD: &str = env!("WIFI_PASSWORD");
  * see https://github.com/esp-rs/esp-idf-svc/issues/304#issuecomment-1865823612
  */
 pub trait WifiConnectFix {
-    fn connect_with_retry(&mut self) -> anyhow::Result<()>;
+    /// `ipv6` creates the link-local address after each connect, SLAAC follows from it
+    fn connect_with_retry(&mut self, ipv6: bool) -> anyhow::Result<()>;
 }
 
 impl WifiConnectFix for BlockingWifi<EspWifi<'_>> {
-    fn connect_with_retry(&mut self) -> anyhow::Result<()> {
+    fn connect_with_retry(&mut self, ipv6: bool) -> anyhow::Result<()> {
         let mut retry_delay_ms = 1_000;
         loop {
             info!("Connecting wifi...");
@@ -44,6 +52,13 @@ impl WifiConnectFix for BlockingWifi<EspWifi<'_>> {
             }
         }
 
+        if ipv6 {
+            let netif = self.wifi().sta_netif().handle();
+            if let Err(e) = esp!(unsafe { sys::esp_netif_create_ip6_linklocal(netif) }) {
+                error!("Failed to enable IPv6: {}", e);
+            }
+        }
+
         info!("Waiting for DHCP lease...");
 
         self.wait_netif_up()?;
@@ -55,8 +70,23 @@ impl WifiConnectFix for BlockingWifi<EspWifi<'_>> {
 pub fn wifi(
     modem: impl peripheral::Peripheral<P = esp_idf_svc::hal::modem::Modem> + 'static,
     sysloop: EspSystemEventLoop,
+    network: &NetworkConfig,
+    hostname: &str,
 ) -> anyhow::Result<BlockingWifi<EspWifi<'static>>> {
-    let esp_wifi = EspWifi::new(modem, sysloop.clone(), None)?;
+    // The addressing is fixed when the netif is created, config changes apply after a restart
+    let mut sta_netif = EspNetif::new_with_conf(&NetifConfiguration {
+        ip_configuration: Some(ip_configuration(&network.ipv4)?),
+        ..NetifConfiguration::wifi_default_client()
+    })?;
+    // sent with DHCP requests, before the first one, the device still connects without it
+    if let Err(e) = sta_netif.set_hostname(hostname) {
+        error!("Failed to set the DHCP hostname {:?}: {}", hostname, e);
+    }
+    let esp_wifi = EspWifi::wrap_all(
+        WifiDriver::new(modem, sysloop.clone(), None)?,
+        sta_netif,
+        EspNetif::new(NetifStack::Ap)?,
+    )?;
 
     let mut wifi = BlockingWifi::wrap(esp_wifi, sysloop)?;
 
@@ -93,19 +123,175 @@ pub fn wifi(
     };
 
     wifi.set_configuration(&Configuration::Client(ClientConfiguration {
-        ssid: WIFI_SSID.try_into().unwrap(),
-        password: WIFI_PASSWORD.try_into().unwrap(),
+        ssid: WIFI_SSID
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("WIFI_SSID is longer than 32 bytes"))?,
+        password: WIFI_PASSWORD
+            .try_into()
+            .map_err(|_| anyhow::anyhow!("WIFI_PASSWORD is longer than 64 bytes"))?,
         channel,
         ..Default::default()
     }))?;
 
     wifi.start()?;
 
-    wifi.connect_with_retry()?;
+    wifi.connect_with_retry(network.ipv6)?;
 
     let ip_info = wifi.wifi().sta_netif().get_ip_info()?;
 
-    info!("Wifi DHCP info: {:?}", ip_info);
+    info!("Wifi IP info: {:?}", ip_info);
 
     Ok(wifi)
 }
+
+fn ip_configuration(ipv4: &Ipv4Config) -> anyhow::Result<ipv4::Configuration> {
+    let client = match ipv4 {
+        Ipv4Config::Dhcp => ipv4::ClientConfiguration::DHCP(Default::default()),
+        Ipv4Config::Static {
+            address,
+            netmask,
+            gateway,
+            dns,
+        } => {
+            let prefix_len = network::prefix_len(*netmask)?;
+            info!(
+                "Static IPv4 {}/{}, gateway {}, DNS {:?}",
+                address, prefix_len, gateway, dns
+            );
+            ipv4::ClientConfiguration::Fixed(ipv4::ClientSettings {
+                ip: *address,
+                subnet: ipv4::Subnet {
+                    gateway: *gateway,
+                    mask: ipv4::Mask(prefix_len),
+                },
+                dns: dns.first().copied(),
+                secondary_dns: dns.get(1).copied(),
+            })
+        }
+    };
+    Ok(ipv4::Configuration::Client(client))
+}
+
+/// Null before `wifi()` created it
+fn sta_netif() -> *mut sys::esp_netif_t {
+    unsafe { sys::esp_netif_get_handle_from_ifkey(c"WIFI_STA_DEF".as_ptr()) }
+}
+
+/// Sent with the following DHCP requests, `wifi()` sets it before the first one
+pub fn set_hostname(hostname: &str) -> anyhow::Result<()> {
+    let netif = sta_netif();
+    if netif.is_null() {
+        anyhow::bail!("Wifi isn't set up");
+    }
+    let hostname = CString::new(hostname)?;
+    esp!(unsafe { sys::esp_netif_set_hostname(netif, hostname.as_ptr()) })?;
+    Ok(())
+}
+
+/// Station connection, read from the driver so it works without the `BlockingWifi` handle
+#[derive(Debug, Serialize)]
+pub struct WifiStatus {
+    pub connected: bool,
+    pub ssid: Option<String>,
+    pub bssid: Option<String>,
+    /// dBm
+    pub rssi: Option<i8>,
+    pub channel: Option<u8>,
+    pub ip: Option<Ipv4Addr>,
+    pub netmask: Option<Ipv4Addr>,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns: Option<Ipv4Addr>,
+    /// Link-local and SLAAC addresses, empty unless IPv6 is enabled
+    pub ipv6: Vec<Ipv6Addr>,
+}
+
+fn ipv4_addr(addr: &sys::esp_ip4_addr_t) -> Option<Ipv4Addr> {
+    Some(Ipv4Addr::from(addr.addr.to_le_bytes())).filter(|ip| !ip.is_unspecified())
+}
+
+fn ipv6_addr(addr: &sys::esp_ip6_addr_t) -> Ipv6Addr {
+    let mut octets = [0_u8; 16];
+    for (chunk, word) in octets.chunks_exact_mut(4).zip(addr.addr) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    Ipv6Addr::from(octets)
+}
+
+pub fn status() -> WifiStatus {
+    let mut ap = sys::wifi_ap_record_t::default();
+    let connected = unsafe { sys::esp_wifi_sta_get_ap_info(&mut ap) } == sys::ESP_OK;
+
+    let netif = sta_netif();
+    let mut ip_info = sys::esp_netif_ip_info_t::default();
+    let has_ip_info = !netif.is_null()
+        && unsafe { sys::esp_netif_get_ip_info(netif, &mut ip_info) } == sys::ESP_OK;
+    let (ip, netmask, gateway) = if has_ip_info {
+        (
+            ipv4_addr(&ip_info.ip),
+            ipv4_addr(&ip_info.netmask),
+            ipv4_addr(&ip_info.gw),
+        )
+    } else {
+        (None, None, None)
+    };
+    let mut dns_info = sys::esp_netif_dns_info_t::default();
+    let dns = (!netif.is_null()
+        && unsafe {
+            sys::esp_netif_get_dns_info(
+                netif,
+                sys::esp_netif_dns_type_t_ESP_NETIF_DNS_MAIN,
+                &mut dns_info,
+            )
+        } == sys::ESP_OK)
+        .then(|| ipv4_addr(unsafe { &dns_info.ip.u_addr.ip4 }))
+        .flatten();
+    let mut ip6 = [sys::esp_ip6_addr_t::default(); sys::CONFIG_LWIP_IPV6_NUM_ADDRESSES as usize];
+    let ip6_count = if netif.is_null() {
+        0
+    } else {
+        unsafe { sys::esp_netif_get_all_ip6(netif, ip6.as_mut_ptr()) }
+    };
+    let ipv6 = ip6
+        .iter()
+        .take(ip6_count.max(0) as usize)
+        .map(ipv6_addr)
+        .collect();
+
+    if !connected {
+        return WifiStatus {
+            connected,
+            ssid: None,
+            bssid: None,
+            rssi: None,
+            channel: None,
+            ip,
+            netmask,
+            gateway,
+            dns,
+            ipv6,
+        };
+    }
+    let ssid_len = ap
+        .ssid
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(ap.ssid.len());
+    WifiStatus {
+        connected,
+        ssid: Some(String::from_utf8_lossy(&ap.ssid[..ssid_len]).into_owned()),
+        bssid: Some(
+            ap.bssid
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<_>>()
+                .join(":"),
+        ),
+        rssi: Some(ap.rssi),
+        channel: Some(ap.primary),
+        ip,
+        netmask,
+        gateway,
+        dns,
+        ipv6,
+    }
+}
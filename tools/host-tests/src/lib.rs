@@ -0,0 +1,45 @@
+//! Firmware modules without ESP-IDF dependencies, compiled for the host so they can be tested
+//! with mock drivers, see `tests/`
+
+#[path = "../../../src/alerts.rs"]
+pub mod alerts;
+#[path = "../../../src/animation.rs"]
+pub mod animation;
+#[path = "../../../src/bands.rs"]
+pub mod bands;
+#[path = "../../../src/brightness.rs"]
+pub mod brightness;
+#[path = "../../../src/color.rs"]
+pub mod color;
+#[path = "../../../src/display.rs"]
+pub mod display;
+#[path = "../../../src/filter.rs"]
+pub mod filter;
+#[path = "../../../src/gamma.rs"]
+pub mod gamma;
+#[path = "../../../src/humidity.rs"]
+pub mod humidity;
+#[path = "../../../src/leds.rs"]
+pub mod leds;
+#[path = "../../../src/liveness.rs"]
+pub mod liveness;
+#[path = "../../../src/logs.rs"]
+pub mod logs;
+#[path = "../../../src/network.rs"]
+pub mod network;
+#[path = "../../../src/palette.rs"]
+pub mod palette;
+#[path = "../../../src/schedule.rs"]
+pub mod schedule;
+#[path = "../../../src/signature.rs"]
+pub mod signature;
+#[path = "../../../src/solar.rs"]
+pub mod solar;
+#[path = "../../../src/stats.rs"]
+pub mod stats;
+#[path = "../../../src/supervisor.rs"]
+pub mod supervisor;
+#[path = "../../../src/timezone.rs"]
+pub mod timezone;
+#[path = "../../../src/update.rs"]
+pub mod update;
@@ -0,0 +1,570 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use host_tests::alerts::{
+    render_template, send_webhooks, AlertConfig, AlertEngine, Condition, Notification, Rule,
+    Sample, Status, Webhook,
+};
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+fn sample(co2: Option<u16>, pm25: Option<u16>) -> Sample {
+    Sample {
+        co2,
+        pm25,
+        clock_synced: true,
+    }
+}
+
+fn co2_rule() -> Rule {
+    Rule {
+        name: "co2_high".into(),
+        condition: Condition::Co2Above { threshold: 1500 },
+        for_secs: 10 * 60,
+        hysteresis: 100,
+        renotify_secs: 60 * 60,
+    }
+}
+
+fn engine(rules: Vec<Rule>) -> AlertEngine {
+    AlertEngine::new(AlertConfig {
+        rules,
+        webhooks: Vec::new(),
+    })
+}
+
+fn statuses(notifications: &[Notification]) -> Vec<(&str, Status)> {
+    notifications
+        .iter()
+        .map(|notification| (notification.rule.as_str(), notification.status))
+        .collect()
+}
+
+#[test]
+fn fires_after_the_condition_holds_long_enough() {
+    let mut engine = engine(vec![co2_rule()]);
+    let start = Instant::now();
+
+    assert!(engine.evaluate(&sample(Some(1600), None), start).is_empty());
+    assert!(engine
+        .evaluate(&sample(Some(1700), None), start + 9 * MINUTE)
+        .is_empty());
+    assert!(engine.active(start + 9 * MINUTE).is_empty());
+
+    let notifications = engine.evaluate(&sample(Some(1650), None), start + 10 * MINUTE);
+    assert_eq!(statuses(&notifications), [("co2_high", Status::Firing)]);
+    assert_eq!(notifications[0].value, Some(1650));
+    assert_eq!(notifications[0].threshold, Some(1500));
+
+    let active = engine.active(start + 12 * MINUTE);
+    assert_eq!(active.len(), 1);
+    assert_eq!(active[0].firing_secs, 12 * 60);
+}
+
+#[test]
+fn a_drop_before_the_delay_resets_it() {
+    let mut engine = engine(vec![co2_rule()]);
+    let start = Instant::now();
+
+    engine.evaluate(&sample(Some(1600), None), start);
+    engine.evaluate(&sample(Some(1400), None), start + 5 * MINUTE);
+    engine.evaluate(&sample(Some(1600), None), start + 6 * MINUTE);
+    assert!(engine
+        .evaluate(&sample(Some(1600), None), start + 12 * MINUTE)
+        .is_empty());
+    assert_eq!(
+        statuses(&engine.evaluate(&sample(Some(1600), None), start + 16 * MINUTE)),
+        [("co2_high", Status::Firing)]
+    );
+}
+
+#[test]
+fn resolves_below_the_hysteresis() {
+    let mut engine = engine(vec![Rule {
+        for_secs: 0,
+        ..co2_rule()
+    }]);
+    let start = Instant::now();
+
+    assert_eq!(
+        statuses(&engine.evaluate(&sample(Some(1501), None), start)),
+        [("co2_high", Status::Firing)]
+    );
+    // within the hysteresis
+    assert!(engine
+        .evaluate(&sample(Some(1450), None), start + MINUTE)
+        .is_empty());
+    assert_eq!(engine.active(start + MINUTE).len(), 1);
+
+    let notifications = engine.evaluate(&sample(Some(1400), None), start + 2 * MINUTE);
+    assert_eq!(statuses(&notifications), [("co2_high", Status::Resolved)]);
+    assert_eq!(notifications[0].value, Some(1400));
+    assert!(engine.active(start + 2 * MINUTE).is_empty());
+}
+
+#[test]
+fn renotifies_while_firing() {
+    let mut engine = engine(vec![Rule {
+        for_secs: 0,
+        ..co2_rule()
+    }]);
+    let start = Instant::now();
+    let high = sample(Some(2000), None);
+
+    assert_eq!(engine.evaluate(&high, start).len(), 1);
+    assert!(engine.evaluate(&high, start + 59 * MINUTE).is_empty());
+    assert_eq!(
+        statuses(&engine.evaluate(&high, start + 60 * MINUTE)),
+        [("co2_high", Status::Firing)]
+    );
+    assert!(engine.evaluate(&high, start + 119 * MINUTE).is_empty());
+
+    let mut once = self::engine(vec![Rule {
+        for_secs: 0,
+        renotify_secs: 0,
+        ..co2_rule()
+    }]);
+    assert_eq!(once.evaluate(&high, start).len(), 1);
+    assert!(once.evaluate(&high, start + 24 * 60 * MINUTE).is_empty());
+}
+
+#[test]
+fn missing_values_keep_the_state() {
+    let mut engine = engine(vec![Rule {
+        for_secs: 0,
+        ..co2_rule()
+    }]);
+    let start = Instant::now();
+
+    engine.evaluate(&sample(Some(2000), None), start);
+    assert!(engine
+        .evaluate(&sample(None, None), start + MINUTE)
+        .is_empty());
+    assert_eq!(engine.active(start + MINUTE).len(), 1);
+    assert_eq!(
+        statuses(&engine.evaluate(&sample(Some(800), None), start + 2 * MINUTE)),
+        [("co2_high", Status::Resolved)]
+    );
+}
+
+#[test]
+fn default_rules() {
+    let mut engine = AlertEngine::new(AlertConfig::default());
+    let start = Instant::now();
+    let offline = Sample {
+        co2: Some(600),
+        pm25: None,
+        clock_synced: false,
+    };
+
+    assert!(engine.evaluate(&offline, start).is_empty());
+    assert_eq!(
+        statuses(&engine.evaluate(&offline, start + 5 * MINUTE)),
+        [("sensor_offline", Status::Firing)]
+    );
+    assert_eq!(
+        statuses(&engine.evaluate(&offline, start + 10 * MINUTE)),
+        [("clock_unsynced", Status::Firing)]
+    );
+    // PM2.5 fires right away
+    assert_eq!(
+        statuses(&engine.evaluate(&sample(Some(600), Some(80)), start + 11 * MINUTE)),
+        [
+            ("pm25_high", Status::Firing),
+            ("sensor_offline", Status::Resolved),
+            ("clock_unsynced", Status::Resolved),
+        ]
+    );
+}
+
+#[test]
+fn new_config_resolves_the_removed_alerts() {
+    let mut engine = engine(vec![Rule {
+        for_secs: 0,
+        ..co2_rule()
+    }]);
+    let start = Instant::now();
+    engine.evaluate(&sample(Some(2000), None), start);
+
+    // renamed
+    let notifications = engine.set_config(AlertConfig {
+        rules: vec![Rule {
+            name: "co2_office".into(),
+            ..co2_rule()
+        }],
+        webhooks: Vec::new(),
+    });
+    assert_eq!(statuses(&notifications), [("co2_high", Status::Resolved)]);
+    assert_eq!(notifications[0].value, None);
+    assert_eq!(notifications[0].threshold, Some(1500));
+    assert!(engine.active(start).is_empty());
+    // the new rule starts over, waiting for its delay
+    assert!(engine
+        .evaluate(&sample(Some(2000), None), start + MINUTE)
+        .is_empty());
+}
+
+#[test]
+fn new_config_keeps_unchanged_rules() {
+    let pm25_rule = Rule {
+        name: "pm25_high".into(),
+        condition: Condition::Pm25Above { threshold: 55 },
+        for_secs: 0,
+        hysteresis: 5,
+        renotify_secs: 0,
+    };
+    let mut engine = engine(vec![
+        Rule {
+            for_secs: 0,
+            ..co2_rule()
+        },
+        pm25_rule.clone(),
+    ]);
+    let start = Instant::now();
+    engine.evaluate(&sample(Some(2000), Some(80)), start);
+
+    // reordered, the CO2 rule only with a new renotify interval
+    let notifications = engine.set_config(AlertConfig {
+        rules: vec![
+            pm25_rule,
+            Rule {
+                for_secs: 0,
+                renotify_secs: 0,
+                ..co2_rule()
+            },
+        ],
+        webhooks: Vec::new(),
+    });
+    assert!(notifications.is_empty());
+    let active: Vec<_> = engine
+        .active(start + MINUTE)
+        .into_iter()
+        .map(|alert| (alert.rule, alert.firing_secs))
+        .collect();
+    assert_eq!(
+        active,
+        [("pm25_high".to_string(), 60), ("co2_high".to_string(), 60)]
+    );
+    // still resolves
+    let notifications = engine.evaluate(&sample(Some(1300), Some(80)), start + 2 * MINUTE);
+    assert_eq!(statuses(&notifications), [("co2_high", Status::Resolved)]);
+
+    // a changed condition is a new rule
+    let notifications = engine.set_config(AlertConfig {
+        rules: vec![Rule {
+            name: "pm25_high".into(),
+            condition: Condition::Pm25Above { threshold: 100 },
+            for_secs: 0,
+            hysteresis: 5,
+            renotify_secs: 0,
+        }],
+        webhooks: Vec::new(),
+    });
+    assert_eq!(statuses(&notifications), [("pm25_high", Status::Resolved)]);
+}
+
+fn config(rules: Vec<Rule>, urls: &[&str]) -> AlertConfig {
+    AlertConfig {
+        rules,
+        webhooks: urls
+            .iter()
+            .map(|&url| Webhook {
+                url: url.into(),
+                template: None,
+            })
+            .collect(),
+    }
+}
+
+#[test]
+fn valid_configs() {
+    assert!(AlertConfig::default().validate().is_ok());
+    assert!(config(
+        vec![co2_rule()],
+        &["http://192.168.1.10:8080/alert", "https://ntfy.sh/air"]
+    )
+    .validate()
+    .is_ok());
+}
+
+#[test]
+fn invalid_configs_are_rejected() {
+    let named = |name: &str| Rule {
+        name: name.into(),
+        ..co2_rule()
+    };
+    assert!(config(vec![named("")], &[]).validate().is_err());
+    assert!(config(vec![named("co2"), named("co2")], &[])
+        .validate()
+        .is_err());
+    // never resolves
+    let hysteresis = |hysteresis| Rule {
+        hysteresis,
+        ..co2_rule()
+    };
+    assert!(config(vec![hysteresis(1499)], &[]).validate().is_ok());
+    assert!(config(vec![hysteresis(1500)], &[]).validate().is_err());
+    assert!(config(vec![hysteresis(2000)], &[]).validate().is_err());
+    for url in [
+        "",
+        "192.168.1.10/alert",
+        "ftp://192.168.1.10/alert",
+        "mqtt://broker",
+        "http://",
+        "https:///alert",
+    ] {
+        assert!(config(Vec::new(), &[url]).validate().is_err(), "{:?}", url);
+    }
+}
+
+#[test]
+fn config_json() {
+    let config: AlertConfig = serde_json::from_str(
+        r#"{
+            "rules": [
+                {"name": "stale", "condition": {"type": "sensor_offline"}},
+                {"name": "pm", "condition": {"type": "pm25_above", "threshold": 35}, "for_secs": 60}
+            ],
+            "webhooks": [{"url": "http://192.168.1.10:8080/alert"}]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(config.rules[0].condition, Condition::SensorOffline);
+    assert_eq!(
+        (config.rules[0].for_secs, config.rules[0].renotify_secs),
+        (0, 0)
+    );
+    assert_eq!(
+        config.rules[1].condition,
+        Condition::Pm25Above { threshold: 35 }
+    );
+    assert_eq!(config.webhooks[0].template, None);
+    assert!(serde_json::from_str::<AlertConfig>(
+        r#"{"rules": [{"name": "x", "condition": {"type": "co2_above"}}], "webhooks": []}"#
+    )
+    .is_err());
+}
+
+fn notification() -> Notification {
+    Notification {
+        rule: "co2_high".into(),
+        status: Status::Firing,
+        value: Some(1650),
+        threshold: Some(1500),
+    }
+}
+
+#[test]
+fn default_payload() {
+    let webhook = Webhook {
+        url: "http://192.168.1.10:8080/alert".into(),
+        template: None,
+    };
+    let payload = webhook.payload(&notification(), "a0b1c2d3e4f5", Some(1_718_454_896));
+    assert_eq!(
+        payload,
+        r#"{"alert":"co2_high","status":"firing","value":1650,"threshold":1500,"device":"a0b1c2d3e4f5","timestamp":1718454896}"#
+    );
+    assert!(serde_json::from_str::<serde_json::Value>(&payload).is_ok());
+}
+
+#[test]
+fn missing_values_render_as_null() {
+    let notification = Notification {
+        rule: "test".into(),
+        status: Status::Resolved,
+        value: None,
+        threshold: None,
+    };
+    assert_eq!(
+        render_template(
+            "{{status}} {{value}} {{threshold}} {{timestamp}}",
+            &notification,
+            "",
+            None
+        ),
+        r#""resolved" null null null"#
+    );
+}
+
+#[test]
+fn custom_template() {
+    let webhook = Webhook {
+        url: "http://ntfy.local/air".into(),
+        template: Some(
+            r#"{"title":{{rule}},"message":{"ppm":{{value}},"limit":{{threshold}}},"tags":[{{device}}]}"#
+                .into(),
+        ),
+    };
+    assert_eq!(
+        webhook.payload(&notification(), "a0b1c2d3e4f5", None),
+        r#"{"title":"co2_high","message":{"ppm":1650,"limit":1500},"tags":["a0b1c2d3e4f5"]}"#
+    );
+}
+
+#[test]
+fn strings_are_escaped() {
+    let notification = Notification {
+        rule: "co2 \"kitchen\"\n".into(),
+        ..notification()
+    };
+    let payload = render_template(r#"{"rule":{{rule}}}"#, &notification, "", None);
+    assert_eq!(payload, r#"{"rule":"co2 \"kitchen\"\n"}"#);
+    let value: serde_json::Value = serde_json::from_str(&payload).unwrap();
+    assert_eq!(value["rule"], "co2 \"kitchen\"\n");
+}
+
+/// Local HTTP server standing in for the webhook receivers, answers the requests with `statuses`
+/// in turn and returns the path and the body of each
+fn receiver(statuses: Vec<u16>) -> (String, JoinHandle<Vec<(String, String)>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let handle = thread::spawn(move || {
+        statuses
+            .into_iter()
+            .map(|status| {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let path = request_line.split(' ').nth(1).unwrap().to_string();
+                assert!(request_line.starts_with("POST "), "{}", request_line);
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    let header = header.trim_end();
+                    if header.is_empty() {
+                        break;
+                    }
+                    let (name, value) = header.split_once(':').unwrap();
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+                (path, String::from_utf8(body).unwrap())
+            })
+            .collect()
+    });
+    (base_url, handle)
+}
+
+/// Minimal HTTP client in place of the ESP-IDF one, fails on non-2xx responses like `post_json`
+fn post(url: &str, payload: &str) -> Result<()> {
+    let address = url
+        .strip_prefix("http://")
+        .ok_or_else(|| anyhow!("Unsupported URL {}", url))?;
+    let (host, path) = address.split_at(address.find('/').unwrap_or(address.len()));
+    let mut stream = TcpStream::connect(host)?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\ncontent-type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        if path.is_empty() { "/" } else { path },
+        host,
+        payload.len(),
+        payload
+    )?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let status: u16 = response
+        .split(' ')
+        .nth(1)
+        .ok_or_else(|| anyhow!("Invalid response {:?}", response))?
+        .parse()?;
+    if !(200..=299).contains(&status) {
+        bail!("Unexpected response code: {}", status);
+    }
+    Ok(())
+}
+
+#[test]
+fn webhooks_receive_their_payloads() {
+    let (base_url, receiver) = receiver(vec![200, 204]);
+    let webhooks = [
+        Webhook {
+            url: format!("{}/alert", base_url),
+            template: None,
+        },
+        Webhook {
+            url: format!("{}/ntfy/air", base_url),
+            template: Some(r#"{"title":{{rule}},"ppm":{{value}}}"#.into()),
+        },
+    ];
+    send_webhooks(
+        &webhooks,
+        &notification(),
+        "a0b1c2d3e4f5",
+        Some(1_718_454_896),
+        post,
+    )
+    .unwrap();
+
+    let received = receiver.join().unwrap();
+    assert_eq!(received.len(), 2);
+    assert_eq!(received[0].0, "/alert");
+    let body: serde_json::Value = serde_json::from_str(&received[0].1).unwrap();
+    assert_eq!(
+        body,
+        serde_json::json!({
+            "alert": "co2_high",
+            "status": "firing",
+            "value": 1650,
+            "threshold": 1500,
+            "device": "a0b1c2d3e4f5",
+            "timestamp": 1718454896
+        })
+    );
+    assert_eq!(
+        received[1],
+        (
+            "/ntfy/air".to_string(),
+            r#"{"title":"co2_high","ppm":1650}"#.to_string()
+        )
+    );
+}
+
+#[test]
+fn a_failing_webhook_doesnt_stop_the_others() {
+    let (base_url, receiver) = receiver(vec![500, 200]);
+    // nothing listens there anymore
+    let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed_url = format!("http://{}/alert", closed.local_addr().unwrap());
+    drop(closed);
+    let webhooks = [
+        Webhook {
+            url: format!("{}/first", base_url),
+            template: None,
+        },
+        Webhook {
+            url: closed_url,
+            template: None,
+        },
+        Webhook {
+            url: format!("{}/third", base_url),
+            template: None,
+        },
+    ];
+    let resolved = Notification {
+        status: Status::Resolved,
+        ..notification()
+    };
+    let result = send_webhooks(&webhooks, &resolved, "a0b1c2d3e4f5", None, post);
+    assert_eq!(result.unwrap_err().to_string(), "2 of 3 webhooks failed");
+
+    let received = receiver.join().unwrap();
+    let paths: Vec<&str> = received.iter().map(|(path, _)| path.as_str()).collect();
+    assert_eq!(paths, ["/first", "/third"]);
+    assert!(received[1].1.contains(r#""status":"resolved""#));
+}
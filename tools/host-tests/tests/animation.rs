@@ -0,0 +1,210 @@
+use std::time::{Duration, Instant};
+
+use host_tests::animation::{Indication, Indications, Pattern};
+use host_tests::color::{Color, LedPosition};
+use host_tests::palette::Palette;
+
+const RED: Color = Color {
+    r: 255,
+    g: 0,
+    b: 0,
+    brightness: None,
+};
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+/// Brightness of each LED, bottom to top
+fn levels(frame: [Color; 3]) -> [u8; 3] {
+    frame.map(|color| color.brightness.unwrap())
+}
+
+#[test]
+fn blink_alternates() {
+    let blink = Pattern::Blink {
+        color: RED,
+        on_ms: 200,
+        off_ms: 1_800,
+    };
+    assert_eq!(levels(blink.frame(0)), [255; 3]);
+    assert_eq!(levels(blink.frame(199)), [255; 3]);
+    assert_eq!(levels(blink.frame(200)), [0; 3]);
+    assert_eq!(levels(blink.frame(1_999)), [0; 3]);
+    assert_eq!(levels(blink.frame(2_000)), [255; 3]);
+    assert!(blink
+        .frame(0)
+        .iter()
+        .all(|color| color.r == 255 && color.g == 0));
+}
+
+#[test]
+fn pulse_flashes_and_fades_out() {
+    let pulse = Pattern::Pulse {
+        color: RED,
+        period_ms: 1_000,
+    };
+    let level = |elapsed_ms| levels(pulse.frame(elapsed_ms))[0];
+    assert_eq!(level(0), 0);
+    assert_eq!(level(50), 128);
+    assert_eq!(level(100), 255);
+    // (1 - 0.45 / 0.9)^2
+    assert_eq!(level(550), 64);
+    assert!((100..950).step_by(50).all(|t| level(t) >= level(t + 50)));
+    assert_eq!(level(1_000), 0);
+    assert_eq!(level(1_100), 255);
+}
+
+#[test]
+fn breathe_fades_in_and_out() {
+    let breathe = Pattern::Breathe {
+        color: RED,
+        period_ms: 3_000,
+    };
+    let level = |elapsed_ms| levels(breathe.frame(elapsed_ms))[0];
+    assert_eq!(level(0), 0);
+    assert_eq!(level(750), 128);
+    assert_eq!(level(1_500), 255);
+    assert_eq!(level(2_250), 128);
+    assert_eq!(level(3_000), 0);
+    assert!((0..1_500).step_by(100).all(|t| level(t) <= level(t + 100)));
+}
+
+#[test]
+fn chase_moves_bottom_to_top() {
+    let chase = Pattern::Chase {
+        color: RED,
+        step_ms: 150,
+    };
+    let lit = |elapsed_ms| {
+        let frame = levels(chase.frame(elapsed_ms));
+        assert_eq!(frame.iter().filter(|&&level| level == 255).count(), 1);
+        frame.iter().position(|&level| level == 255).unwrap()
+    };
+    assert_eq!(lit(0), LedPosition::Bottom as usize);
+    assert_eq!(lit(149), LedPosition::Bottom as usize);
+    assert_eq!(lit(150), LedPosition::Center as usize);
+    assert_eq!(lit(300), LedPosition::Top as usize);
+    assert_eq!(lit(450), LedPosition::Bottom as usize);
+}
+
+#[test]
+fn zero_periods_dont_divide_by_zero() {
+    let patterns = [
+        Pattern::Pulse {
+            color: RED,
+            period_ms: 0,
+        },
+        Pattern::Blink {
+            color: RED,
+            on_ms: 0,
+            off_ms: 0,
+        },
+        Pattern::Chase {
+            color: RED,
+            step_ms: 0,
+        },
+        Pattern::Breathe {
+            color: RED,
+            period_ms: 0,
+        },
+    ];
+    for pattern in patterns {
+        pattern.frame(1_234);
+    }
+}
+
+#[test]
+fn highest_indication_is_shown() {
+    let start = Instant::now();
+    let palette = Palette::default();
+    let mut indications = Indications::new(start);
+    assert_eq!(indications.shown(), None);
+    assert_eq!(indications.frame(&palette, start), None);
+
+    indications.insert(Indication::Provisioning, None, start);
+    indications.insert(Indication::Alert, None, start);
+    indications.insert(Indication::SensorFault, None, start);
+    assert_eq!(indications.shown(), Some(Indication::Alert));
+    assert_eq!(
+        indications.frame(&palette, start),
+        Some(Indication::Alert.pattern(&palette).frame(0))
+    );
+
+    indications.insert(Indication::OtaInProgress, None, start);
+    assert_eq!(indications.shown(), Some(Indication::OtaInProgress));
+    indications.remove(Indication::OtaInProgress, start);
+    indications.remove(Indication::Alert, start);
+    assert_eq!(indications.shown(), Some(Indication::SensorFault));
+    assert!(indications.is_active(Indication::Provisioning));
+    assert!(!indications.is_active(Indication::Alert));
+}
+
+#[test]
+fn animation_restarts_when_the_shown_indication_changes() {
+    let start = Instant::now();
+    let palette = Palette::default();
+    let mut indications = Indications::new(start);
+    let elapsed =
+        |indication: Indication, elapsed_ms| Some(indication.pattern(&palette).frame(elapsed_ms));
+
+    indications.insert(Indication::OtaInProgress, None, start);
+    assert_eq!(
+        indications.frame(&palette, start + ms(300)),
+        elapsed(Indication::OtaInProgress, 300)
+    );
+    // lower priority, the chase goes on
+    indications.insert(Indication::Alert, None, start + ms(300));
+    assert_eq!(
+        indications.frame(&palette, start + ms(450)),
+        elapsed(Indication::OtaInProgress, 450)
+    );
+    // the alert pulse starts from the beginning
+    indications.remove(Indication::OtaInProgress, start + ms(500));
+    assert_eq!(
+        indications.frame(&palette, start + ms(600)),
+        elapsed(Indication::Alert, 100)
+    );
+}
+
+#[test]
+fn timed_indications_expire() {
+    let start = Instant::now();
+    let mut indications = Indications::new(start);
+    indications.insert(Indication::SensorFault, None, start);
+    indications.insert(Indication::Identify, Some(start + ms(10_000)), start);
+
+    assert!(!indications.expire(start + ms(9_999)));
+    assert_eq!(indications.shown(), Some(Indication::Identify));
+    assert!(indications.expire(start + ms(10_000)));
+    assert_eq!(indications.shown(), Some(Indication::SensorFault));
+    assert!(!indications.expire(start + ms(60_000)));
+
+    // a repeated identify extends it
+    indications.insert(
+        Indication::Identify,
+        Some(start + ms(20_000)),
+        start + ms(15_000),
+    );
+    indications.insert(
+        Indication::Identify,
+        Some(start + ms(30_000)),
+        start + ms(16_000),
+    );
+    assert!(!indications.expire(start + ms(25_000)));
+    assert!(indications.is_active(Indication::Identify));
+}
+
+#[test]
+fn animations_follow_the_palette() {
+    let palette = Palette::default();
+    let frame = Indication::SensorFault.pattern(&palette).frame(0);
+    assert_eq!(
+        (frame[0].r, frame[0].g, frame[0].b),
+        (
+            palette.sensor_fault.r,
+            palette.sensor_fault.g,
+            palette.sensor_fault.b
+        )
+    );
+}
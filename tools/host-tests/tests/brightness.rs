@@ -0,0 +1,177 @@
+use std::time::{Duration, Instant};
+
+use host_tests::brightness::{
+    BrightnessArbiter, BrightnessPreferences, Effective, ManualBrightness, Source,
+};
+use host_tests::schedule::Scheduled;
+
+const PREFERENCES: BrightnessPreferences = BrightnessPreferences {
+    day: 85,
+    night: 1,
+    alert: 200,
+};
+
+// A night window
+const NIGHT: Option<Scheduled> = Some(Scheduled {
+    brightness: 1,
+    night: true,
+});
+
+/// Every input set, resolved by a fresh arbiter
+struct Inputs {
+    alert: bool,
+    identify: bool,
+    manual: Option<u8>,
+    scheduled: Option<Scheduled>,
+}
+
+const NOTHING: Inputs = Inputs {
+    alert: false,
+    identify: false,
+    manual: None,
+    scheduled: None,
+};
+
+fn resolve(inputs: Inputs) -> (u8, Source) {
+    let now = Instant::now();
+    let mut arbiter = BrightnessArbiter::default();
+    if let Some(level) = inputs.manual {
+        arbiter.set_manual(ManualBrightness::Level(level), now);
+    }
+    let Effective { brightness, source } = arbiter.resolve(
+        &PREFERENCES,
+        inputs.alert,
+        inputs.identify,
+        inputs.scheduled,
+        now,
+    );
+    (brightness, source)
+}
+
+#[test]
+fn day_applies_outside_of_the_schedule() {
+    assert_eq!(resolve(NOTHING), (85, Source::Day));
+    assert_eq!(
+        resolve(Inputs {
+            scheduled: NIGHT,
+            ..NOTHING
+        }),
+        (1, Source::Schedule)
+    );
+}
+
+#[test]
+fn manual_wins_over_the_schedule() {
+    let manual = Inputs {
+        manual: Some(40),
+        scheduled: NIGHT,
+        ..NOTHING
+    };
+    assert_eq!(resolve(manual), (40, Source::Manual));
+}
+
+#[test]
+fn identify_wins_over_manual() {
+    let identify = Inputs {
+        identify: true,
+        manual: Some(40),
+        scheduled: NIGHT,
+        ..NOTHING
+    };
+    assert_eq!(resolve(identify), (255, Source::Identify));
+}
+
+#[test]
+fn alert_wins_over_everything() {
+    let alert = Inputs {
+        alert: true,
+        identify: true,
+        manual: Some(40),
+        scheduled: NIGHT,
+    };
+    assert_eq!(resolve(alert), (200, Source::Alert));
+}
+
+#[test]
+fn timed_manual_brightness_expires() {
+    let now = Instant::now();
+    let mut arbiter = BrightnessArbiter::default();
+    arbiter.set_manual(
+        ManualBrightness::Timed {
+            brightness: 40,
+            duration_secs: Some(60),
+        },
+        now,
+    );
+    let manual = arbiter.manual(now).unwrap();
+    assert_eq!(manual.remaining(now), Some(Duration::from_secs(60)));
+
+    let resolve = |arbiter: &mut BrightnessArbiter, secs| {
+        let at = now + Duration::from_secs(secs);
+        arbiter
+            .resolve(&PREFERENCES, false, false, NIGHT, at)
+            .source
+    };
+    assert_eq!(resolve(&mut arbiter, 59), Source::Manual);
+    assert_eq!(resolve(&mut arbiter, 60), Source::Schedule);
+    assert!(arbiter.manual(now).is_none());
+    assert_eq!(arbiter.effective().unwrap().brightness, 1);
+}
+
+#[test]
+fn plain_manual_brightness_stays_until_cleared() {
+    let now = Instant::now();
+    let mut arbiter = BrightnessArbiter::default();
+    arbiter.set_manual(ManualBrightness::Level(40), now);
+    let later = now + Duration::from_secs(365 * 24 * 60 * 60);
+    let manual = arbiter.manual(later).unwrap();
+    assert_eq!(manual.remaining(later), None);
+
+    arbiter.clear_manual();
+    let effective = arbiter.resolve(&PREFERENCES, false, false, None, later);
+    assert_eq!(effective.source, Source::Day);
+}
+
+#[test]
+fn manual_duration_is_capped_at_a_day() {
+    let timed = |duration_secs| ManualBrightness::Timed {
+        brightness: 40,
+        duration_secs: Some(duration_secs),
+    };
+    assert!(timed(24 * 60 * 60).validate().is_ok());
+    assert!(timed(24 * 60 * 60 + 1).validate().is_err());
+    assert!(ManualBrightness::Level(40).validate().is_ok());
+}
+
+#[test]
+fn put_bodies_deserialize() {
+    let level: ManualBrightness = serde_json::from_str("40").unwrap();
+    assert!(matches!(level, ManualBrightness::Level(40)));
+    let timed: ManualBrightness =
+        serde_json::from_str(r#"{"brightness": 40, "duration_secs": 3600}"#).unwrap();
+    assert!(matches!(
+        timed,
+        ManualBrightness::Timed {
+            brightness: 40,
+            duration_secs: Some(3600)
+        }
+    ));
+    let untimed: ManualBrightness = serde_json::from_str(r#"{"brightness": 40}"#).unwrap();
+    assert!(matches!(
+        untimed,
+        ManualBrightness::Timed {
+            duration_secs: None,
+            ..
+        }
+    ));
+    assert!(serde_json::from_str::<ManualBrightness>("256").is_err());
+}
+
+#[test]
+fn preferences_fill_in_missing_levels() {
+    let preferences: BrightnessPreferences = serde_json::from_str(r#"{"night": 5}"#).unwrap();
+    assert_eq!(
+        (preferences.day, preferences.night, preferences.alert),
+        (85, 5, 85)
+    );
+}
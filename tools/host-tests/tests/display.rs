@@ -0,0 +1,162 @@
+use host_tests::bands::{get_co2_band, get_humidity_band, get_pm25_band, get_temperature_band};
+use host_tests::color::Color;
+use host_tests::display::{layout, DisplayMode, DisplaySettings, Measures};
+use host_tests::palette::Palette;
+
+const OFF: Color = Color {
+    r: 0,
+    g: 0,
+    b: 0,
+    brightness: None,
+};
+
+// Band 1 of CO2 and PM2.5, comfortable climate
+const FRESH: Measures = Measures {
+    co2: 800,
+    pm25: 20,
+    temperature: Some(22.0),
+    humidity: Some(40.0),
+};
+
+// CO2 band 3, PM2.5 band 2, temperature band 2, humidity band 1
+const STUFFY: Measures = Measures {
+    co2: 1800,
+    pm25: 40,
+    temperature: Some(27.5),
+    humidity: Some(55.0),
+};
+
+/// Bottom, center and top
+fn show(mode: DisplayMode, measures: &Measures) -> [Color; 3] {
+    layout(mode, measures, &Palette::default(), 0)
+}
+
+#[test]
+fn bands_follow_the_thresholds() {
+    let co2 = [400, 401, 1000, 1001, 1500, 1501, 2000, 2001].map(get_co2_band);
+    assert_eq!(co2, [0, 1, 1, 2, 2, 3, 3, 4]);
+    let pm25 = [12, 13, 35, 36, 55, 56, 150, 151].map(get_pm25_band);
+    assert_eq!(pm25, [0, 1, 1, 2, 2, 3, 3, 4]);
+    // too cold and too warm count alike
+    let temperature = [22.0, 20.0, 18.0, 26.0, 17.0, 27.0, 30.5, 13.0].map(get_temperature_band);
+    assert_eq!(temperature, [0, 0, 1, 1, 2, 2, 4, 4]);
+    let humidity = [40.0, 30.0, 50.0, 20.0, 65.0, 75.0, 85.0, 5.0].map(get_humidity_band);
+    assert_eq!(humidity, [0, 0, 0, 1, 2, 3, 4, 3]);
+}
+
+#[test]
+fn split_shows_co2_top_and_pm25_bottom() {
+    let palette = Palette::default();
+    let [bottom, center, top] = show(DisplayMode::Split, &STUFFY);
+    assert_eq!(top, palette.co2[3]);
+    assert_eq!(bottom, palette.pm25[2]);
+    assert_eq!(center, palette.pm25[2].mix(&palette.co2[3]));
+}
+
+#[test]
+fn worst_of_shows_the_worse_band_everywhere() {
+    let palette = Palette::default();
+    assert_eq!(show(DisplayMode::WorstOf, &STUFFY), [palette.co2[3]; 3]);
+    let smoky = Measures {
+        pm25: 200,
+        ..STUFFY
+    };
+    assert_eq!(show(DisplayMode::WorstOf, &smoky), [palette.pm25[4]; 3]);
+    // a tie goes to PM2.5
+    assert_eq!(show(DisplayMode::WorstOf, &FRESH), [palette.pm25[1]; 3]);
+}
+
+#[test]
+fn bar_graph_grows_with_co2() {
+    let palette = Palette::default();
+    let bar = |co2| {
+        show(
+            DisplayMode::BarGraph,
+            &Measures {
+                co2,
+                ..Measures::default()
+            },
+        )
+    };
+    assert_eq!(bar(400), [palette.co2[0], OFF, OFF]);
+    assert_eq!(bar(800), [palette.co2[1], OFF, OFF]);
+    assert_eq!(bar(1200), [palette.co2[2], palette.co2[2], OFF]);
+    assert_eq!(bar(1800), [palette.co2[3]; 3]);
+    assert_eq!(bar(3000), [palette.co2[4]; 3]);
+}
+
+#[test]
+fn climate_shows_temperature_top_and_humidity_bottom() {
+    let palette = Palette::default();
+    let [bottom, center, top] = show(DisplayMode::Climate, &STUFFY);
+    assert_eq!(top, palette.pm25[2]);
+    assert_eq!(bottom, palette.pm25[1]);
+    assert_eq!(center, palette.pm25[2].mix(&palette.pm25[1]));
+
+    // without the SCD41 climate values it falls back to the air quality
+    let no_climate = Measures {
+        humidity: None,
+        ..STUFFY
+    };
+    assert_eq!(
+        show(DisplayMode::Climate, &no_climate),
+        show(DisplayMode::Split, &no_climate)
+    );
+}
+
+#[test]
+fn cycle_steps_through_the_metrics_and_wraps_around() {
+    let palette = Palette::default();
+    let step = |measures: &Measures, step| layout(DisplayMode::Cycle, measures, &palette, step)[0];
+    let shown: Vec<Color> = (0..6).map(|i| step(&STUFFY, i)).collect();
+    assert_eq!(
+        shown,
+        [
+            palette.co2[3],
+            palette.pm25[2],
+            palette.pm25[2],
+            palette.pm25[1],
+            palette.co2[3],
+            palette.pm25[2]
+        ]
+    );
+    assert_eq!(
+        layout(DisplayMode::Cycle, &STUFFY, &palette, 2),
+        [palette.pm25[2]; 3]
+    );
+
+    // only CO2 and PM2.5 without the climate values
+    let no_climate = Measures {
+        temperature: None,
+        humidity: None,
+        ..STUFFY
+    };
+    assert_eq!(step(&no_climate, 2), palette.co2[3]);
+    assert_eq!(step(&no_climate, usize::MAX), palette.pm25[2]);
+}
+
+#[test]
+fn off_unless_bad_lights_up_from_band_two() {
+    let palette = Palette::default();
+    assert_eq!(show(DisplayMode::OffUnlessBad, &FRESH), [OFF; 3]);
+    assert_eq!(
+        show(DisplayMode::OffUnlessBad, &STUFFY),
+        [palette.co2[3]; 3]
+    );
+    let dusty = Measures { pm25: 40, ..FRESH };
+    assert_eq!(
+        show(DisplayMode::OffUnlessBad, &dusty),
+        [palette.pm25[2]; 3]
+    );
+}
+
+#[test]
+fn night_mode_applies_during_schedule_windows() {
+    let settings = DisplaySettings {
+        mode: DisplayMode::Split,
+        night_mode: Some(DisplayMode::OffUnlessBad),
+    };
+    assert_eq!(settings.mode(false), DisplayMode::Split);
+    assert_eq!(settings.mode(true), DisplayMode::OffUnlessBad);
+    assert_eq!(DisplaySettings::default().mode(true), DisplayMode::Split);
+}
@@ -0,0 +1,80 @@
+use host_tests::filter::{median, reject_spikes, trimmed_mean, PM25_FILTER};
+
+#[test]
+fn median_of_odd_and_even_counts() {
+    assert_eq!(median(&[]), None);
+    assert_eq!(median(&[4.0]), Some(4.0));
+    assert_eq!(median(&[1.0, 2.0, 9.0]), Some(2.0));
+    assert_eq!(median(&[1.0, 2.0, 4.0, 9.0]), Some(3.0));
+}
+
+#[test]
+fn spikes_are_rejected() {
+    let sorted = [10.0, 11.0, 11.0, 12.0, 12.0, 13.0, 14.0, 250.0];
+    assert_eq!(
+        reject_spikes(&sorted, 3.0, 1.0),
+        [10.0, 11.0, 11.0, 12.0, 12.0, 13.0, 14.0]
+    );
+    // low spikes too
+    assert_eq!(
+        reject_spikes(&[0.0, 40.0, 41.0, 42.0, 43.0], 3.0, 1.0),
+        [40.0, 41.0, 42.0, 43.0]
+    );
+}
+
+#[test]
+fn min_spread_keeps_small_deviations_of_identical_readings() {
+    let sorted = [5.0, 5.0, 5.0, 5.0, 6.0];
+    // the MAD is zero, every deviation would be a spike
+    assert_eq!(reject_spikes(&sorted, 3.0, 0.0), [5.0, 5.0, 5.0, 5.0]);
+    assert_eq!(reject_spikes(&sorted, 3.0, 1.0), sorted);
+    assert_eq!(
+        reject_spikes(&[5.0, 5.0, 5.0, 5.0, 9.0], 3.0, 1.0),
+        [5.0; 4]
+    );
+}
+
+#[test]
+fn trimmed_mean_drops_both_ends() {
+    let sorted = [1.0, 10.0, 11.0, 12.0, 100.0];
+    assert_eq!(trimmed_mean(&sorted, 0.2), Some(11.0));
+    assert_eq!(trimmed_mean(&sorted, 0.0), Some(26.8));
+    // at most a half of each end, the middle sample is kept
+    assert_eq!(trimmed_mean(&sorted, 0.9), Some(11.0));
+    assert_eq!(trimmed_mean(&[3.0, 5.0], 0.5), Some(4.0));
+    assert_eq!(trimmed_mean(&[], 0.2), None);
+}
+
+#[test]
+fn summary_of_a_window() {
+    let samples: [u16; 10] = [12, 14, 13, 12, 15, 13, 14, 900, 13, 12];
+    let summary = PM25_FILTER.apply(&samples).unwrap();
+    assert_eq!(summary.samples, 10);
+    assert_eq!(summary.rejected, 1);
+    assert_eq!((summary.min, summary.max), (12.0, 15.0));
+    assert_eq!(summary.median, 13.0);
+    // 12 and 15 trimmed
+    assert!((summary.value - 13.0).abs() < 1e-6, "{}", summary.value);
+    assert!(
+        (summary.mean - 118.0 / 9.0).abs() < 1e-6,
+        "{}",
+        summary.mean
+    );
+    assert!(summary.stddev > 0.0 && summary.stddev < 1.5);
+}
+
+#[test]
+fn too_few_samples() {
+    assert_eq!(PM25_FILTER.apply::<u16>(&[]), None);
+
+    let single = PM25_FILTER.apply(&[42u16]).unwrap();
+    assert_eq!(
+        (single.value, single.median, single.stddev),
+        (42.0, 42.0, 0.0)
+    );
+    assert_eq!(single.rejected, 0);
+
+    // nothing to compare with, both are kept
+    let pair = PM25_FILTER.apply(&[10u16, 500]).unwrap();
+    assert_eq!((pair.value, pair.rejected), (255.0, 0));
+}
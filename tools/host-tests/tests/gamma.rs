@@ -0,0 +1,142 @@
+use host_tests::color::Color;
+use host_tests::gamma::{lightness_to_luminance, white_point, ColorPipeline};
+use host_tests::palette::Palette;
+use rgb::RGB8;
+
+fn channels(rgb: RGB8) -> [u8; 3] {
+    [rgb.r, rgb.g, rgb.b]
+}
+
+fn output(pipeline: &ColorPipeline, color: Color, brightness: u8) -> [u8; 3] {
+    channels(pipeline.apply(color.brightness(brightness)))
+}
+
+/// Palette colors and a few dim and unsaturated ones
+fn colors() -> Vec<Color> {
+    let palette = Palette::default();
+    let mut colors = [palette.co2, palette.pm25].concat();
+    colors.extend([
+        Color::new(255, 255, 255),
+        Color::new(40, 40, 40),
+        Color::new(255, 0, 5),
+        Color::new(86, 180, 233),
+        Color::new(204, 121, 167),
+    ]);
+    colors
+}
+
+#[test]
+fn off_and_full() {
+    let pipeline = ColorPipeline::default();
+    assert_eq!(output(&pipeline, Color::new(255, 140, 0), 0), [0, 0, 0]);
+    assert_eq!(output(&pipeline, Color::new(0, 0, 0), 255), [0, 0, 0]);
+    assert_eq!(output(&pipeline, Color::new(255, 255, 255), 255), [255; 3]);
+    assert_eq!(output(&pipeline, Color::new(255, 0, 0), 255), [255, 0, 0]);
+    // without brightness it's full
+    assert_eq!(channels(pipeline.apply(Color::new(0, 255, 0))), [0, 255, 0]);
+}
+
+#[test]
+fn output_grows_with_brightness() {
+    let pipeline = ColorPipeline::default();
+    for color in colors() {
+        let mut previous = [0; 3];
+        for brightness in 0..=255 {
+            let current = output(&pipeline, color, brightness);
+            assert!(
+                current.iter().zip(previous).all(|(&c, p)| c >= p),
+                "{:?} at {}: {:?} < {:?}",
+                color,
+                brightness,
+                current,
+                previous
+            );
+            previous = current;
+        }
+    }
+}
+
+#[test]
+fn lowest_brightness_levels_differ() {
+    let pipeline = ColorPipeline::default();
+    let white = Color::new(255, 255, 255);
+    let peak = |brightness| output(&pipeline, white, brightness)[0];
+    assert_eq!(peak(1), pipeline.min_level);
+    assert!(peak(33) > peak(1));
+    assert!(peak(50) > peak(33));
+    assert!(peak(85) > peak(50));
+}
+
+#[test]
+fn hue_is_preserved_when_dimming() {
+    let pipeline = ColorPipeline::default();
+    for color in colors() {
+        let linear = [color.r, color.g, color.b].map(|c| (c as f32 / 255.0).powf(pipeline.gamma));
+        let peak = linear.iter().copied().fold(0.0, f32::max);
+        let brightest = linear.iter().position(|&c| c == peak).unwrap();
+
+        for brightness in 1..=255 {
+            let current = output(&pipeline, color, brightness);
+            let level = current[brightest] as f32;
+            assert!(level >= 1.0);
+            for (channel, &c) in linear.iter().enumerate() {
+                let expected = c / peak * level;
+                let actual = current[channel] as f32;
+                // rounding, dim channels are kept at 1 at least
+                assert!(
+                    (actual - expected).abs() <= 1.0,
+                    "{:?} at {}: {:?}",
+                    color,
+                    brightness,
+                    current
+                );
+                assert_eq!(actual == 0.0, c == 0.0 || c / peak < 0.02);
+            }
+        }
+    }
+}
+
+#[test]
+fn brightness_is_perceptual() {
+    assert_eq!(lightness_to_luminance(0), 0.0);
+    assert!((lightness_to_luminance(255) - 1.0).abs() < 1e-6);
+    // L* 50 is ~18 % luminance
+    assert!((lightness_to_luminance(128) - 0.186).abs() < 0.001);
+    // linear segment below L* 8
+    assert!((lightness_to_luminance(10) - 3.922 / 903.3).abs() < 1e-5);
+}
+
+#[test]
+fn white_balance_scales_the_channels() {
+    let pipeline = ColorPipeline {
+        white_balance: [1.0, 0.5, 0.25],
+        ..Default::default()
+    };
+    assert_eq!(
+        output(&pipeline, Color::new(255, 255, 255), 255),
+        [255, 128, 64]
+    );
+}
+
+#[test]
+fn white_points() {
+    let neutral = white_point(6_500);
+    assert!(neutral.iter().all(|&gain| gain > 0.95), "{:?}", neutral);
+    let [r, g, b] = white_point(2_700);
+    assert!(r == 1.0 && g < r && b < g, "{:?}", [r, g, b]);
+    let [r, g, b] = white_point(10_000);
+    assert!(b == 1.0 && r < b, "{:?}", [r, g, b]);
+    assert_eq!(white_point(100), white_point(1_000));
+}
+
+#[test]
+fn white_balance_is_parsed() {
+    let pipeline: ColorPipeline = "1.0, 0.85,0.75".parse().unwrap();
+    assert_eq!(pipeline.white_balance, [1.0, 0.85, 0.75]);
+    assert_eq!(pipeline.min_level, ColorPipeline::default().min_level);
+    let pipeline: ColorPipeline = "2700K".parse().unwrap();
+    assert_eq!(pipeline.white_balance, white_point(2_700));
+    for value in ["1.2,1,1", "1,1", "warm", "-5K", "1,1,1,1"] {
+        assert!(value.parse::<ColorPipeline>().is_err(), "{:?}", value);
+    }
+}
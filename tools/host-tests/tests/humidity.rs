@@ -0,0 +1,87 @@
+use host_tests::humidity::HumidityCorrection;
+
+fn assert_close(actual: f32, expected: f32) {
+    assert!(
+        (actual - expected).abs() < 0.01,
+        "{} != {}",
+        actual,
+        expected
+    );
+}
+
+#[test]
+fn corrections_are_parsed() {
+    assert_eq!(
+        "".parse::<HumidityCorrection>().unwrap(),
+        HumidityCorrection::None
+    );
+    assert_eq!(
+        " none ".parse::<HumidityCorrection>().unwrap(),
+        HumidityCorrection::None
+    );
+    assert_eq!(
+        "kappa:0.4".parse::<HumidityCorrection>().unwrap(),
+        HumidityCorrection::Kappa { kappa: 0.4 }
+    );
+    assert_eq!(
+        "linear: 0.8, -1.5".parse::<HumidityCorrection>().unwrap(),
+        HumidityCorrection::Linear {
+            slope: 0.8,
+            offset: -1.5
+        }
+    );
+}
+
+#[test]
+fn invalid_corrections_are_rejected() {
+    for value in [
+        "kappa",
+        "kappa:",
+        "kappa:abc",
+        "kappa:-0.1",
+        "kappa:2.5",
+        "linear:0.8",
+        "linear:0.8,x",
+        "offset:1",
+    ] {
+        assert!(
+            value.parse::<HumidityCorrection>().is_err(),
+            "{:?} was accepted",
+            value
+        );
+    }
+}
+
+#[test]
+fn kappa_growth_grows_with_humidity() {
+    let kappa = HumidityCorrection::Kappa { kappa: 0.4 };
+    assert_close(kappa.apply(100.0, Some(0.0)), 100.0);
+    // 1 + 0.4 * 0.5 / 0.5
+    assert_close(kappa.apply(100.0, Some(50.0)), 100.0 / 1.4);
+    // 1 + 0.4 * 0.95 / 0.05
+    assert_close(kappa.apply(100.0, Some(95.0)), 100.0 / 8.6);
+    // capped at 95 %
+    assert_close(kappa.apply(100.0, Some(100.0)), 100.0 / 8.6);
+    assert_close(kappa.apply(100.0, Some(-5.0)), 100.0);
+}
+
+#[test]
+fn linear_calibration_ignores_humidity() {
+    let linear = HumidityCorrection::Linear {
+        slope: 0.8,
+        offset: -2.0,
+    };
+    for humidity in [Some(0.0), Some(50.0), Some(95.0), None] {
+        assert_close(linear.apply(50.0, humidity), 38.0);
+        // never negative
+        assert_close(linear.apply(1.0, humidity), 0.0);
+    }
+}
+
+#[test]
+fn missing_humidity_skips_only_kappa() {
+    let kappa = HumidityCorrection::Kappa { kappa: 0.4 };
+    assert_close(kappa.apply(35.0, None), 35.0);
+    assert_close(HumidityCorrection::None.apply(35.0, None), 35.0);
+    assert_close(HumidityCorrection::None.apply(35.0, Some(95.0)), 35.0);
+}
@@ -0,0 +1,285 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use host_tests::animation::Indication;
+use host_tests::color::Color;
+use host_tests::display::{DisplayMode, Measures};
+use host_tests::gamma::ColorPipeline;
+use host_tests::leds::{LedOverride, LedWriter, Leds, INITIAL_BRIGHTNESS};
+use host_tests::palette::Palette;
+use rgb::RGB8;
+
+const RED: Color = Color {
+    r: 255,
+    g: 0,
+    b: 0,
+    brightness: None,
+};
+
+// CO2 band 3, PM2.5 band 2
+const MEASURES: Measures = Measures {
+    co2: 1800,
+    pm25: 40,
+    temperature: Some(22.0),
+    humidity: Some(40.0),
+};
+
+/// Keeps every write, bottom to top
+#[derive(Clone, Default)]
+struct Recorder(Rc<RefCell<Vec<[RGB8; 3]>>>);
+
+impl Recorder {
+    fn last(&self) -> [RGB8; 3] {
+        *self.0.borrow().last().expect("nothing written")
+    }
+
+    fn count(&self) -> usize {
+        self.0.borrow().len()
+    }
+}
+
+impl LedWriter for Recorder {
+    type Error = ();
+
+    fn write(&mut self, colors: [RGB8; 3]) -> Result<(), ()> {
+        self.0.borrow_mut().push(colors);
+        Ok(())
+    }
+}
+
+fn leds() -> (Leds<Recorder>, Recorder) {
+    let recorder = Recorder::default();
+    (Leds::with_driver(recorder.clone()), recorder)
+}
+
+fn pwm(color: Color, brightness: u8) -> RGB8 {
+    ColorPipeline::default().apply(color.brightness(brightness))
+}
+
+fn split(brightness: u8) -> [RGB8; 3] {
+    let palette = Palette::default();
+    let (co2, pm25) = (palette.co2[3], palette.pm25[2]);
+    [
+        pwm(pm25, brightness),
+        pwm(pm25.mix(&co2), brightness),
+        pwm(co2, brightness),
+    ]
+}
+
+fn secs(secs: u64) -> Duration {
+    Duration::from_secs(secs)
+}
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+#[test]
+fn measures_are_written_at_the_brightness() {
+    let (mut leds, recorder) = leds();
+    leds.visualize_measures(MEASURES);
+    assert_eq!(recorder.last(), split(INITIAL_BRIGHTNESS));
+
+    leds.set_brightness(200);
+    leds.flush().unwrap();
+    assert_eq!(recorder.last(), split(200));
+}
+
+#[test]
+fn disconnected_leds_keep_their_state() {
+    let mut leds = Leds::<Recorder>::disconnected();
+    leds.visualize_measures(MEASURES);
+    leds.set_brightness(10);
+    assert!(leds.flush().is_ok());
+    assert_eq!(leds.get_brightness(), 10);
+}
+
+#[test]
+fn indications_are_shown_over_the_colors() {
+    let (mut leds, recorder) = leds();
+    let palette = Palette::default();
+    leds.visualize_measures(MEASURES);
+
+    let start = Instant::now();
+    leds.set_indication(Indication::SensorFault, true);
+    // blinks 200 ms on, 1.8 s off
+    leds.flush_at(start + ms(100)).unwrap();
+    assert_eq!(
+        recorder.last(),
+        [pwm(palette.sensor_fault, INITIAL_BRIGHTNESS); 3]
+    );
+    leds.flush_at(start + ms(1_000)).unwrap();
+    assert_eq!(recorder.last(), [RGB8::default(); 3]);
+
+    // new measures don't replace the indication
+    leds.visualize_measures(MEASURES);
+    leds.flush_at(start + ms(2_100)).unwrap();
+    assert_eq!(
+        recorder.last(),
+        [pwm(palette.sensor_fault, INITIAL_BRIGHTNESS); 3]
+    );
+
+    leds.set_indication(Indication::SensorFault, false);
+    leds.flush().unwrap();
+    assert_eq!(recorder.last(), split(INITIAL_BRIGHTNESS));
+}
+
+#[test]
+fn the_highest_indication_wins() {
+    let (mut leds, recorder) = leds();
+    let palette = Palette::default();
+    leds.set_indication(Indication::Provisioning, true);
+    let start = Instant::now();
+    leds.set_indication(Indication::SensorFault, true);
+    assert!(leds.has_indication(Indication::Provisioning));
+    leds.flush_at(start + ms(100)).unwrap();
+    assert_eq!(
+        recorder.last(),
+        [pwm(palette.sensor_fault, INITIAL_BRIGHTNESS); 3]
+    );
+}
+
+#[test]
+fn animation_frames_are_scaled_by_the_brightness() {
+    let (mut leds, recorder) = leds();
+    let palette = Palette::default();
+    leds.set_brightness(200);
+    let start = Instant::now();
+    leds.set_indication(Indication::Provisioning, true);
+
+    // breathing over 3 s, at its peak the full brightness
+    leds.flush_at(start + ms(1_500)).unwrap();
+    assert_eq!(recorder.last(), [pwm(palette.provisioning, 200); 3]);
+    // 3/4 of the peak, 191 * 200 / 255
+    leds.flush_at(start + ms(1_000)).unwrap();
+    assert_eq!(recorder.last(), [pwm(palette.provisioning, 149); 3]);
+}
+
+#[test]
+fn tick_expires_timed_indications() {
+    let (mut leds, recorder) = leds();
+    leds.visualize_measures(MEASURES);
+    let start = Instant::now();
+    leds.set_indication_for(Indication::Identify, secs(10));
+
+    leds.tick_at(start + secs(5)).unwrap();
+    assert!(leds.has_indication(Indication::Identify));
+    assert_ne!(recorder.last(), split(INITIAL_BRIGHTNESS));
+
+    leds.tick_at(start + secs(11)).unwrap();
+    assert!(!leds.has_indication(Indication::Identify));
+    assert_eq!(recorder.last(), split(INITIAL_BRIGHTNESS));
+}
+
+#[test]
+fn tick_only_writes_on_changes() {
+    let (mut leds, recorder) = leds();
+    leds.visualize_measures(MEASURES);
+    let writes = recorder.count();
+    leds.tick_at(Instant::now() + ms(50)).unwrap();
+    assert_eq!(recorder.count(), writes);
+
+    // animations are written on every tick
+    leds.set_indication(Indication::Alert, true);
+    leds.tick_at(Instant::now() + ms(50)).unwrap();
+    leds.tick_at(Instant::now() + ms(100)).unwrap();
+    assert_eq!(recorder.count(), writes + 2);
+}
+
+#[test]
+fn overrides_expire_back_to_the_measures() {
+    let (mut leds, recorder) = leds();
+    leds.visualize_measures(MEASURES);
+    let start = Instant::now();
+    leds.override_colors(LedOverride {
+        top: Some(RED),
+        center: None,
+        bottom: None,
+        duration_secs: 60,
+    });
+    leds.show();
+    let overridden = [
+        RGB8::default(),
+        RGB8::default(),
+        pwm(RED, INITIAL_BRIGHTNESS),
+    ];
+    assert_eq!(recorder.last(), overridden);
+
+    // new measures are kept for later
+    leds.visualize_measures(Measures {
+        co2: 400,
+        ..MEASURES
+    });
+    assert_eq!(recorder.last(), overridden);
+    leds.visualize_measures(MEASURES);
+
+    leds.tick_at(start + secs(59)).unwrap();
+    assert_eq!(recorder.last(), overridden);
+    leds.tick_at(start + secs(61)).unwrap();
+    assert_eq!(recorder.last(), split(INITIAL_BRIGHTNESS));
+}
+
+#[test]
+fn overrides_are_shown_over_the_status_indications_only() {
+    let (mut leds, recorder) = leds();
+    let palette = Palette::default();
+    let start = Instant::now();
+    leds.set_indication(Indication::SensorFault, true);
+    leds.override_colors(LedOverride {
+        top: Some(RED),
+        center: Some(RED),
+        bottom: Some(RED),
+        duration_secs: 60,
+    });
+    leds.flush_at(start + ms(100)).unwrap();
+    assert_eq!(recorder.last(), [pwm(RED, INITIAL_BRIGHTNESS); 3]);
+
+    let alert = Instant::now();
+    leds.set_indication(Indication::Alert, true);
+    // the pulse peaks 100 ms in
+    leds.flush_at(alert + ms(100)).unwrap();
+    assert_eq!(recorder.last(), [pwm(palette.alert, INITIAL_BRIGHTNESS); 3]);
+
+    // the sensor fault is back once the override expires, its blink starts over
+    let restarted = Instant::now();
+    leds.set_indication(Indication::Alert, false);
+    leds.tick_at(start + secs(61)).unwrap();
+    leds.flush_at(restarted + ms(100)).unwrap();
+    assert_eq!(
+        recorder.last(),
+        [pwm(palette.sensor_fault, INITIAL_BRIGHTNESS); 3]
+    );
+}
+
+#[test]
+fn overrides_are_capped_at_a_day() {
+    let led_override = |duration_secs| LedOverride {
+        top: None,
+        center: None,
+        bottom: None,
+        duration_secs,
+    };
+    assert!(led_override(24 * 60 * 60).validate().is_ok());
+    assert!(led_override(24 * 60 * 60 + 1).validate().is_err());
+}
+
+#[test]
+fn cycle_advances_every_five_seconds() {
+    let (mut leds, recorder) = leds();
+    let palette = Palette::default();
+    let start = Instant::now();
+    leds.set_display_mode(DisplayMode::Cycle);
+    leds.visualize_measures(MEASURES);
+    assert_eq!(
+        recorder.last(),
+        [pwm(palette.co2[3], INITIAL_BRIGHTNESS); 3]
+    );
+
+    leds.tick_at(start + secs(6)).unwrap();
+    assert_eq!(
+        recorder.last(),
+        [pwm(palette.pm25[2], INITIAL_BRIGHTNESS); 3]
+    );
+    assert_eq!(leds.get_display_mode(), DisplayMode::Cycle);
+}
@@ -0,0 +1,68 @@
+use std::time::{Duration, Instant};
+
+use host_tests::liveness::{Liveness, Stall};
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+#[test]
+fn beating_loop_is_alive() {
+    let start = Instant::now();
+    let mut liveness = Liveness::default();
+    liveness.register("main", 3 * MINUTE, start);
+    for minute in 1..=10 {
+        let now = start + MINUTE * minute;
+        liveness.beat("main", now);
+        assert_eq!(liveness.stalled(now), None);
+    }
+    let report = liveness.report(start + MINUTE * 10);
+    assert_eq!(report["main"].iterations, 10);
+    assert_eq!(report["main"].longest_secs, 60);
+}
+
+#[test]
+fn iteration_over_the_budget_stalls() {
+    let start = Instant::now();
+    let mut liveness = Liveness::default();
+    liveness.register("main", 3 * MINUTE, start);
+    assert_eq!(liveness.stalled(start + 3 * MINUTE), None);
+    assert_eq!(
+        liveness.stalled(start + 4 * MINUTE),
+        Some(Stall {
+            name: "main",
+            elapsed: 4 * MINUTE,
+            budget: 3 * MINUTE,
+        })
+    );
+
+    // the loop made it through eventually
+    liveness.beat("main", start + 4 * MINUTE);
+    assert_eq!(liveness.stalled(start + 5 * MINUTE), None);
+    assert_eq!(
+        liveness.report(start + 5 * MINUTE)["main"].longest_secs,
+        240
+    );
+}
+
+#[test]
+fn loop_furthest_over_its_budget_is_reported() {
+    let start = Instant::now();
+    let mut liveness = Liveness::default();
+    liveness.register("clock", 2 * MINUTE, start);
+    liveness.register("main", 3 * MINUTE, start);
+    liveness.register("update", 15 * MINUTE, start);
+    let stall = liveness.stalled(start + 6 * MINUTE).unwrap();
+    assert_eq!(stall.name, "clock");
+
+    liveness.beat("clock", start + 6 * MINUTE);
+    let stall = liveness.stalled(start + 6 * MINUTE).unwrap();
+    assert_eq!(stall.name, "main");
+}
+
+#[test]
+fn unknown_loop_is_ignored() {
+    let start = Instant::now();
+    let mut liveness = Liveness::default();
+    liveness.beat("main", start);
+    assert_eq!(liveness.stalled(start + 60 * MINUTE), None);
+    assert!(liveness.report(start).is_empty());
+}
@@ -0,0 +1,164 @@
+use host_tests::logs::{
+    format_syslog, frame, Levels, LogConfig, LogLine, Protocol, Remote, RingBuffer, Threshold,
+};
+use log::{Level, LevelFilter};
+
+fn line(message: &str) -> LogLine {
+    LogLine {
+        seq: 0,
+        uptime_ms: 1500,
+        timestamp_ms: None,
+        level: Level::Info,
+        target: "esp_vindriktning".into(),
+        thread: Some("main".into()),
+        message: message.into(),
+    }
+}
+
+fn messages<'a>(lines: impl Iterator<Item = &'a LogLine>) -> Vec<&'a str> {
+    lines.map(|line| line.message.as_str()).collect()
+}
+
+#[test]
+fn buffer_numbers_the_lines() {
+    let mut buffer = RingBuffer::new(4096);
+    assert_eq!(buffer.push(line("first")), 0);
+    assert_eq!(buffer.push(line("second")), 1);
+    assert_eq!(buffer.push(line("third")), 2);
+    assert_eq!(messages(buffer.since(None)), ["first", "second", "third"]);
+    assert_eq!(messages(buffer.since(Some(1))), ["second", "third"]);
+    assert_eq!(messages(buffer.since(Some(3))), Vec::<&str>::new());
+    assert_eq!(buffer.next_seq(), 3);
+    assert_eq!(buffer.dropped(), 0);
+}
+
+#[test]
+fn buffer_drops_the_oldest_lines() {
+    let mut buffer = RingBuffer::new(1024);
+    for i in 0..100 {
+        buffer.push(line(&format!("line {:02} {}", i, "x".repeat(40))));
+    }
+    let kept: Vec<_> = buffer.since(None).map(|line| line.seq).collect();
+    assert!(kept.len() < 100 && !kept.is_empty(), "{:?}", kept);
+    // the newest ones, in order
+    assert_eq!(*kept.last().unwrap(), 99);
+    assert!(kept.windows(2).all(|pair| pair[1] == pair[0] + 1));
+    assert_eq!(buffer.dropped(), 100 - kept.len() as u64);
+}
+
+#[test]
+fn buffer_keeps_an_oversized_line() {
+    let mut buffer = RingBuffer::new(64);
+    buffer.push(line("short"));
+    buffer.push(line(&"x".repeat(1000)));
+    assert_eq!(buffer.since(None).count(), 1);
+    assert_eq!(buffer.since(None).next().unwrap().seq, 1);
+}
+
+#[test]
+fn module_levels_are_inherited() {
+    let config: LogConfig = serde_json::from_str(
+        r#"{"levels": {"default": "warn", "modules": {
+            "esp_vindriktning::clock": "debug",
+            "esp_idf_svc": "ERROR"
+        }}}"#,
+    )
+    .unwrap();
+    let levels = &config.levels;
+    assert_eq!(levels.level("esp_vindriktning"), LevelFilter::Warn);
+    assert_eq!(levels.level("esp_vindriktning::clock"), LevelFilter::Debug);
+    assert_eq!(
+        levels.level("esp_vindriktning::clock::sntp"),
+        LevelFilter::Debug
+    );
+    // not a submodule
+    assert_eq!(
+        levels.level("esp_vindriktning::clockwork"),
+        LevelFilter::Warn
+    );
+    assert_eq!(levels.level("esp_idf_svc::wifi"), LevelFilter::Error);
+    assert!(levels.enabled(Level::Debug, "esp_vindriktning::clock"));
+    assert!(!levels.enabled(Level::Info, "esp_vindriktning::wifi"));
+    assert_eq!(levels.max(), LevelFilter::Debug);
+}
+
+#[test]
+fn levels_serialize_in_lowercase() {
+    let mut levels = Levels::default();
+    levels
+        .modules
+        .insert("esp_vindriktning::leds".into(), Threshold(LevelFilter::Off));
+    assert_eq!(
+        serde_json::to_value(&levels).unwrap(),
+        serde_json::json!({
+            "default": "info",
+            "modules": { "esp_vindriktning::leds": "off" }
+        })
+    );
+    assert!(serde_json::from_str::<Levels>(r#"{"default": "verbose"}"#).is_err());
+}
+
+#[test]
+fn syslog_message_follows_rfc_5424() {
+    let mut line = line("CO2: 612 ppm");
+    line.level = Level::Warn;
+    line.timestamp_ms = Some(1_718_454_896_123);
+    assert_eq!(
+        format_syslog(&line, "vindriktning-a0b1c2"),
+        "<132>1 2024-06-15T12:34:56.123Z vindriktning-a0b1c2 esp-vindriktning main - - \
+         esp_vindriktning: CO2: 612 ppm"
+    );
+}
+
+#[test]
+fn syslog_message_without_clock_and_thread() {
+    let mut line = line("Connecting");
+    line.level = Level::Debug;
+    line.thread = Some("sys evt".into());
+    assert_eq!(
+        format_syslog(&line, ""),
+        "<135>1 - - esp-vindriktning sys_evt - - esp_vindriktning: Connecting"
+    );
+    line.thread = None;
+    assert!(format_syslog(&line, "host").starts_with("<135>1 - host esp-vindriktning - - - "));
+}
+
+#[test]
+fn tcp_frames_count_octets() {
+    assert_eq!(frame(Protocol::Udp, "<134>1 - -"), b"<134>1 - -");
+    assert_eq!(frame(Protocol::Tcp, "<134>1 - -"), b"10 <134>1 - -");
+    assert_eq!(frame(Protocol::Tcp, "čau"), "4 čau".as_bytes());
+}
+
+#[test]
+fn remote_is_parsed_from_a_url() {
+    assert_eq!(
+        "udp://192.168.1.10".parse::<Remote>().unwrap(),
+        Remote {
+            protocol: Protocol::Udp,
+            host: "192.168.1.10".into(),
+            port: 514,
+        }
+    );
+    let remote: Remote = "tcp://logs.local:6514".parse().unwrap();
+    assert_eq!((remote.protocol, remote.port), (Protocol::Tcp, 6514));
+    assert_eq!(remote.to_string(), "tcp://logs.local:6514");
+    assert!("http://logs.local".parse::<Remote>().is_err());
+    assert!("udp://logs.local:syslog".parse::<Remote>().is_err());
+    assert!("udp://:514".parse::<Remote>().is_err());
+    assert!("udp://logs.local:0".parse::<Remote>().is_err());
+}
+
+#[test]
+fn config_round_trips() {
+    let config = LogConfig {
+        levels: Levels::default(),
+        remote: Some("udp://192.168.1.10:514".parse().unwrap()),
+    };
+    let json = serde_json::to_string(&config).unwrap();
+    assert_eq!(serde_json::from_str::<LogConfig>(&json).unwrap(), config);
+    assert_eq!(
+        serde_json::from_str::<LogConfig>("{}").unwrap(),
+        LogConfig::default()
+    );
+}
@@ -0,0 +1,216 @@
+use std::net::Ipv4Addr;
+
+use host_tests::network::{netmask, prefix_len, validate_hostname, Ipv4Config, NetworkConfig};
+
+#[test]
+fn default_hostname_contains_the_mac() {
+    let config = NetworkConfig::default();
+    assert_eq!(config.hostname("a0b1c2d3e4f5"), "vindriktning-a0b1c2d3e4f5");
+    let config = NetworkConfig {
+        hostname: Some("kitchen".into()),
+        ..Default::default()
+    };
+    assert_eq!(config.hostname("a0b1c2d3e4f5"), "kitchen");
+}
+
+#[test]
+fn valid_hostnames() {
+    for hostname in ["kitchen", "vindriktning-a0b1c2d3e4f5", "Office-2", "7"] {
+        assert!(validate_hostname(hostname).is_ok(), "{}", hostname);
+    }
+}
+
+#[test]
+fn invalid_hostnames() {
+    for hostname in [
+        "",
+        "kitchen.local",
+        "living room",
+        "-kitchen",
+        "kitchen-",
+        "kuchyň",
+    ] {
+        assert!(validate_hostname(hostname).is_err(), "{:?}", hostname);
+    }
+}
+
+#[test]
+fn hostname_fits_the_netif() {
+    // the ESP-IDF netif takes at most 32 characters, less than a DNS label
+    assert!(validate_hostname(&"a".repeat(32)).is_ok());
+    assert!(validate_hostname(&"a".repeat(33)).is_err());
+    assert!(validate_hostname(&"a".repeat(63)).is_err());
+    let default = NetworkConfig::default().hostname("a0b1c2d3e4f5");
+    assert!(validate_hostname(&default).is_ok());
+}
+
+#[test]
+fn config_is_validated() {
+    let config: NetworkConfig = serde_json::from_str(r#"{"hostname": "my_sensor"}"#).unwrap();
+    assert!(config.validate().is_err());
+    let config: NetworkConfig = serde_json::from_str("{}").unwrap();
+    assert_eq!(config, NetworkConfig::default());
+    assert!(config.validate().is_ok());
+}
+
+#[test]
+fn invalid_stored_config_falls_back_to_the_defaults() {
+    // hostnames up to 63 characters were accepted before the netif limit
+    let stored = format!(
+        r#"{{"hostname": "{}", "ipv4": {{"mode": "static", "address": "192.168.1.20",
+            "netmask": "255.255.255.0", "gateway": "10.0.0.1"}}, "ipv6": true}}"#,
+        "a".repeat(63)
+    );
+    let config = serde_json::from_str::<NetworkConfig>(&stored)
+        .unwrap()
+        .sanitized();
+    assert_eq!(
+        config,
+        NetworkConfig {
+            hostname: None,
+            ipv4: Ipv4Config::Dhcp,
+            ipv6: true,
+        }
+    );
+    assert!(config.validate().is_ok());
+
+    let valid = NetworkConfig {
+        hostname: Some("kitchen".into()),
+        ipv4: "192.168.1.20/24,192.168.1.1".parse().unwrap(),
+        ipv6: false,
+    };
+    assert_eq!(valid.clone().sanitized(), valid);
+}
+
+fn ip(value: &str) -> Ipv4Addr {
+    value.parse().unwrap()
+}
+
+fn static_config(address: &str, netmask: &str, gateway: &str, dns: &[&str]) -> Ipv4Config {
+    Ipv4Config::Static {
+        address: ip(address),
+        netmask: ip(netmask),
+        gateway: ip(gateway),
+        dns: dns.iter().map(|server| ip(server)).collect(),
+    }
+}
+
+#[test]
+fn netmask_and_prefix_length_convert() {
+    for (len, mask) in [
+        (8, "255.0.0.0"),
+        (24, "255.255.255.0"),
+        (30, "255.255.255.252"),
+        (32, "255.255.255.255"),
+    ] {
+        assert_eq!(netmask(len).unwrap(), ip(mask));
+        assert_eq!(prefix_len(ip(mask)).unwrap(), len);
+    }
+    assert!(netmask(33).is_err());
+    assert!(prefix_len(ip("0.0.0.0")).is_err());
+    assert!(prefix_len(ip("255.0.255.0")).is_err());
+}
+
+#[test]
+fn static_ipv4_is_parsed() {
+    assert_eq!("dhcp".parse::<Ipv4Config>().unwrap(), Ipv4Config::Dhcp);
+    assert_eq!(
+        "192.168.10.20/24,192.168.10.1,192.168.10.1, 1.1.1.1"
+            .parse::<Ipv4Config>()
+            .unwrap(),
+        static_config(
+            "192.168.10.20",
+            "255.255.255.0",
+            "192.168.10.1",
+            &["192.168.10.1", "1.1.1.1"]
+        )
+    );
+    assert_eq!(
+        "10.0.0.5/8,10.0.0.1".parse::<Ipv4Config>().unwrap(),
+        static_config("10.0.0.5", "255.0.0.0", "10.0.0.1", &[])
+    );
+    for invalid in [
+        "",
+        "static",
+        "192.168.10.20",
+        "192.168.10.20/24",
+        "192.168.10.20/33,192.168.10.1",
+        "192.168.10.256/24,192.168.10.1",
+        "192.168.10.20/24,192.168.10.1,dns.google",
+    ] {
+        assert!(invalid.parse::<Ipv4Config>().is_err(), "{:?}", invalid);
+    }
+}
+
+#[test]
+fn static_ipv4_is_validated() {
+    let valid = static_config(
+        "192.168.10.20",
+        "255.255.255.0",
+        "192.168.10.1",
+        &["1.1.1.1"],
+    );
+    assert!(valid.validate().is_ok());
+    for invalid in [
+        // gateway in another subnet
+        static_config("192.168.10.20", "255.255.255.0", "192.168.11.1", &[]),
+        // network and broadcast addresses
+        static_config("192.168.10.0", "255.255.255.0", "192.168.10.1", &[]),
+        static_config("192.168.10.255", "255.255.255.0", "192.168.10.1", &[]),
+        static_config("0.0.0.0", "255.255.255.0", "192.168.10.1", &[]),
+        static_config("192.168.10.20", "255.255.0.255", "192.168.10.1", &[]),
+        static_config(
+            "192.168.10.20",
+            "255.255.255.0",
+            "192.168.10.1",
+            &["0.0.0.0"],
+        ),
+        static_config(
+            "192.168.10.20",
+            "255.255.255.0",
+            "192.168.10.1",
+            &["1.1.1.1", "8.8.8.8", "9.9.9.9"],
+        ),
+    ] {
+        assert!(invalid.validate().is_err(), "{:?}", invalid);
+    }
+    // point-to-point subnet
+    assert!(
+        static_config("10.0.0.0", "255.255.255.254", "10.0.0.1", &[])
+            .validate()
+            .is_ok()
+    );
+}
+
+#[test]
+fn network_config_json() {
+    let config: NetworkConfig = serde_json::from_str(
+        r#"{
+            "hostname": "vindriktning-lab",
+            "ipv4": {
+                "mode": "static",
+                "address": "192.168.10.20",
+                "netmask": "255.255.255.0",
+                "gateway": "192.168.10.1",
+                "dns": ["192.168.10.1"]
+            },
+            "ipv6": true
+        }"#,
+    )
+    .unwrap();
+    assert!(config.validate().is_ok());
+    assert!(config.ipv6);
+    assert_eq!(
+        config.ipv4,
+        static_config(
+            "192.168.10.20",
+            "255.255.255.0",
+            "192.168.10.1",
+            &["192.168.10.1"]
+        )
+    );
+    assert_eq!(
+        serde_json::to_value(NetworkConfig::default()).unwrap(),
+        serde_json::json!({ "hostname": null, "ipv4": { "mode": "dhcp" }, "ipv6": false })
+    );
+}
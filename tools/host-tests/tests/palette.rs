@@ -0,0 +1,87 @@
+use host_tests::color::Color;
+use host_tests::gamma::ColorPipeline;
+use host_tests::palette::{Palette, PaletteName, PaletteSettings};
+
+// The default day and night brightness preferences
+const DAY: u8 = 85;
+const NIGHT: u8 = 1;
+
+fn palette(name: PaletteName) -> Palette {
+    PaletteSettings { name, custom: None }.palette()
+}
+
+fn pwm(color: Color, brightness: u8) -> [u8; 3] {
+    let rgb = ColorPipeline::default().apply(color.brightness(brightness));
+    [rgb.r, rgb.g, rgb.b]
+}
+
+#[test]
+fn monochrome_bands_stay_distinct() {
+    let palette = palette(PaletteName::Monochrome);
+    for brightness in [NIGHT, DAY, 255] {
+        let levels: Vec<u8> = palette
+            .co2
+            .iter()
+            .map(|&color| pwm(color, brightness)[0])
+            .collect();
+        assert!(
+            levels.windows(2).all(|pair| pair[0] < pair[1]),
+            "brightness {}: {:?}",
+            brightness,
+            levels
+        );
+    }
+    // any brightness the LEDs are on at
+    for brightness in 1..=255 {
+        let levels = palette.pm25.map(|color| pwm(color, brightness)[0]);
+        assert!(levels.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
+
+#[test]
+fn bands_are_distinct_in_all_palettes() {
+    for name in [
+        PaletteName::Default,
+        PaletteName::ColorBlind,
+        PaletteName::Monochrome,
+        PaletteName::BlueToRed,
+    ] {
+        let palette = palette(name);
+        for brightness in [NIGHT, DAY] {
+            for bands in [palette.co2, palette.pm25] {
+                let outputs = bands.map(|color| pwm(color, brightness));
+                for (i, output) in outputs.iter().enumerate() {
+                    assert!(
+                        outputs[i + 1..].iter().all(|other| other != output),
+                        "{:?} at {}: {:?}",
+                        name,
+                        brightness,
+                        outputs
+                    );
+                    assert_ne!(*output, [0, 0, 0]);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn custom_palette_needs_colors() {
+    let settings: PaletteSettings = serde_json::from_str(r#"{"name": "custom"}"#).unwrap();
+    assert!(settings.validate().is_err());
+    // stored before the validation, shown with the default colors
+    assert_eq!(settings.palette(), Palette::default());
+
+    let settings: PaletteSettings = serde_json::from_str(
+        r##"{"name": "custom", "custom": {"co2": ["#000011", "#000022", "#000033", "#000044", "#000055"]}}"##,
+    )
+    .unwrap();
+    settings.validate().unwrap();
+    let palette = settings.palette();
+    assert_eq!(palette.co2[4], Color::new(0, 0, 0x55));
+    // the rest from the default one
+    assert_eq!(palette.pm25, Palette::default().pm25);
+
+    let settings: PaletteSettings = serde_json::from_str(r#"{"name": "monochrome"}"#).unwrap();
+    settings.validate().unwrap();
+}
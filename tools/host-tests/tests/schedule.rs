@@ -0,0 +1,286 @@
+use host_tests::schedule::{Action, Day, Holiday, LocalTime, Schedule, Window};
+use host_tests::timezone::Timezone;
+use time::macros::datetime;
+use time::{Duration, OffsetDateTime};
+
+const ALL_DAYS: [Day; 7] = [
+    Day::Mon,
+    Day::Tue,
+    Day::Wed,
+    Day::Thu,
+    Day::Fri,
+    Day::Sat,
+    Day::Sun,
+];
+
+fn window(days: &[Day], start: &str, end: &str, action: Action) -> Window {
+    Window {
+        days: days.to_vec(),
+        start: start.parse().unwrap(),
+        end: end.parse().unwrap(),
+        action,
+    }
+}
+
+fn schedule(windows: Vec<Window>) -> Schedule {
+    Schedule {
+        windows,
+        holidays: Vec::new(),
+        solar: None,
+    }
+}
+
+/// Minutes in `[from, to)` UTC the schedule is active in Prague
+fn active_minutes(schedule: &Schedule, from: OffsetDateTime, to: OffsetDateTime) -> i64 {
+    let prague: Timezone = "Europe/Prague".parse().unwrap();
+    let mut minutes = 0;
+    let mut utc = from;
+    while utc < to {
+        if schedule.evaluate(prague.convert(utc)).is_some() {
+            minutes += 1;
+        }
+        utc += Duration::minutes(1);
+    }
+    minutes
+}
+
+/// First and last minute (UTC) the schedule is active in Prague within `[from, to)`
+fn active_span(
+    schedule: &Schedule,
+    from: OffsetDateTime,
+    to: OffsetDateTime,
+) -> Option<(OffsetDateTime, OffsetDateTime)> {
+    let prague: Timezone = "Europe/Prague".parse().unwrap();
+    let mut span = None;
+    let mut utc = from;
+    while utc < to {
+        if schedule.evaluate(prague.convert(utc)).is_some() {
+            span = Some((span.map_or(utc, |(first, _)| first), utc));
+        }
+        utc += Duration::minutes(1);
+    }
+    span
+}
+
+#[test]
+fn local_times_are_parsed() {
+    assert_eq!("22:00".parse::<LocalTime>().unwrap(), LocalTime::new(22, 0));
+    assert_eq!("7:05".parse::<LocalTime>().unwrap().to_string(), "07:05");
+    assert_eq!("24:00".parse::<LocalTime>().unwrap().to_string(), "24:00");
+    for value in ["24:01", "25:00", "12:60", "12", "noon", "-1:00"] {
+        assert!(value.parse::<LocalTime>().is_err(), "{:?}", value);
+    }
+}
+
+#[test]
+fn window_over_midnight() {
+    let schedule = Schedule::default();
+    // a Tuesday
+    assert_eq!(schedule.evaluate(datetime!(2024-06-11 21:59 UTC)), None);
+    assert_eq!(
+        schedule.evaluate(datetime!(2024-06-11 22:00 UTC)),
+        Some(Action::Night)
+    );
+    assert_eq!(
+        schedule.evaluate(datetime!(2024-06-11 23:59 UTC)),
+        Some(Action::Night)
+    );
+    assert_eq!(
+        schedule.evaluate(datetime!(2024-06-12 00:00 UTC)),
+        Some(Action::Night)
+    );
+    assert_eq!(
+        schedule.evaluate(datetime!(2024-06-12 05:59 UTC)),
+        Some(Action::Night)
+    );
+    assert_eq!(schedule.evaluate(datetime!(2024-06-12 06:00 UTC)), None);
+}
+
+#[test]
+fn window_over_midnight_belongs_to_its_start_day() {
+    let schedule = schedule(vec![window(&[Day::Fri], "23:00", "07:00", Action::Off)]);
+    // Friday 2024-06-14
+    assert_eq!(schedule.evaluate(datetime!(2024-06-14 03:00 UTC)), None);
+    assert_eq!(
+        schedule.evaluate(datetime!(2024-06-14 23:30 UTC)),
+        Some(Action::Off)
+    );
+    assert_eq!(
+        schedule.evaluate(datetime!(2024-06-15 06:59 UTC)),
+        Some(Action::Off)
+    );
+    assert_eq!(schedule.evaluate(datetime!(2024-06-15 07:00 UTC)), None);
+    assert_eq!(schedule.evaluate(datetime!(2024-06-15 23:30 UTC)), None);
+}
+
+#[test]
+fn window_until_midnight() {
+    let schedule = schedule(vec![window(&[Day::Mon], "20:00", "24:00", Action::Off)]);
+    assert_eq!(
+        schedule.evaluate(datetime!(2024-06-10 23:59 UTC)),
+        Some(Action::Off)
+    );
+    assert_eq!(schedule.evaluate(datetime!(2024-06-11 00:00 UTC)), None);
+
+    // the same start and end is a whole day
+    let schedule = self::schedule(vec![window(&[Day::Mon], "08:00", "08:00", Action::Off)]);
+    assert_eq!(schedule.evaluate(datetime!(2024-06-10 07:59 UTC)), None);
+    assert!(schedule.evaluate(datetime!(2024-06-10 08:00 UTC)).is_some());
+    assert!(schedule.evaluate(datetime!(2024-06-11 07:59 UTC)).is_some());
+    assert_eq!(schedule.evaluate(datetime!(2024-06-11 08:00 UTC)), None);
+}
+
+#[test]
+fn first_active_window_wins() {
+    let schedule = schedule(vec![
+        window(&ALL_DAYS, "00:00", "05:00", Action::Off),
+        window(&ALL_DAYS, "22:00", "07:00", Action::Night),
+    ]);
+    assert_eq!(
+        schedule.evaluate(datetime!(2024-06-11 23:00 UTC)),
+        Some(Action::Night)
+    );
+    assert_eq!(
+        schedule.evaluate(datetime!(2024-06-12 04:00 UTC)),
+        Some(Action::Off)
+    );
+    assert_eq!(
+        schedule.evaluate(datetime!(2024-06-12 06:00 UTC)),
+        Some(Action::Night)
+    );
+}
+
+#[test]
+fn holidays_take_the_windows_of_another_day() {
+    let mut schedule = schedule(vec![
+        window(&[Day::Sun], "21:00", "09:00", Action::Night),
+        window(&[Day::Mon], "23:00", "06:00", Action::Night),
+    ]);
+    schedule.holidays.push(Holiday {
+        // Easter Monday
+        date: "2024-04-01".into(),
+        as_day: Day::Sun,
+    });
+    schedule.validate().unwrap();
+
+    // the Sunday window goes on until 09:00 on a Monday
+    assert!(schedule.evaluate(datetime!(2024-04-01 08:00 UTC)).is_some());
+    // the holiday starts a Sunday window in the evening
+    assert!(schedule.evaluate(datetime!(2024-04-01 21:30 UTC)).is_some());
+    // which lasts over the following morning
+    assert!(schedule.evaluate(datetime!(2024-04-02 08:30 UTC)).is_some());
+    assert_eq!(schedule.evaluate(datetime!(2024-04-02 09:00 UTC)), None);
+    // an ordinary Monday
+    assert_eq!(schedule.evaluate(datetime!(2024-04-08 21:30 UTC)), None);
+    assert!(schedule.evaluate(datetime!(2024-04-08 23:30 UTC)).is_some());
+}
+
+#[test]
+fn spring_forward_shortens_the_night() {
+    let schedule = Schedule::default();
+    // Prague skips 02:00 - 03:00 on 2024-03-31
+    let from = datetime!(2024-03-30 12:00 UTC);
+    let to = datetime!(2024-03-31 12:00 UTC);
+    assert_eq!(active_minutes(&schedule, from, to), 7 * 60);
+    // 22:00 CET - 06:00 CEST
+    assert_eq!(
+        active_span(&schedule, from, to),
+        Some((
+            datetime!(2024-03-30 21:00 UTC),
+            datetime!(2024-03-31 03:59 UTC)
+        ))
+    );
+
+    // a window within the skipped hour doesn't happen that night
+    let skipped = self::schedule(vec![window(&ALL_DAYS, "02:00", "03:00", Action::Off)]);
+    assert_eq!(active_minutes(&skipped, from, to), 0);
+    assert_eq!(
+        active_minutes(
+            &skipped,
+            datetime!(2024-04-06 12:00 UTC),
+            datetime!(2024-04-07 12:00 UTC)
+        ),
+        60
+    );
+}
+
+#[test]
+fn fall_back_lengthens_the_night() {
+    let schedule = Schedule::default();
+    // Prague repeats 02:00 - 03:00 on 2024-10-27
+    let from = datetime!(2024-10-26 12:00 UTC);
+    let to = datetime!(2024-10-27 12:00 UTC);
+    assert_eq!(active_minutes(&schedule, from, to), 9 * 60);
+    // 22:00 CEST - 06:00 CET
+    assert_eq!(
+        active_span(&schedule, from, to),
+        Some((
+            datetime!(2024-10-26 20:00 UTC),
+            datetime!(2024-10-27 04:59 UTC)
+        ))
+    );
+
+    let repeated = self::schedule(vec![window(&ALL_DAYS, "02:00", "03:00", Action::Off)]);
+    assert_eq!(active_minutes(&repeated, from, to), 2 * 60);
+}
+
+#[test]
+fn brightness_of_the_actions() {
+    let schedule = schedule(vec![
+        window(&ALL_DAYS, "22:00", "23:00", Action::Night),
+        window(
+            &ALL_DAYS,
+            "23:00",
+            "01:00",
+            Action::Brightness { brightness: 10 },
+        ),
+        window(&ALL_DAYS, "01:00", "06:00", Action::Off),
+    ]);
+    let brightness = |datetime| {
+        schedule
+            .brightness(datetime, 85, 1)
+            .map(|scheduled| (scheduled.brightness, scheduled.night))
+    };
+    assert_eq!(brightness(datetime!(2024-06-11 12:00 UTC)), None);
+    assert_eq!(brightness(datetime!(2024-06-11 22:30 UTC)), Some((1, true)));
+    // only night and off windows switch to the night display mode
+    assert_eq!(
+        brightness(datetime!(2024-06-12 00:30 UTC)),
+        Some((10, false))
+    );
+    assert_eq!(brightness(datetime!(2024-06-12 03:00 UTC)), Some((0, true)));
+}
+
+#[test]
+fn schedule_json() {
+    let schedule: Schedule = serde_json::from_str(
+        r#"{
+            "windows": [{"days": ["sat", "sun"], "start": "23:30", "end": "08:00", "action": {"type": "off"}}],
+            "holidays": [{"date": "2024-12-24", "as_day": "sun"}]
+        }"#,
+    )
+    .unwrap();
+    schedule.validate().unwrap();
+    assert_eq!(schedule.windows[0].start, LocalTime::new(23, 30));
+    assert!(schedule.solar.is_none());
+    assert_eq!(
+        serde_json::to_value(&schedule.windows[0]).unwrap()["end"],
+        "08:00"
+    );
+    assert!(serde_json::from_str::<Schedule>(
+        r#"{"windows": [{"days": ["mon"], "start": "25:00", "end": "08:00", "action": {"type": "off"}}]}"#
+    )
+    .is_err());
+}
+
+#[test]
+fn invalid_holidays_are_rejected() {
+    for date in ["2024-02-30", "2024-2-03", "24-12-2024", "christmas"] {
+        let mut schedule = Schedule::default();
+        schedule.holidays.push(Holiday {
+            date: date.into(),
+            as_day: Day::Sun,
+        });
+        assert!(schedule.validate().is_err(), "{:?}", date);
+    }
+}
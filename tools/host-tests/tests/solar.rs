@@ -0,0 +1,215 @@
+use host_tests::schedule::{Schedule, Scheduled};
+use host_tests::solar::{sun_times, Crossing, SolarDimming};
+use time::macros::{date, datetime, offset};
+use time::{Date, Duration, OffsetDateTime, UtcOffset};
+
+const PRAGUE: (f64, f64) = (50.0755, 14.4378);
+const TROMSO: (f64, f64) = (69.6492, 18.9553);
+
+/// Sunrise and sunset, `HH:MM` local time as published by timeanddate.com
+fn assert_sun_times(
+    (latitude, longitude): (f64, f64),
+    date: Date,
+    offset: UtcOffset,
+    sunrise: &str,
+    sunset: &str,
+) {
+    let Crossing::Times { rise, set } = sun_times(date, offset, latitude, longitude).sun else {
+        panic!("No sunrise on {}", date);
+    };
+    for (actual, expected) in [(rise, sunrise), (set, sunset)] {
+        let (hour, minute) = expected.split_once(':').unwrap();
+        let expected = date
+            .with_hms(hour.parse().unwrap(), minute.parse().unwrap(), 0)
+            .unwrap()
+            .assume_offset(offset);
+        // the tables round to minutes, the algorithm is accurate to about a minute
+        assert!(
+            (actual - expected).abs() <= Duration::minutes(2),
+            "{} at {:?}: {} instead of {}",
+            date,
+            (latitude, longitude),
+            actual,
+            expected
+        );
+    }
+}
+
+fn solar((latitude, longitude): (f64, f64)) -> SolarDimming {
+    SolarDimming {
+        latitude,
+        longitude,
+        sunrise_offset_mins: 0,
+        sunset_offset_mins: 0,
+    }
+}
+
+#[test]
+fn prague() {
+    assert_sun_times(PRAGUE, date!(2024 - 06 - 21), offset!(+2), "04:52", "21:15");
+    assert_sun_times(PRAGUE, date!(2024 - 12 - 21), offset!(+1), "07:59", "16:02");
+    assert_sun_times(PRAGUE, date!(2024 - 03 - 20), offset!(+1), "06:04", "18:15");
+}
+
+#[test]
+fn other_latitudes() {
+    // Sydney, southern winter
+    assert_sun_times(
+        (-33.8688, 151.2093),
+        date!(2024 - 06 - 21),
+        offset!(+10),
+        "07:00",
+        "16:54",
+    );
+    // New York, west of Greenwich
+    assert_sun_times(
+        (40.7128, -74.0060),
+        date!(2024 - 12 - 21),
+        offset!(-5),
+        "07:17",
+        "16:32",
+    );
+    // Singapore, close to the equator
+    assert_sun_times(
+        (1.2897, 103.8501),
+        date!(2024 - 03 - 20),
+        offset!(+8),
+        "07:09",
+        "19:15",
+    );
+}
+
+#[test]
+fn solar_noon_is_on_the_local_date() {
+    let times = sun_times(date!(2024 - 06 - 21), offset!(+2), PRAGUE.0, PRAGUE.1);
+    assert_eq!(times.noon.date(), date!(2024 - 06 - 21));
+    assert_eq!((times.noon.hour(), times.noon.minute()), (13, 4));
+    // a time zone far from the longitude
+    let times = sun_times(date!(2024 - 06 - 21), offset!(+14), 0.0, -150.0);
+    assert_eq!(times.noon.date(), date!(2024 - 06 - 21));
+    assert_eq!(times.noon.offset(), offset!(+14));
+}
+
+#[test]
+fn polar_day() {
+    let times = sun_times(date!(2024 - 06 - 21), offset!(+2), TROMSO.0, TROMSO.1);
+    assert_eq!(times.sun, Crossing::AlwaysAbove);
+    assert_eq!(times.civil, Crossing::AlwaysAbove);
+    // McMurdo Station in the southern summer
+    let times = sun_times(date!(2024 - 12 - 21), offset!(+13), -77.846, 166.676);
+    assert_eq!(times.sun, Crossing::AlwaysAbove);
+
+    let solar = solar(TROMSO);
+    for hour in 0..24 {
+        let datetime = date!(2024 - 06 - 21)
+            .with_hms(hour, 0, 0)
+            .unwrap()
+            .assume_offset(offset!(+2));
+        assert_eq!(solar.daylight(datetime), 1.0);
+        assert_eq!(solar.brightness(datetime, 85, 1), 85);
+    }
+}
+
+#[test]
+fn polar_night() {
+    let times = sun_times(date!(2024 - 12 - 21), offset!(+1), TROMSO.0, TROMSO.1);
+    assert_eq!(times.sun, Crossing::AlwaysBelow);
+    // the sun is less than 6° below the horizon at noon
+    let Crossing::Times { rise, set } = times.civil else {
+        panic!("No civil twilight in Tromsø");
+    };
+    assert!(rise < times.noon && times.noon < set);
+    // Svalbard, dark all day
+    let times = sun_times(date!(2024 - 12 - 21), offset!(+1), 78.2232, 15.6267);
+    assert_eq!(times.sun, Crossing::AlwaysBelow);
+    assert_eq!(times.civil, Crossing::AlwaysBelow);
+
+    let solar = solar(TROMSO);
+    assert_eq!(solar.daylight(datetime!(2024-12-21 11:42 +1)), 0.0);
+    assert_eq!(solar.brightness(datetime!(2024-12-21 11:42 +1), 85, 1), 1);
+}
+
+#[test]
+fn daylight_ramps_during_the_twilight() {
+    let solar = solar(PRAGUE);
+    let times = sun_times(date!(2024 - 12 - 21), offset!(+1), PRAGUE.0, PRAGUE.1);
+    let (
+        Crossing::Times { rise, set },
+        Crossing::Times {
+            rise: dawn,
+            set: dusk,
+        },
+    ) = (times.sun, times.civil)
+    else {
+        panic!("No sunrise in Prague");
+    };
+
+    assert_eq!(solar.daylight(datetime!(2024-12-21 03:00 +1)), 0.0);
+    assert_eq!(solar.daylight(dawn), 0.0);
+    assert!((solar.daylight(dawn + (rise - dawn) / 2) - 0.5).abs() < 0.01);
+    assert_eq!(solar.daylight(rise), 1.0);
+    assert_eq!(solar.daylight(times.noon), 1.0);
+    assert_eq!(solar.daylight(set), 1.0);
+    assert!((solar.daylight(set + (dusk - set) / 2) - 0.5).abs() < 0.01);
+    assert_eq!(solar.daylight(dusk), 0.0);
+    assert_eq!(solar.daylight(datetime!(2024-12-21 23:00 +1)), 0.0);
+
+    let halfway = set + (dusk - set) / 2;
+    assert_eq!(solar.brightness(halfway, 85, 1), 43);
+}
+
+#[test]
+fn offsets_shift_the_ramps() {
+    let times = sun_times(date!(2024 - 12 - 21), offset!(+1), PRAGUE.0, PRAGUE.1);
+    let Crossing::Times { set, .. } = times.sun else {
+        panic!("No sunset in Prague");
+    };
+    let solar = SolarDimming {
+        sunset_offset_mins: 30,
+        sunrise_offset_mins: -15,
+        ..solar(PRAGUE)
+    };
+    assert_eq!(solar.daylight(set + Duration::minutes(29)), 1.0);
+    assert!(solar.daylight(set + Duration::minutes(45)) < 1.0);
+    assert_eq!(solar.daylight(datetime!(2024-12-21 07:50 +1)), 1.0);
+}
+
+#[test]
+fn schedule_dims_with_the_sun_outside_of_the_windows() {
+    let schedule = Schedule {
+        windows: Vec::new(),
+        holidays: Vec::new(),
+        solar: Some(solar(PRAGUE)),
+    };
+    assert_eq!(
+        schedule.brightness(datetime!(2024-12-21 12:00 +1), 85, 1),
+        None
+    );
+    assert_eq!(
+        schedule.brightness(datetime!(2024-12-21 22:00 +1), 85, 1),
+        Some(Scheduled {
+            brightness: 1,
+            night: true
+        })
+    );
+}
+
+#[test]
+fn stored_schedules_with_the_former_night_brightness_load() {
+    let schedule: Schedule = serde_json::from_str(
+        r#"{"windows": [], "solar": {"latitude": 50.08, "longitude": 14.42, "night_brightness": 5}}"#,
+    )
+    .unwrap();
+    schedule.validate().unwrap();
+    let solar = schedule.solar.unwrap();
+    assert_eq!((solar.latitude, solar.sunset_offset_mins), (50.08, 0));
+}
+
+#[test]
+fn solar_daylight_follows_the_offset_of_the_datetime() {
+    let solar = solar(PRAGUE);
+    let local: OffsetDateTime = datetime!(2024-06-21 21:40 +2);
+    let daylight = solar.daylight(local);
+    assert!(daylight > 0.0 && daylight < 1.0, "{}", daylight);
+    assert_eq!(daylight, solar.daylight(local.to_offset(offset!(UTC))));
+}
@@ -0,0 +1,153 @@
+use host_tests::stats::{BandMinutes, DailyStatsHistory, DAYS_KEPT};
+use time::macros::datetime;
+use time::{Duration, OffsetDateTime};
+
+fn assert_close(actual: f32, expected: f32) {
+    assert!(
+        (actual - expected).abs() < 1e-3,
+        "{} != {}",
+        actual,
+        expected
+    );
+}
+
+fn minutes_above(minutes: &[BandMinutes]) -> Vec<(u16, f32)> {
+    minutes
+        .iter()
+        .map(|band| (band.threshold, band.minutes))
+        .collect()
+}
+
+#[test]
+fn first_sample_only_starts_the_day() {
+    let mut history = DailyStatsHistory::default();
+    assert!(!history.record(datetime!(2024-06-15 10:00 +2), Some(800), Some(10)));
+    let today = history.today.as_ref().unwrap();
+    assert_eq!(today.date, "2024-06-15");
+    assert_eq!((today.co2.min, today.co2.max), (Some(800), Some(800)));
+    // no time covered yet, so no mean
+    assert_eq!(today.co2.mean, None);
+    assert_eq!(today.co2.hours, 0.0);
+}
+
+#[test]
+fn days_roll_over_at_local_midnight() {
+    let mut history = DailyStatsHistory::default();
+    // 23:58 local is already the next day in UTC, but still the same local day
+    assert!(!history.record(datetime!(2024-06-15 23:58 +2), Some(700), Some(5)));
+    assert!(!history.record(datetime!(2024-06-15 23:59 +2), Some(700), Some(5)));
+    assert!(history.record(datetime!(2024-06-16 00:01 +2), Some(900), Some(8)));
+
+    assert_eq!(history.today.as_ref().unwrap().date, "2024-06-16");
+    assert_eq!(history.days.len(), 1);
+    assert_eq!(history.days[0].date, "2024-06-15");
+    // the new day only covers the minute since midnight, not the two since the previous sample
+    let today = history.today.as_ref().unwrap();
+    assert_close(today.co2.hours, 1.0 / 60.0);
+    assert_close(history.days[0].co2.hours, 1.0 / 60.0);
+}
+
+#[test]
+fn only_the_last_days_are_kept() {
+    let mut history = DailyStatsHistory::default();
+    let start = datetime!(2024-06-01 12:00 UTC);
+    for day in 0..10 {
+        history.record(start + Duration::days(day), Some(600), None);
+    }
+    assert_eq!(history.days.len(), DAYS_KEPT);
+    let dates: Vec<&str> = history.days.iter().map(|day| day.date.as_str()).collect();
+    // the most recent first, today excluded
+    assert_eq!(
+        dates,
+        [
+            "2024-06-09",
+            "2024-06-08",
+            "2024-06-07",
+            "2024-06-06",
+            "2024-06-05",
+            "2024-06-04",
+            "2024-06-03"
+        ]
+    );
+    assert_eq!(history.today.as_ref().unwrap().date, "2024-06-10");
+}
+
+#[test]
+fn gaps_count_at_most_five_minutes() {
+    let mut history = DailyStatsHistory::default();
+    let start = datetime!(2024-06-15 08:00 UTC);
+    history.record(start, Some(500), None);
+    // e.g. after a reboot
+    history.record(start + Duration::hours(2), Some(500), None);
+    assert_close(history.today.as_ref().unwrap().co2.hours, 5.0 / 60.0);
+    // a clock going backwards doesn't count negative time
+    history.record(start + Duration::hours(1), Some(500), None);
+    assert_close(history.today.as_ref().unwrap().co2.hours, 5.0 / 60.0);
+}
+
+#[test]
+fn mean_and_exposure_are_time_weighted() {
+    let mut history = DailyStatsHistory::default();
+    let start = datetime!(2024-06-15 08:00 UTC);
+    let at = |minutes: i64| -> OffsetDateTime { start + Duration::minutes(minutes) };
+    history.record(at(0), Some(400), Some(10));
+    // 4 minutes of 1000 ppm, 1 minute of 2000 ppm
+    history.record(at(4), Some(1000), Some(10));
+    history.record(at(5), Some(2000), Some(40));
+
+    let co2 = &history.today.as_ref().unwrap().co2;
+    assert_eq!((co2.min, co2.max), (Some(400), Some(2000)));
+    assert_close(co2.hours, 5.0 / 60.0);
+    // ppm·h
+    assert_close(co2.exposure, (1000.0 * 4.0 + 2000.0 * 1.0) / 60.0);
+    assert_close(co2.mean.unwrap(), 1200.0);
+
+    let pm25 = &history.today.as_ref().unwrap().pm25;
+    assert_close(pm25.exposure, (10.0 * 4.0 + 40.0 * 1.0) / 60.0);
+    assert_close(pm25.mean.unwrap(), 16.0);
+}
+
+#[test]
+fn minutes_above_each_threshold() {
+    let mut history = DailyStatsHistory::default();
+    let start = datetime!(2024-06-15 08:00 UTC);
+    history.record(start, Some(400), Some(5));
+    history.record(start + Duration::minutes(3), Some(1600), Some(40));
+    history.record(start + Duration::minutes(5), Some(1000), Some(160));
+
+    let today = history.today.as_ref().unwrap();
+    // at the threshold isn't above it
+    assert_eq!(
+        minutes_above(&today.co2.above),
+        [(1000, 3.0), (1500, 3.0), (2000, 0.0)]
+    );
+    assert_eq!(
+        minutes_above(&today.pm25.above),
+        [(12, 5.0), (35, 5.0), (55, 2.0), (150, 2.0)]
+    );
+}
+
+#[test]
+fn missing_readings_leave_their_metric_alone() {
+    let mut history = DailyStatsHistory::default();
+    let start = datetime!(2024-06-15 08:00 UTC);
+    history.record(start, None, Some(10));
+    history.record(start + Duration::minutes(1), None, Some(10));
+    let today = history.today.as_ref().unwrap();
+    assert_eq!(today.co2.min, None);
+    assert_eq!(today.co2.hours, 0.0);
+    assert_close(today.pm25.hours, 1.0 / 60.0);
+}
+
+#[test]
+fn history_round_trips() {
+    let mut history = DailyStatsHistory::default();
+    history.record(datetime!(2024-06-15 23:59 UTC), Some(800), Some(10));
+    history.record(datetime!(2024-06-16 00:01 UTC), Some(800), Some(10));
+    let json = serde_json::to_string(&history).unwrap();
+    let mut loaded: DailyStatsHistory = serde_json::from_str(&json).unwrap();
+    assert_eq!(loaded.days.len(), 1);
+    // continues from the stored last sample
+    loaded.record(datetime!(2024-06-16 00:03 UTC), Some(800), Some(10));
+    assert_close(loaded.today.as_ref().unwrap().co2.hours, 3.0 / 60.0);
+}
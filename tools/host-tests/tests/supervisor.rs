@@ -0,0 +1,249 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use host_tests::supervisor::{Driver, Outcome, Policy, RestartReason, State, Supervised};
+
+/// Replays scripted results, succeeds once the script is exhausted
+#[derive(Default)]
+struct MockDriver {
+    init_results: VecDeque<Result<(), &'static str>>,
+    run_results: VecDeque<Result<u16, &'static str>>,
+    /// Shared with the test, the supervisor owns the driver
+    inits: Rc<Cell<u32>>,
+    runs: Rc<Cell<u32>>,
+}
+
+impl MockDriver {
+    fn failing_runs(count: usize) -> Self {
+        Self {
+            run_results: vec![Err("CRC error"); count].into(),
+            ..Default::default()
+        }
+    }
+
+    fn failing_inits(count: usize) -> Self {
+        Self {
+            init_results: vec![Err("NACK"); count].into(),
+            ..Default::default()
+        }
+    }
+}
+
+impl Driver for MockDriver {
+    type Output = u16;
+    type Error = &'static str;
+
+    fn init(&mut self) -> Result<(), &'static str> {
+        self.inits.set(self.inits.get() + 1);
+        self.init_results.pop_front().unwrap_or(Ok(()))
+    }
+
+    fn run(&mut self) -> Result<u16, &'static str> {
+        self.runs.set(self.runs.get() + 1);
+        self.run_results.pop_front().unwrap_or(Ok(420))
+    }
+}
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+const DEGRADE: Policy = Policy::Degrade {
+    degrade_after: 3,
+    initial_backoff: MINUTE,
+    max_backoff: Duration::from_secs(10 * 60),
+};
+
+const RESTART: Policy = Policy::Restart { restart_after: 2 };
+
+fn supervised(driver: MockDriver, policy: Policy) -> Supervised<MockDriver> {
+    Supervised::new("sensor", driver, policy)
+}
+
+#[test]
+fn healthy_driver_is_initialized_once() {
+    let now = Instant::now();
+    let driver = MockDriver::default();
+    let inits = driver.inits.clone();
+    let mut sensor = supervised(driver, DEGRADE);
+    assert_eq!(sensor.state(), State::Starting);
+    assert_eq!(sensor.start(now), Outcome::Ok(()));
+    assert_eq!(sensor.state(), State::Running);
+    for minute in 0..5 {
+        assert_eq!(sensor.run(now + MINUTE * minute), Outcome::Ok(420));
+    }
+    assert_eq!(sensor.state(), State::Running);
+    assert_eq!(inits.get(), 1);
+}
+
+#[test]
+fn run_initializes_and_waits_for_the_next_call() {
+    let now = Instant::now();
+    let driver = MockDriver::default();
+    let runs = driver.runs.clone();
+    let mut sensor = supervised(driver, DEGRADE);
+    // sensors need a measurement interval after the start
+    assert_eq!(sensor.run(now), Outcome::Unavailable);
+    assert_eq!(runs.get(), 0);
+    assert_eq!(sensor.state(), State::Running);
+    assert_eq!(sensor.run(now + MINUTE), Outcome::Ok(420));
+}
+
+#[test]
+fn failure_is_retried_after_the_backoff_and_reinitializes() {
+    let now = Instant::now();
+    let driver = MockDriver::failing_runs(1);
+    let (inits, runs) = (driver.inits.clone(), driver.runs.clone());
+    let mut sensor = supervised(driver, DEGRADE);
+    sensor.start(now);
+    assert_eq!(sensor.run(now), Outcome::Unavailable);
+    assert_eq!(sensor.state(), State::Retrying);
+    assert_eq!(sensor.last_error(), Some("\"CRC error\""));
+
+    // backing off, the driver isn't touched
+    assert_eq!(sensor.run(now + MINUTE / 2), Outcome::Unavailable);
+    assert_eq!(sensor.start(now + MINUTE / 2), Outcome::Unavailable);
+    assert_eq!((inits.get(), runs.get()), (1, 1));
+
+    // reinitialized, then used on the next call
+    assert_eq!(sensor.run(now + MINUTE), Outcome::Unavailable);
+    assert_eq!(inits.get(), 2);
+    assert_eq!(sensor.run(now + MINUTE * 2), Outcome::Ok(420));
+    assert_eq!(sensor.state(), State::Running);
+}
+
+#[test]
+fn backoff_doubles_up_to_the_maximum() {
+    let start = Instant::now();
+    let driver = MockDriver::failing_inits(100);
+    let inits = driver.inits.clone();
+    let mut sensor = supervised(driver, DEGRADE);
+    let mut attempts = Vec::new();
+    // probe every second for an hour
+    for second in 0..3600 {
+        let before = inits.get();
+        sensor.start(start + Duration::from_secs(second));
+        if inits.get() != before {
+            attempts.push(second);
+        }
+    }
+    let gaps: Vec<u64> = attempts.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    assert_eq!(&gaps[..5], &[60, 120, 240, 480, 600]);
+    assert!(gaps[5..].iter().all(|&gap| gap == 600), "{:?}", gaps);
+}
+
+#[test]
+fn degrades_and_keeps_retrying() {
+    let mut now = Instant::now();
+    let mut sensor = supervised(MockDriver::failing_inits(5), DEGRADE);
+    let mut states = Vec::new();
+    for _ in 0..5 {
+        assert_eq!(sensor.start(now), Outcome::Unavailable);
+        states.push(sensor.state());
+        now += Duration::from_secs(10 * 60);
+    }
+    assert_eq!(
+        states,
+        [
+            State::Retrying,
+            State::Retrying,
+            State::Degraded,
+            State::Degraded,
+            State::Degraded
+        ]
+    );
+
+    // the sensor came back, e.g. it was reconnected
+    assert_eq!(sensor.start(now), Outcome::Ok(()));
+    assert_eq!(sensor.state(), State::Degraded);
+    assert_eq!(sensor.run(now), Outcome::Ok(420));
+    assert_eq!(sensor.state(), State::Running);
+}
+
+#[test]
+fn degrade_policy_never_restarts() {
+    let start = Instant::now();
+    let mut sensor = supervised(MockDriver::failing_runs(1000), DEGRADE);
+    for minute in 0..1000 {
+        let outcome = sensor.run(start + MINUTE * minute);
+        assert!(!matches!(outcome, Outcome::Restart(_)));
+    }
+}
+
+#[test]
+fn restart_policy_restarts_after_repeated_failures() {
+    let now = Instant::now();
+    let mut sensor = supervised(MockDriver::failing_runs(2), RESTART);
+    sensor.start(now);
+    assert_eq!(sensor.run(now), Outcome::Unavailable);
+    // no backoff, reinitialized right away
+    assert_eq!(sensor.run(now), Outcome::Unavailable);
+    assert_eq!(
+        sensor.run(now),
+        Outcome::Restart(RestartReason::Unrecoverable {
+            subsystem: "sensor".into(),
+            error: "\"CRC error\"".into(),
+        })
+    );
+}
+
+#[test]
+fn restart_policy_counts_failures_in_a_row_only() {
+    let now = Instant::now();
+    let driver = MockDriver {
+        run_results: vec![Err("CRC error"), Ok(1), Err("CRC error"), Ok(2)].into(),
+        ..Default::default()
+    };
+    let mut sensor = supervised(driver, RESTART);
+    sensor.start(now);
+    let outcomes: Vec<_> = (0..6).map(|_| sensor.run(now)).collect();
+    assert_eq!(
+        outcomes,
+        [
+            Outcome::Unavailable,
+            Outcome::Unavailable,
+            Outcome::Ok(1),
+            Outcome::Unavailable,
+            Outcome::Unavailable,
+            Outcome::Ok(2),
+        ]
+    );
+}
+
+#[test]
+fn failing_start_restarts_with_the_restart_policy() {
+    let now = Instant::now();
+    let mut sensor = supervised(MockDriver::failing_inits(2), RESTART);
+    assert_eq!(sensor.start(now), Outcome::Unavailable);
+    assert!(matches!(sensor.start(now), Outcome::Restart(_)));
+}
+
+#[test]
+fn restart_reason_round_trips() {
+    let reasons = [
+        RestartReason::UserRequest,
+        RestartReason::Update {
+            version: "0.2.0".into(),
+        },
+        RestartReason::Unrecoverable {
+            subsystem: "wifi".into(),
+            error: "ESP_FAIL".into(),
+        },
+        RestartReason::Stalled {
+            task: "main".into(),
+            elapsed_secs: 185,
+            budget_secs: 180,
+        },
+    ];
+    for reason in reasons {
+        let json = serde_json::to_string(&reason).unwrap();
+        assert_eq!(
+            serde_json::from_str::<RestartReason>(&json).unwrap(),
+            reason
+        );
+    }
+    assert_eq!(
+        serde_json::to_value(RestartReason::UserRequest).unwrap(),
+        serde_json::json!({ "reason": "user_request" })
+    );
+}
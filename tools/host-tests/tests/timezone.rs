@@ -0,0 +1,92 @@
+use host_tests::timezone::{format_offset, Timezone};
+use time::macros::{datetime, offset};
+
+const WINTER: time::OffsetDateTime = datetime!(2024-01-15 12:00 UTC);
+const SUMMER: time::OffsetDateTime = datetime!(2024-07-15 12:00 UTC);
+
+#[test]
+fn iana_names_are_looked_up() {
+    let prague: Timezone = "Europe/Prague".parse().unwrap();
+    assert!(matches!(prague, Timezone::Iana(_)));
+    assert_eq!(prague.to_string(), "Europe/Prague");
+    assert_eq!(prague.offset(WINTER), offset!(+1));
+    assert_eq!(prague.offset(SUMMER), offset!(+2));
+    let local = prague.convert(SUMMER);
+    assert_eq!((local.hour(), local.offset()), (14, offset!(+2)));
+    assert_eq!(local, SUMMER);
+
+    let new_york: Timezone = " America/New_York ".parse().unwrap();
+    assert_eq!(new_york.to_string(), "America/New_York");
+    assert_eq!(new_york.offset(WINTER), offset!(-5));
+}
+
+#[test]
+fn posix_strings_follow_their_rules() {
+    let prague: Timezone = "CET-1CEST,M3.5.0,M10.5.0/3".parse().unwrap();
+    assert!(matches!(prague, Timezone::Posix(_)));
+    assert_eq!(prague.to_string(), "CET-1CEST,M3.5.0,M10.5.0/3");
+    assert_eq!(prague.offset(WINTER), offset!(+1));
+    assert_eq!(prague.offset(SUMMER), offset!(+2));
+    // the switch to summer time, 2:00 local on the last Sunday of March
+    assert_eq!(prague.offset(datetime!(2024-03-31 0:59 UTC)), offset!(+1));
+    assert_eq!(prague.offset(datetime!(2024-03-31 1:00 UTC)), offset!(+2));
+    assert_eq!(prague.convert(SUMMER).hour(), 14);
+
+    // without daylight saving time, west of Greenwich is positive
+    let india: Timezone = "IST-5:30".parse().unwrap();
+    assert_eq!(india.offset(SUMMER), offset!(+5:30));
+    let newfoundland: Timezone = "NST3:30".parse().unwrap();
+    assert_eq!(newfoundland.offset(WINTER), offset!(-3:30));
+}
+
+#[test]
+fn invalid_time_zones_are_rejected() {
+    assert!("Europe/Atlantis".parse::<Timezone>().is_err());
+    assert!("not a time zone".parse::<Timezone>().is_err());
+    assert!("".parse::<Timezone>().is_err());
+    assert!(serde_json::from_str::<Timezone>(r#""Mars/Olympus_Mons""#).is_err());
+}
+
+#[test]
+fn default_is_utc() {
+    let utc = Timezone::default();
+    assert_eq!(utc.to_string(), "UTC");
+    assert_eq!(utc.offset(SUMMER), offset!(UTC));
+    assert_eq!(utc.convert(SUMMER), SUMMER);
+}
+
+#[test]
+fn env_default_falls_back_to_utc() {
+    assert_eq!(
+        Timezone::from_config(Some("Europe/Prague")).to_string(),
+        "Europe/Prague"
+    );
+    assert_eq!(
+        Timezone::from_config(Some("EST5EDT,M3.2.0,M11.1.0")).to_string(),
+        "EST5EDT,M3.2.0,M11.1.0"
+    );
+    assert_eq!(Timezone::from_config(None).to_string(), "UTC");
+    assert_eq!(
+        Timezone::from_config(Some("Europe/Atlantis")).to_string(),
+        "UTC"
+    );
+}
+
+#[test]
+fn stored_time_zones_round_trip() {
+    for value in ["Europe/Prague", "CET-1CEST,M3.5.0,M10.5.0/3"] {
+        let timezone: Timezone = value.parse().unwrap();
+        let json = serde_json::to_string(&timezone).unwrap();
+        assert_eq!(json, format!("{:?}", value));
+        let loaded: Timezone = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.to_string(), value);
+    }
+}
+
+#[test]
+fn offsets_are_formatted_with_sign_and_minutes() {
+    assert_eq!(format_offset(offset!(+2)), "+02:00");
+    assert_eq!(format_offset(offset!(UTC)), "+00:00");
+    assert_eq!(format_offset(offset!(+5:30)), "+05:30");
+    assert_eq!(format_offset(offset!(-3:30)), "-03:30");
+}
@@ -0,0 +1,408 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Result};
+use host_tests::schedule::LocalTime;
+use host_tests::update::{
+    check, parse_manifest, rollout_bucket, CheckResult, Decision, MaintenanceWindow, Manifest,
+    UpdateChecker, UpdateConfig, MANIFEST_MAX_LEN,
+};
+use semver::Version;
+use sha2::{Digest, Sha256};
+use time::macros::datetime;
+
+const CURRENT: &str = "1.2.0";
+const DEVICE: &str = "a0b1c2d3e4f5";
+const IMAGE: &[u8] = b"\xe9 not really an ESP image, but the bytes the manifest hashes";
+
+fn version(version: &str) -> Version {
+    Version::parse(version).unwrap()
+}
+
+fn manifest(version: &str) -> Manifest {
+    Manifest {
+        version: self::version(version),
+        url: "http://192.168.1.10:8000/firmware.bin".into(),
+        sha256: hex(&Sha256::digest(IMAGE)),
+        min_version: None,
+        rollout_percent: 100,
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn config(window: Option<MaintenanceWindow>) -> UpdateConfig {
+    UpdateConfig {
+        manifest_url: Some("http://192.168.1.10:8000/manifest.json".into()),
+        check_interval_mins: 60,
+        window,
+    }
+}
+
+fn window(start: (u8, u8), end: (u8, u8)) -> MaintenanceWindow {
+    MaintenanceWindow {
+        start: LocalTime::new(start.0, start.1),
+        end: LocalTime::new(end.0, end.1),
+    }
+}
+
+#[test]
+fn newer_versions_are_installed() {
+    let current = version(CURRENT);
+    assert_eq!(
+        manifest("1.2.0").decide(&current, DEVICE),
+        Decision::UpToDate
+    );
+    assert_eq!(
+        manifest("1.1.9").decide(&current, DEVICE),
+        Decision::UpToDate
+    );
+    assert_eq!(
+        manifest("1.2.1").decide(&current, DEVICE),
+        Decision::Install
+    );
+    // pre-releases are older than the release
+    assert_eq!(
+        manifest("1.2.0-rc.1").decide(&current, DEVICE),
+        Decision::UpToDate
+    );
+}
+
+#[test]
+fn older_firmwares_need_the_min_version_first() {
+    let manifest = Manifest {
+        min_version: Some(version("1.3.0")),
+        ..manifest("2.0.0")
+    };
+    assert_eq!(
+        manifest.decide(&version(CURRENT), DEVICE),
+        Decision::Unsupported
+    );
+    assert_eq!(
+        manifest.decide(&version("1.3.0"), DEVICE),
+        Decision::Install
+    );
+}
+
+#[test]
+fn rollout_buckets_are_stable_and_spread() {
+    let next = version("1.3.0");
+    let devices: Vec<String> = (0..1000).map(|i| format!("a0b1c2{:06x}", i)).collect();
+    let buckets: Vec<u8> = devices
+        .iter()
+        .map(|device| rollout_bucket(device, &next))
+        .collect();
+    assert!(buckets.iter().all(|&bucket| bucket < 100));
+    assert_eq!(rollout_bucket(&devices[0], &next), buckets[0]);
+
+    // about the share of the devices, and raising it only adds devices
+    let rolled_out = |percent| {
+        let manifest = Manifest {
+            rollout_percent: percent,
+            ..manifest("1.3.0")
+        };
+        devices
+            .iter()
+            .filter(|device| manifest.decide(&version(CURRENT), device) == Decision::Install)
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    assert!(rolled_out(0).is_empty());
+    let (quarter, half) = (rolled_out(25), rolled_out(50));
+    assert!((200..300).contains(&quarter.len()), "{}", quarter.len());
+    assert!((450..550).contains(&half.len()), "{}", half.len());
+    assert!(quarter.iter().all(|device| half.contains(device)));
+    assert_eq!(rolled_out(100).len(), devices.len());
+
+    // each version picks different early adopters
+    let other: Vec<u8> = devices
+        .iter()
+        .map(|device| rollout_bucket(device, &version("1.4.0")))
+        .collect();
+    assert_ne!(buckets, other);
+}
+
+#[test]
+fn manifests_are_parsed() {
+    let json = format!(
+        r#"{{"version": "1.3.0", "url": "http://192.168.1.10:8000/firmware.bin", "sha256": "{}"}}"#,
+        hex(&Sha256::digest(IMAGE))
+    );
+    let manifest = parse_manifest(json.as_bytes()).unwrap();
+    assert_eq!(manifest.version, version("1.3.0"));
+    assert_eq!(manifest.min_version, None);
+    assert_eq!(manifest.rollout_percent, 100);
+    assert_eq!(
+        manifest.sha256().unwrap(),
+        <[u8; 32]>::from(Sha256::digest(IMAGE))
+    );
+
+    let invalid_sha256 = json.replace(&manifest.sha256, "c0ffee");
+    assert!(parse_manifest(invalid_sha256.as_bytes()).is_err());
+    assert!(parse_manifest(br#"{"version": "1.3"}"#).is_err());
+    let padded = format!("{}{}", json, " ".repeat(MANIFEST_MAX_LEN));
+    assert!(parse_manifest(padded.as_bytes()).is_err());
+}
+
+#[test]
+fn maintenance_windows() {
+    let night = window((2, 0), (5, 0));
+    assert!(!night.contains(datetime!(2024-06-15 01:59 UTC)));
+    assert!(night.contains(datetime!(2024-06-15 02:00 UTC)));
+    assert!(night.contains(datetime!(2024-06-15 04:59 UTC)));
+    assert!(!night.contains(datetime!(2024-06-15 05:00 UTC)));
+
+    let over_midnight = window((23, 0), (1, 0));
+    assert!(over_midnight.contains(datetime!(2024-06-15 23:30 UTC)));
+    assert!(over_midnight.contains(datetime!(2024-06-16 00:30 UTC)));
+    assert!(!over_midnight.contains(datetime!(2024-06-16 01:00 UTC)));
+    assert!(!over_midnight.contains(datetime!(2024-06-15 12:00 UTC)));
+}
+
+#[test]
+fn installs_wait_for_the_window() {
+    let checker = UpdateChecker::new(config(Some(window((2, 0), (5, 0)))));
+    assert!(checker.in_window(Some(datetime!(2024-06-15 03:00 UTC))));
+    assert!(!checker.in_window(Some(datetime!(2024-06-15 12:00 UTC))));
+    // not before the clock is synced
+    assert!(!checker.in_window(None));
+
+    let anytime = UpdateChecker::new(config(None));
+    assert!(anytime.in_window(None));
+}
+
+#[test]
+fn config_is_validated() {
+    assert!(config(None).validate().is_ok());
+    assert!(UpdateConfig {
+        check_interval_mins: 0,
+        ..config(None)
+    }
+    .validate()
+    .is_err());
+    assert!(UpdateConfig {
+        manifest_url: Some("ftp://192.168.1.10/manifest.json".into()),
+        ..config(None)
+    }
+    .validate()
+    .is_err());
+}
+
+#[test]
+fn checks_are_due_every_interval() {
+    let now = Instant::now();
+    let mut checker = UpdateChecker::new(config(None));
+    assert!(checker.due(false, now));
+    checker.set_result(CheckResult::UpToDate, now);
+    assert!(!checker.due(false, now + Duration::from_secs(59 * 60)));
+    assert!(checker.due(false, now + Duration::from_secs(60 * 60)));
+    assert_eq!(
+        checker
+            .status(now + Duration::from_secs(90))
+            .last_check_age_secs,
+        Some(90)
+    );
+
+    // a pending update is checked again as soon as the window opens
+    checker.set_result(
+        CheckResult::Pending {
+            version: version("1.3.0"),
+        },
+        now,
+    );
+    assert!(!checker.due(false, now));
+    assert!(checker.due(true, now));
+
+    // a new config is checked right away
+    checker.set_config(config(Some(window((2, 0), (5, 0)))));
+    assert!(checker.due(false, now));
+
+    let disabled = UpdateChecker::new(UpdateConfig {
+        manifest_url: None,
+        ..config(None)
+    });
+    assert!(!disabled.due(true, now));
+}
+
+#[test]
+fn requested_checks_ignore_the_interval_and_window() {
+    let now = Instant::now();
+    let mut checker = UpdateChecker::new(config(None));
+    checker.set_result(CheckResult::UpToDate, now);
+    checker.request_check();
+    assert!(checker.due(false, now));
+    assert!(checker.status(now).check_requested);
+    let (url, forced) = checker.start_check().unwrap();
+    assert_eq!(url, "http://192.168.1.10:8000/manifest.json");
+    assert!(forced);
+    // only once
+    assert!(!checker.due(false, now));
+    assert!(!checker.start_check().unwrap().1);
+}
+
+/// Local HTTP server standing in for the update server, answers `GET`s of the paths `files`
+/// returns for its base URL and 404 otherwise, the requested paths are recorded
+fn update_server(
+    files: impl FnOnce(&str) -> Vec<(&'static str, Vec<u8>)>,
+) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let files = files(&base_url);
+    let requested = Arc::new(Mutex::new(Vec::new()));
+    thread::spawn({
+        let requested = requested.clone();
+        move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                assert!(request_line.starts_with("GET "), "{}", request_line);
+                let path = request_line.split(' ').nth(1).unwrap().to_string();
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim_end().is_empty() {
+                        break;
+                    }
+                }
+                let (status, body) = match files.iter().find(|(file, _)| *file == path) {
+                    Some((_, body)) => (200, body.as_slice()),
+                    None => (404, &b""[..]),
+                };
+                let stream = reader.get_mut();
+                write!(
+                    stream,
+                    "HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(body).unwrap();
+                requested.lock().unwrap().push(path);
+            }
+        }
+    });
+    (base_url, requested)
+}
+
+/// Minimal HTTP client in place of the ESP-IDF one, fails on non-2xx responses like the device
+fn get(url: &str) -> Result<Vec<u8>> {
+    let address = url
+        .strip_prefix("http://")
+        .ok_or_else(|| anyhow!("Unsupported URL {}", url))?;
+    let (host, path) = address.split_at(address.find('/').unwrap_or(address.len()));
+    let mut stream = TcpStream::connect(host)?;
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, host
+    )?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| anyhow!("Response without a body"))?;
+    let head = String::from_utf8_lossy(&response[..end]);
+    let status: u16 = head
+        .split(' ')
+        .nth(1)
+        .ok_or_else(|| anyhow!("Invalid response {:?}", head))?
+        .parse()?;
+    if !(200..=299).contains(&status) {
+        bail!("Unexpected response code: {}", status);
+    }
+    Ok(response[end + 4..].to_vec())
+}
+
+/// Serves the manifest of `version` and the image, like `make serve`
+fn publish(version: &str, image: &[u8]) -> (String, Arc<Mutex<Vec<String>>>) {
+    update_server(|base_url| {
+        let manifest = Manifest {
+            url: format!("{}/firmware.bin", base_url),
+            ..manifest(version)
+        };
+        vec![
+            ("/manifest.json", serde_json::to_vec(&manifest).unwrap()),
+            ("/firmware.bin", image.to_vec()),
+        ]
+    })
+}
+
+/// `check()` against the local server, the image is written to `flash` when its SHA-256 matches
+fn check_server(base_url: &str, may_install: bool, flash: &mut Vec<u8>) -> CheckResult {
+    check(
+        CURRENT,
+        DEVICE,
+        may_install,
+        || parse_manifest(&get(&format!("{}/manifest.json", base_url))?),
+        |manifest| {
+            let image = get(&manifest.url)?;
+            if <[u8; 32]>::from(Sha256::digest(&image)) != manifest.sha256()? {
+                bail!("SHA-256 mismatch, the image is corrupted");
+            }
+            *flash = image;
+            Ok(())
+        },
+    )
+}
+
+#[test]
+fn newer_firmware_is_pulled_from_the_server() {
+    let (base_url, requested) = publish("1.3.0", IMAGE);
+    let mut flash = Vec::new();
+
+    // outside of the window only the manifest is fetched
+    let result = check_server(&base_url, false, &mut flash);
+    assert!(
+        matches!(result, CheckResult::Pending { ref version } if *version == self::version("1.3.0"))
+    );
+    assert!(flash.is_empty());
+
+    let result = check_server(&base_url, true, &mut flash);
+    assert!(
+        matches!(result, CheckResult::Installed { .. }),
+        "{:?}",
+        result
+    );
+    assert_eq!(flash, IMAGE);
+    assert_eq!(
+        *requested.lock().unwrap(),
+        ["/manifest.json", "/manifest.json", "/firmware.bin"]
+    );
+}
+
+#[test]
+fn current_firmware_isnt_downloaded() {
+    let (base_url, requested) = publish(CURRENT, IMAGE);
+    let mut flash = Vec::new();
+    let result = check_server(&base_url, true, &mut flash);
+    assert!(matches!(result, CheckResult::UpToDate));
+    assert_eq!(*requested.lock().unwrap(), ["/manifest.json"]);
+}
+
+#[test]
+fn corrupted_downloads_fail() {
+    let (base_url, _) = publish("1.3.0", b"\xe9 truncated");
+    let mut flash = Vec::new();
+    match check_server(&base_url, true, &mut flash) {
+        CheckResult::Failed { error } => assert!(error.contains("SHA-256"), "{}", error),
+        result => panic!("{:?}", result),
+    }
+    assert!(flash.is_empty());
+}
+
+#[test]
+fn missing_manifests_fail() {
+    let (base_url, _) = update_server(|_| Vec::new());
+    let mut flash = Vec::new();
+    match check_server(&base_url, true, &mut flash) {
+        CheckResult::Failed { error } => assert!(error.contains("404"), "{}", error),
+        result => panic!("{:?}", result),
+    }
+}
@@ -0,0 +1,206 @@
+//! Host side of the signed OTA images, see `src/signature.rs` of the firmware
+//!
+//! ```text
+//! sign-image keygen <signing key file>
+//! sign-image sign <signing key file> <image> <signed image>
+//! sign-image verify <public key hex> <signed image>
+//! ```
+
+use std::fs;
+use std::io::{Read, Write};
+use std::process::ExitCode;
+
+use anyhow::{bail, Context, Result};
+use ed25519_dalek::{Signer, SigningKey, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+#[path = "../../../src/signature.rs"]
+mod signature;
+
+use signature::{parse_hex, parse_public_key, signed_message, TRAILER_LEN, TRAILER_MAGIC};
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn read_signing_key(path: &str) -> Result<SigningKey> {
+    let hex = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+    Ok(SigningKey::from_bytes(&parse_hex(&hex)?))
+}
+
+fn generate_key() -> Result<SigningKey> {
+    let mut secret = [0; 32];
+    fs::File::open("/dev/urandom")?.read_exact(&mut secret)?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+/// Creates the file readable by the owner only, never overwrites a key
+fn write_secret(path: &str, contents: &str) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path))?
+        .write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+/// `image` with the signature trailer appended
+fn sign(key: &SigningKey, image: &[u8]) -> Vec<u8> {
+    let sha256: [u8; 32] = Sha256::digest(image).into();
+    let signature = key.sign(&signed_message(&sha256));
+    [image, &TRAILER_MAGIC, &signature.to_bytes()].concat()
+}
+
+/// Checks a signed image the way the firmware does
+fn verify(public_key: &VerifyingKey, signed: &[u8]) -> Result<()> {
+    if signed.len() < TRAILER_LEN {
+        bail!("Image is not signed");
+    }
+    let (image, trailer) = signed.split_at(signed.len() - TRAILER_LEN);
+    let sha256: [u8; 32] = Sha256::digest(image).into();
+    signature::verify(public_key, &sha256, trailer)
+}
+
+fn run(args: &[String]) -> Result<()> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args[..] {
+        ["keygen", key_path] => {
+            let key = generate_key()?;
+            write_secret(key_path, &(hex(&key.to_bytes()) + "\n"))?;
+            println!("Signing key written to {}, keep it secret", key_path);
+            println!("Public key, set OTA_PUBLIC_KEY in .env:");
+            println!("{}", hex(key.verifying_key().as_bytes()));
+        }
+        ["sign", key_path, image_path, output_path] => {
+            let key = read_signing_key(key_path)?;
+            let image =
+                fs::read(image_path).with_context(|| format!("Failed to read {}", image_path))?;
+            if verify(&key.verifying_key(), &image).is_ok() {
+                bail!("{} is already signed", image_path);
+            }
+            fs::write(output_path, sign(&key, &image))?;
+            println!(
+                "Signed {} with {}",
+                output_path,
+                hex(key.verifying_key().as_bytes())
+            );
+        }
+        ["verify", public_key, signed_path] => {
+            let signed =
+                fs::read(signed_path).with_context(|| format!("Failed to read {}", signed_path))?;
+            verify(&parse_public_key(public_key)?, &signed)?;
+            println!("{}: valid signature", signed_path);
+        }
+        _ => bail!(
+            "Usage:\n  sign-image keygen <signing key file>\n  sign-image sign <signing key file> \
+             <image> <signed image>\n  sign-image verify <public key hex> <signed image>"
+        ),
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn image() -> Vec<u8> {
+        (0..10_000).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn signed_image_verifies() {
+        let key = key(1);
+        let signed = sign(&key, &image());
+        assert_eq!(signed.len(), image().len() + TRAILER_LEN);
+        assert!(signed.starts_with(&image()));
+        verify(&key.verifying_key(), &signed).unwrap();
+    }
+
+    #[test]
+    fn trailer_format() {
+        let signed = sign(&key(1), &image());
+        let trailer = &signed[image().len()..];
+        assert_eq!(&trailer[..8], b"VNDSIG\x00\x01");
+        assert_eq!(trailer.len(), 8 + 64);
+    }
+
+    #[test]
+    fn signature_is_deterministic() {
+        assert_eq!(sign(&key(1), &image()), sign(&key(1), &image()));
+    }
+
+    #[test]
+    fn unsigned_image_is_rejected() {
+        let error = verify(&key(1).verifying_key(), &image()).unwrap_err();
+        assert_eq!(error.to_string(), "Image is not signed");
+        let error = verify(&key(1).verifying_key(), b"short").unwrap_err();
+        assert_eq!(error.to_string(), "Image is not signed");
+    }
+
+    #[test]
+    fn tampered_image_is_rejected() {
+        let mut signed = sign(&key(1), &image());
+        signed[100] ^= 1;
+        let error = verify(&key(1).verifying_key(), &signed).unwrap_err();
+        assert!(error.to_string().contains("tampered"), "{}", error);
+    }
+
+    #[test]
+    fn tampered_signature_is_rejected() {
+        let mut signed = sign(&key(1), &image());
+        let last = signed.len() - 1;
+        signed[last] ^= 1;
+        assert!(verify(&key(1).verifying_key(), &signed).is_err());
+    }
+
+    #[test]
+    fn other_key_is_rejected() {
+        let signed = sign(&key(1), &image());
+        let error = verify(&key(2).verifying_key(), &signed).unwrap_err();
+        assert!(error.to_string().contains("another key"), "{}", error);
+    }
+
+    #[test]
+    fn signature_is_bound_to_the_prefix() {
+        // a plain signature of the hash, e.g. made for another purpose, is not accepted
+        let key = key(1);
+        let sha256: [u8; 32] = Sha256::digest(image()).into();
+        let signature = key.sign(&sha256);
+        let signed = [&image()[..], &TRAILER_MAGIC, &signature.to_bytes()].concat();
+        assert!(verify(&key.verifying_key(), &signed).is_err());
+    }
+
+    #[test]
+    fn public_key_hex() {
+        let key = key(1);
+        let public_key = parse_public_key(&hex(key.verifying_key().as_bytes())).unwrap();
+        assert_eq!(public_key, key.verifying_key());
+        assert!(parse_public_key("abcd").is_err());
+        assert!(parse_public_key(&"zz".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn parse_hex_accepts_both_cases_and_whitespace() {
+        assert_eq!(parse_hex::<2>(" aBcD\n").unwrap(), [0xab, 0xcd]);
+        assert!(parse_hex::<2>("abc").is_err());
+        assert!(parse_hex::<2>("ééé").is_err());
+    }
+}